@@ -0,0 +1,566 @@
+//! Load generation against a node or a whole network.
+//!
+//! `bench` issues a weighted mix of puts, gets and FIND_NODE lookups over a fixed
+//! set of keys, either keeping a number of operations in flight or starting them
+//! at a target rate. Keys are picked uniformly or from a Zipf distribution, so
+//! a few hot keys take most of the load. Operations go through iterative lookups
+//! like any client, or with `--direct` straight to the node, which measures the
+//! node alone. The report has the throughput, latency percentiles and errors of
+//! each operation, and its JSON form is stable for tracking regressions.
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use futures::stream::{FuturesUnordered, StreamExt};
+use protocol::{ClientSession, Key, NodeId, RpcClient, StoreStatus, ValueMode};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::keys::generate_key_from_string;
+use crate::output::{with_timeout, Failure, Report};
+
+/// Time between progress lines
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest time between two operations started at a fixed rate
+const MIN_TICK_PERIOD: Duration = Duration::from_micros(1);
+
+/// An operation the benchmark issues
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    Put,
+    Get,
+    FindNode,
+}
+
+impl Operation {
+    const ALL: [Operation; 3] = [Operation::Put, Operation::Get, Operation::FindNode];
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Put => "put",
+            Operation::Get => "get",
+            Operation::FindNode => "find_node",
+        }
+    }
+}
+
+/// Relative weights of the operations, as in `put=1,get=8,find_node=1`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mix {
+    weights: Vec<(Operation, u32)>,
+    total: u32,
+}
+
+impl Mix {
+    /// Pick an operation with the probability of its weight
+    fn choose(&self, rng: &mut impl Rng) -> Operation {
+        let mut point = rng.random_range(0..self.total);
+        for (operation, weight) in &self.weights {
+            if point < *weight {
+                return *operation;
+            }
+            point -= weight;
+        }
+        unreachable!("the weights add up to the total")
+    }
+}
+
+/// Parse an operation mix of `name=weight` pairs
+pub fn parse_mix(arg: &str) -> Result<Mix> {
+    let mut weights = Vec::new();
+    for part in arg.split(',') {
+        let Some((name, weight)) = part.split_once('=') else {
+            bail!("Expected name=weight, got {}", part);
+        };
+        let Some(operation) = Operation::ALL.into_iter().find(|operation| operation.name() == name.trim()) else {
+            bail!("Unknown operation {}; expected put, get or find_node", name);
+        };
+        let weight: u32 = weight.trim().parse()?;
+        if weight > 0 {
+            weights.push((operation, weight));
+        }
+    }
+    let Some(total) = weights.iter().try_fold(0u32, |total, (_, weight)| total.checked_add(*weight)) else {
+        bail!("The weights of the operation mix add up to more than {}", u32::MAX);
+    };
+    if total == 0 {
+        bail!("The operation mix needs a positive weight");
+    }
+    Ok(Mix { weights, total })
+}
+
+/// How keys are picked for each operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum KeyDistribution {
+    /// Every key equally often
+    Uniform,
+    /// The n-th most popular key with weight 1/n^s
+    Zipf,
+}
+
+/// Picks keys from a fixed key space
+struct KeyChooser {
+    keys: Vec<Key>,
+    /// Cumulative probabilities of the keys, for Zipf distributions
+    cdf: Option<Vec<f64>>,
+}
+
+impl KeyChooser {
+    fn new(count: usize, distribution: KeyDistribution, exponent: f64) -> Self {
+        let keys = (0..count).map(|i| generate_key_from_string(&format!("bench/{}", i))).collect();
+        let cdf = match distribution {
+            KeyDistribution::Uniform => None,
+            KeyDistribution::Zipf => {
+                let mut cdf: Vec<f64> = (1..=count)
+                    .scan(0.0, |sum, rank| {
+                        *sum += 1.0 / (rank as f64).powf(exponent);
+                        Some(*sum)
+                    })
+                    .collect();
+                let total = cdf.last().copied().unwrap_or(1.0);
+                cdf.iter_mut().for_each(|p| *p /= total);
+                Some(cdf)
+            }
+        };
+        KeyChooser { keys, cdf }
+    }
+
+    fn index(&self, rng: &mut impl Rng) -> usize {
+        match &self.cdf {
+            None => rng.random_range(0..self.keys.len()),
+            Some(cdf) => {
+                let point: f64 = rng.random();
+                cdf.partition_point(|p| *p < point).min(self.keys.len() - 1)
+            }
+        }
+    }
+
+    fn choose(&self, rng: &mut impl Rng) -> Key {
+        self.keys[self.index(rng)]
+    }
+}
+
+/// Where operations are sent
+pub enum BenchTarget<'a> {
+    /// Through iterative lookups, as clients use the network
+    Network(&'a ClientSession),
+    /// Straight to one node
+    Direct {
+        client: &'a RpcClient,
+        own_id: NodeId,
+        addr: SocketAddr,
+    },
+}
+
+/// How a benchmark runs
+pub struct BenchOptions {
+    pub mix: Mix,
+    /// How long operations are started for
+    pub duration: Duration,
+    /// Operations started per second; as fast as `concurrency` allows if not given
+    pub rate: Option<f64>,
+    /// Maximum number of operations in flight
+    pub concurrency: usize,
+    /// Number of distinct keys
+    pub keys: usize,
+    pub distribution: KeyDistribution,
+    /// Exponent of the Zipf distribution
+    pub zipf_exponent: f64,
+    /// Size of the values put, in bytes
+    pub value_size: usize,
+    /// Timeout of every operation
+    pub timeout: Duration,
+    /// Seed for picking operations, keys and values
+    pub seed: Option<u64>,
+    /// Put every key once before measuring
+    pub prefill: bool,
+    /// Print progress lines to stderr
+    pub progress: bool,
+}
+
+/// How one operation ended
+enum Outcome {
+    Ok,
+    /// A get found no value
+    NotFound,
+    /// The operation failed, with the error it is counted under
+    Failed(String),
+}
+
+/// Latency distribution of an operation, in milliseconds
+#[derive(Serialize)]
+pub struct LatencyReport {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl LatencyReport {
+    fn of(latencies: &mut [Duration]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        let percentile = |p: f64| ms(latencies[((latencies.len() - 1) as f64 * p).round() as usize]);
+        let total: Duration = latencies.iter().sum();
+        Some(LatencyReport {
+            mean: ms(total) / latencies.len() as f64,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: ms(latencies[latencies.len() - 1]),
+        })
+    }
+}
+
+/// Results of one kind of operation
+#[derive(Serialize)]
+pub struct OperationReport {
+    pub operation: &'static str,
+    pub count: usize,
+    pub ok: usize,
+    /// Gets that found no value; not counted as errors
+    pub not_found: usize,
+    pub errors: usize,
+    pub ops_per_sec: f64,
+    /// Latency of operations that didn't fail
+    pub latency_ms: Option<LatencyReport>,
+    /// Number of failures by error
+    pub error_breakdown: BTreeMap<String, usize>,
+}
+
+/// Result of `bench`
+#[derive(Serialize)]
+pub struct BenchReport {
+    /// "network", or "node" for direct operations
+    pub target: &'static str,
+    pub elapsed_ms: u64,
+    pub concurrency: usize,
+    pub target_rate: Option<f64>,
+    pub keys: usize,
+    pub distribution: &'static str,
+    pub total: usize,
+    pub errors: usize,
+    pub ops_per_sec: f64,
+    pub operations: Vec<OperationReport>,
+}
+
+impl Report for BenchReport {
+    fn print_text(&self) -> Result<()> {
+        let rate = match self.target_rate {
+            Some(rate) => format!("{} ops/s", rate),
+            None => "unlimited".to_string(),
+        };
+        println!(
+            "{} operations in {:.1}s against the {}, {} errors",
+            self.total,
+            self.elapsed_ms as f64 / 1000.0,
+            self.target,
+            self.errors
+        );
+        println!(
+            "Concurrency {}, rate {}, {} {} keys",
+            self.concurrency, rate, self.keys, self.distribution
+        );
+        println!("Throughput: {:.1} ops/s", self.ops_per_sec);
+        println!();
+        println!(
+            "{:<10} {:>8} {:>8} {:>9} {:>7} {:>9} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "operation", "count", "ok", "not found", "errors", "ops/s", "mean", "p50", "p90", "p99", "max"
+        );
+        for operation in &self.operations {
+            let latency = match &operation.latency_ms {
+                Some(l) => format!("{:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2}", l.mean, l.p50, l.p90, l.p99, l.max),
+                None => format!("{:>8} {:>8} {:>8} {:>8} {:>8}", "-", "-", "-", "-", "-"),
+            };
+            println!(
+                "{:<10} {:>8} {:>8} {:>9} {:>7} {:>9.1} {}",
+                operation.operation,
+                operation.count,
+                operation.ok,
+                operation.not_found,
+                operation.errors,
+                operation.ops_per_sec,
+                latency
+            );
+        }
+        println!("Latencies in milliseconds");
+
+        for operation in self.operations.iter().filter(|operation| operation.errors > 0) {
+            println!();
+            println!("{} errors:", operation.operation);
+            for (error, count) in &operation.error_breakdown {
+                println!("  {:>8}  {}", count, error);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcomes collected for one kind of operation
+#[derive(Default)]
+struct Tally {
+    count: usize,
+    ok: usize,
+    not_found: usize,
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, usize>,
+}
+
+/// Run the benchmark and report the results
+pub async fn run(target: &BenchTarget<'_>, options: &BenchOptions) -> Result<BenchReport> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let chooser = KeyChooser::new(options.keys.max(1), options.distribution, options.zipf_exponent);
+    let concurrency = options.concurrency.max(1);
+
+    if options.prefill {
+        let mut stores = futures::stream::iter(chooser.keys.iter().map(|key| {
+            let value = random_value(&mut rng, options.value_size);
+            execute(target, Operation::Put, *key, value, options.timeout)
+        }))
+        .buffer_unordered(concurrency);
+        while let Some((_, outcome, _)) = stores.next().await {
+            if let (Outcome::Failed(e), true) = (outcome, options.progress) {
+                eprintln!("Prefill store failed: {}", e);
+            }
+        }
+    }
+
+    let mut ticker = options.rate.map(|rate| {
+        let mut ticker = tokio::time::interval(tick_period(rate, options.duration));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        ticker
+    });
+    let started = Instant::now();
+    let deadline = tokio::time::Instant::now() + options.duration;
+    let mut next_progress = started + PROGRESS_INTERVAL;
+    let mut tallies: BTreeMap<Operation, Tally> = BTreeMap::new();
+    let mut finished = 0;
+    let mut failed = 0;
+    let mut in_flight = FuturesUnordered::new();
+
+    loop {
+        let issuing = tokio::time::Instant::now() < deadline;
+        if !issuing && in_flight.is_empty() {
+            break;
+        }
+        tokio::select! {
+            Some((operation, outcome, latency)) = in_flight.next(), if !in_flight.is_empty() => {
+                let tally = tallies.entry(operation).or_default();
+                tally.count += 1;
+                finished += 1;
+                match outcome {
+                    Outcome::Ok => {
+                        tally.ok += 1;
+                        tally.latencies.push(latency);
+                    }
+                    Outcome::NotFound => {
+                        tally.not_found += 1;
+                        tally.latencies.push(latency);
+                    }
+                    Outcome::Failed(e) => {
+                        *tally.errors.entry(e).or_default() += 1;
+                        failed += 1;
+                    }
+                }
+            }
+            _ = tick(&mut ticker), if issuing && in_flight.len() < concurrency => {
+                let operation = options.mix.choose(&mut rng);
+                let key = chooser.choose(&mut rng);
+                let value = match operation {
+                    Operation::Put => random_value(&mut rng, options.value_size),
+                    _ => Vec::new(),
+                };
+                in_flight.push(execute(target, operation, key, value, options.timeout));
+            }
+            _ = tokio::time::sleep_until(deadline), if issuing => {}
+        }
+
+        if options.progress && Instant::now() >= next_progress {
+            eprintln!(
+                "{:>4}s  {} operations, {} errors, {} in flight",
+                started.elapsed().as_secs(),
+                finished,
+                failed,
+                in_flight.len()
+            );
+            next_progress += PROGRESS_INTERVAL;
+        }
+    }
+
+    let elapsed = started.elapsed();
+    let per_sec = |count: usize| count as f64 / elapsed.as_secs_f64();
+    let operations = tallies
+        .into_iter()
+        .map(|(operation, mut tally)| OperationReport {
+            operation: operation.name(),
+            count: tally.count,
+            ok: tally.ok,
+            not_found: tally.not_found,
+            errors: tally.errors.values().sum(),
+            ops_per_sec: per_sec(tally.count),
+            latency_ms: LatencyReport::of(&mut tally.latencies),
+            error_breakdown: tally.errors,
+        })
+        .collect();
+
+    Ok(BenchReport {
+        target: match target {
+            BenchTarget::Network(_) => "network",
+            BenchTarget::Direct { .. } => "node",
+        },
+        elapsed_ms: elapsed.as_millis() as u64,
+        concurrency,
+        target_rate: options.rate,
+        keys: chooser.keys.len(),
+        distribution: match options.distribution {
+            KeyDistribution::Uniform => "uniform",
+            KeyDistribution::Zipf => "zipf",
+        },
+        total: finished,
+        errors: failed,
+        ops_per_sec: per_sec(finished),
+        operations,
+    })
+}
+
+/// Wait for the next operation to be due; always due without a target rate
+async fn tick(ticker: &mut Option<Interval>) {
+    if let Some(ticker) = ticker {
+        ticker.tick().await;
+    }
+}
+
+/// Time between two operations at `rate` operations per second
+///
+/// Rates too low to express as a period start only one operation per run.
+fn tick_period(rate: f64, duration: Duration) -> Duration {
+    Duration::try_from_secs_f64(1.0 / rate).unwrap_or(duration).max(MIN_TICK_PERIOD)
+}
+
+fn random_value(rng: &mut impl Rng, size: usize) -> Vec<u8> {
+    let mut value = vec![0u8; size];
+    rng.fill(&mut value[..]);
+    value
+}
+
+/// Run one operation and time it
+async fn execute(
+    target: &BenchTarget<'_>,
+    operation: Operation,
+    key: Key,
+    value: Vec<u8>,
+    timeout: Duration,
+) -> (Operation, Outcome, Duration) {
+    let started = Instant::now();
+    let outcome = match with_timeout(timeout, attempt(target, operation, key, value)).await {
+        Ok(outcome) => outcome,
+        // Only the timeout is a Failure; counted under one name whatever its duration
+        Err(e) if e.is::<Failure>() => Outcome::Failed("timed out".to_string()),
+        Err(e) => Outcome::Failed(format!("{:#}", e)),
+    };
+    (operation, outcome, started.elapsed())
+}
+
+async fn attempt(target: &BenchTarget<'_>, operation: Operation, key: Key, value: Vec<u8>) -> Result<Outcome> {
+    let outcome = match (target, operation) {
+        (BenchTarget::Network(session), Operation::Put) => {
+            let outcomes = session.put(key, value, ValueMode::Plain).await?;
+            if outcomes.iter().any(|outcome| outcome.status == StoreStatus::Accepted) {
+                Outcome::Ok
+            } else if outcomes.iter().any(|outcome| outcome.status == StoreStatus::Refused) {
+                Outcome::Failed("refused by every node".to_string())
+            } else {
+                Outcome::Failed("no node reachable".to_string())
+            }
+        }
+        (BenchTarget::Network(session), Operation::Get) => match session.get(key, ValueMode::Plain).await? {
+            Some(_) => Outcome::Ok,
+            None => Outcome::NotFound,
+        },
+        (BenchTarget::Network(session), Operation::FindNode) => {
+            session.lookup_nodes(key).await?;
+            Outcome::Ok
+        }
+        (BenchTarget::Direct { client, own_id, addr }, Operation::Put) => {
+            if client.store(*own_id, *addr, key, value, ValueMode::Plain).await? {
+                Outcome::Ok
+            } else {
+                Outcome::Failed("refused".to_string())
+            }
+        }
+        (BenchTarget::Direct { client, own_id, addr }, Operation::Get) => {
+            match client.find_value(*own_id, key, *addr).await? {
+                Ok(_) => Outcome::Ok,
+                Err(_) => Outcome::NotFound,
+            }
+        }
+        (BenchTarget::Direct { client, own_id, addr }, Operation::FindNode) => {
+            client.find_node(*own_id, key, *addr).await?;
+            Outcome::Ok
+        }
+    };
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() {
+        let mix = parse_mix("put=1, get=8,find_node=0").unwrap();
+        assert_eq!(mix.weights, vec![(Operation::Put, 1), (Operation::Get, 8)]);
+        assert_eq!(mix.total, 9);
+
+        assert!(parse_mix("put=0").is_err());
+        assert!(parse_mix("scan=1").is_err());
+        assert!(parse_mix("get").is_err());
+        assert!(parse_mix(&format!("put={},get=1", u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_tick_period() {
+        let duration = Duration::from_secs(10);
+        assert_eq!(tick_period(4.0, duration), Duration::from_millis(250));
+        assert_eq!(tick_period(1e12, duration), MIN_TICK_PERIOD);
+        assert_eq!(tick_period(1e-300, duration), duration);
+    }
+
+    #[test]
+    fn test_zipf_favours_first_keys() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let uniform = KeyChooser::new(100, KeyDistribution::Uniform, 1.0);
+        let zipf = KeyChooser::new(100, KeyDistribution::Zipf, 1.0);
+
+        let hot = |chooser: &KeyChooser, rng: &mut StdRng| {
+            (0..10_000).filter(|_| chooser.index(rng) < 10).count()
+        };
+        // The first 10% of keys get about 10% of uniform picks and ~56% of Zipf picks
+        assert!(hot(&uniform, &mut rng) < 1_500);
+        assert!(hot(&zipf, &mut rng) > 5_000);
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut latencies: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let report = LatencyReport::of(&mut latencies).unwrap();
+        assert_eq!(report.p50, 51.0);
+        assert_eq!(report.p99, 99.0);
+        assert_eq!(report.max, 100.0);
+        assert!(LatencyReport::of(&mut []).is_none());
+    }
+}
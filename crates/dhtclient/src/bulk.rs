@@ -0,0 +1,375 @@
+//! Bulk import and export of key/value records.
+//!
+//! Records are newline-delimited JSON objects or CSV rows with a header:
+//!
+//! ```text
+//! {"key": "name:greeting", "value": "hello", "ttl": 3600}
+//! {"key": "0011...", "value": "3q2+7w==", "encoding": "base64"}
+//!
+//! key,value,encoding,ttl
+//! name:greeting,hello,,3600
+//! ```
+//!
+//! Keys take any form a key argument takes. Values are text unless `encoding`
+//! says `hex` or `base64`, and records without a TTL get the nodes' default TTL.
+//! Exports write hex keys, text values where they are valid UTF-8 and base64
+//! otherwise, and the TTL each value has left.
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt};
+use protocol::control::ExportedRecord;
+use protocol::{ClientSession, StoreStatus, ValueMode};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::keys::{parse_key_arg, KeyArg};
+use crate::output::{network_error, with_timeout, Report};
+
+/// Number of finished records between progress lines
+const PROGRESS_INTERVAL: usize = 100;
+
+/// Delay before the first retry of a failed store, doubled for every further retry
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Longest delay between two attempts to store a record
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// How records are written in a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// One JSON object per line
+    Ndjson,
+    /// CSV with a key,value,encoding,ttl header
+    Csv,
+}
+
+impl RecordFormat {
+    /// Pick the format from the file extension: CSV for `.csv`, NDJSON otherwise
+    pub fn for_path(path: Option<&Path>) -> Self {
+        match path.and_then(Path::extension) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => RecordFormat::Csv,
+            _ => RecordFormat::Ndjson,
+        }
+    }
+}
+
+/// How the value of a record is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueEncoding {
+    Text,
+    Hex,
+    Base64,
+}
+
+/// A record as it appears in a file
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordLine {
+    key: String,
+    value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<ValueEncoding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u64>,
+}
+
+impl RecordLine {
+    fn exported(record: &ExportedRecord) -> Self {
+        let (encoding, value) = match std::str::from_utf8(&record.value) {
+            Ok(text) => (None, text.to_string()),
+            Err(_) => (Some(ValueEncoding::Base64), base64::engine::general_purpose::STANDARD.encode(&record.value)),
+        };
+        RecordLine { key: hex::encode(record.key.as_bytes()), value, encoding, ttl: Some(record.ttl_secs) }
+    }
+
+    fn into_record(self) -> Result<ImportRecord> {
+        let key = parse_key_arg(&self.key)?;
+        let value = match self.encoding.unwrap_or(ValueEncoding::Text) {
+            ValueEncoding::Text => self.value.into_bytes(),
+            ValueEncoding::Hex => hex::decode(&self.value)?,
+            ValueEncoding::Base64 => base64::engine::general_purpose::STANDARD.decode(&self.value)?,
+        };
+        Ok(ImportRecord { key, value, ttl: self.ttl.map(Duration::from_secs) })
+    }
+}
+
+/// A record to store
+#[derive(Debug)]
+pub struct ImportRecord {
+    pub key: KeyArg,
+    pub value: Vec<u8>,
+    /// How long the nodes should keep the value, if not their default
+    pub ttl: Option<Duration>,
+}
+
+/// Read records, numbered from 1, reporting bad records without stopping
+pub fn read_records(reader: impl Read + 'static, format: RecordFormat) -> Box<dyn Iterator<Item = (usize, Result<ImportRecord>)>> {
+    match format {
+        RecordFormat::Ndjson => {
+            let lines = BufReader::new(reader)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| {
+                    let line: RecordLine = serde_json::from_str(&line?)?;
+                    line.into_record()
+                });
+            Box::new((1..).zip(lines))
+        }
+        RecordFormat::Csv => {
+            let rows = csv::Reader::from_reader(reader)
+                .into_deserialize()
+                .map(|row: csv::Result<RecordLine>| row?.into_record());
+            Box::new((1..).zip(rows))
+        }
+    }
+}
+
+/// Writes exported records in a record format
+pub enum RecordWriter<W: Write> {
+    Ndjson(W),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Start writing records, with the CSV header if needed
+    pub fn new(writer: W, format: RecordFormat) -> Result<Self> {
+        match format {
+            RecordFormat::Ndjson => Ok(RecordWriter::Ndjson(writer)),
+            RecordFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(["key", "value", "encoding", "ttl"])?;
+                Ok(RecordWriter::Csv(Box::new(writer)))
+            }
+        }
+    }
+
+    /// Write one record
+    pub fn write(&mut self, record: &ExportedRecord) -> Result<()> {
+        let line = RecordLine::exported(record);
+        match self {
+            RecordWriter::Ndjson(writer) => writeln!(writer, "{}", serde_json::to_string(&line)?)?,
+            RecordWriter::Csv(writer) => {
+                // Written field by field, as empty optional fields have to keep their column
+                let encoding = match line.encoding {
+                    Some(ValueEncoding::Text) | None => "",
+                    Some(ValueEncoding::Hex) => "hex",
+                    Some(ValueEncoding::Base64) => "base64",
+                };
+                let ttl = line.ttl.map(|ttl| ttl.to_string()).unwrap_or_default();
+                writer.write_record([line.key.as_str(), line.value.as_str(), encoding, ttl.as_str()])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush everything written
+    pub fn finish(self) -> Result<()> {
+        match self {
+            RecordWriter::Ndjson(mut writer) => writer.flush()?,
+            RecordWriter::Csv(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// How an import runs
+pub struct ImportOptions {
+    /// Maximum number of records stored at the same time
+    pub concurrency: usize,
+    /// Number of times a failed store is retried
+    pub retries: u32,
+    /// Timeout of every store attempt
+    pub timeout: Duration,
+    /// Print progress lines to stderr
+    pub progress: bool,
+}
+
+/// A record that couldn't be stored
+#[derive(Serialize)]
+pub struct ImportFailure {
+    /// Number of the record in the input, from 1
+    pub record: usize,
+    pub key: Option<String>,
+    pub attempts: u32,
+    pub error: String,
+}
+
+/// Result of `import`
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub records: usize,
+    pub stored: usize,
+    pub failed: usize,
+    pub elapsed_ms: u64,
+    pub failures: Vec<ImportFailure>,
+}
+
+impl Report for ImportReport {
+    fn print_text(&self) -> Result<()> {
+        for failure in &self.failures {
+            let key = failure.key.as_deref().unwrap_or("-");
+            println!("  record {}  {}  after {} attempts: {}", failure.record, key, failure.attempts, failure.error);
+        }
+        println!(
+            "Imported {} of {} records in {:.1}s, {} failed",
+            self.stored,
+            self.records,
+            self.elapsed_ms as f64 / 1000.0,
+            self.failed
+        );
+        Ok(())
+    }
+}
+
+/// Store every record through the session, a bounded number at a time
+pub async fn import(
+    session: &ClientSession,
+    records: impl Iterator<Item = (usize, Result<ImportRecord>)>,
+    options: &ImportOptions,
+) -> ImportReport {
+    let started = Instant::now();
+    let mut report = ImportReport { records: 0, stored: 0, failed: 0, elapsed_ms: 0, failures: Vec::new() };
+
+    let mut results = stream::iter(records)
+        .map(|(number, record)| async move {
+            match record {
+                Ok(record) => store_record(session, number, record, options).await,
+                Err(e) => Err(ImportFailure { record: number, key: None, attempts: 0, error: format!("{:#}", e) }),
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1));
+
+    while let Some(result) = results.next().await {
+        report.records += 1;
+        match result {
+            Ok(()) => report.stored += 1,
+            Err(failure) => {
+                report.failed += 1;
+                report.failures.push(failure);
+            }
+        }
+        if options.progress && report.records.is_multiple_of(PROGRESS_INTERVAL) {
+            let rate = report.records as f64 / started.elapsed().as_secs_f64();
+            eprintln!("{} records, {} stored, {} failed, {:.0} records/s", report.records, report.stored, report.failed, rate);
+        }
+    }
+
+    report.failures.sort_by_key(|failure| failure.record);
+    report.elapsed_ms = started.elapsed().as_millis() as u64;
+    report
+}
+
+/// Store one record, retrying stores that failed for reasons other than a refusal
+async fn store_record(session: &ClientSession, number: usize, record: ImportRecord, options: &ImportOptions) -> Result<(), ImportFailure> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = with_timeout(options.timeout, async {
+            session
+                .put_with_ttl(record.key.key, record.value.clone(), ValueMode::Plain, record.ttl)
+                .await
+                .map_err(network_error)
+        })
+        .await;
+
+        let (error, retry) = match result {
+            Ok(outcomes) if outcomes.iter().any(|outcome| outcome.status == StoreStatus::Accepted) => return Ok(()),
+            // Nodes refusing the value would refuse it again
+            Ok(outcomes) if outcomes.iter().any(|outcome| outcome.status == StoreStatus::Refused) => {
+                (anyhow!("Every node refused the value"), false)
+            }
+            Ok(_) => (anyhow!("No node could be reached to store the value"), true),
+            Err(e) => (e, true),
+        };
+        if !retry || attempts > options.retries {
+            return Err(ImportFailure { record: number, key: Some(record.key.hex()), attempts, error: format!("{:#}", error) });
+        }
+        tokio::time::sleep(retry_delay(attempts)).await;
+    }
+}
+
+/// Delay after the given number of failed attempts
+fn retry_delay(attempts: u32) -> Duration {
+    let factor = 2u32.checked_pow(attempts.saturating_sub(1)).unwrap_or(u32::MAX);
+    RETRY_DELAY.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+/// Result of `export`
+#[derive(Serialize)]
+pub struct ExportReport {
+    pub records: usize,
+    /// File the records were written to
+    pub out: String,
+}
+
+impl Report for ExportReport {
+    fn print_text(&self) -> Result<()> {
+        println!("Exported {} records to {}", self.records, self.out);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Key;
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        assert_eq!(retry_delay(1), RETRY_DELAY);
+        assert_eq!(retry_delay(2), RETRY_DELAY * 2);
+        assert_eq!(retry_delay(20), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_read_ndjson() {
+        let input = concat!(
+            "{\"key\": \"name:a\", \"value\": \"hello\", \"ttl\": 60}\n",
+            "\n",
+            "{\"key\": \"name:b\", \"value\": \"00ff\", \"encoding\": \"hex\"}\n",
+            "{\"key\": \"nothex\", \"value\": \"x\"}\n",
+        );
+        let records: Vec<_> = read_records(input.as_bytes(), RecordFormat::Ndjson).collect();
+        assert_eq!(records.len(), 3);
+
+        let (number, first) = &records[0];
+        let first = first.as_ref().unwrap();
+        assert_eq!(*number, 1);
+        assert_eq!(first.value, b"hello");
+        assert_eq!(first.ttl, Some(Duration::from_secs(60)));
+
+        let second = records[1].1.as_ref().unwrap();
+        assert_eq!(second.value, vec![0x00, 0xff]);
+        assert_eq!(second.ttl, None);
+        assert!(records[2].1.is_err());
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let exported = vec![
+            ExportedRecord { key: Key::random(), value: b"hello, world\n".to_vec(), ttl_secs: 60 },
+            ExportedRecord { key: Key::random(), value: vec![0xff, 0x00], ttl_secs: 0 },
+        ];
+        let mut writer = RecordWriter::new(Vec::new(), RecordFormat::Csv).unwrap();
+        for record in &exported {
+            writer.write(record).unwrap();
+        }
+        let RecordWriter::Csv(writer) = writer else { unreachable!() };
+        let bytes = writer.into_inner().unwrap();
+
+        let records: Vec<_> = read_records(std::io::Cursor::new(bytes), RecordFormat::Csv)
+            .map(|(_, record)| record.unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        for (record, exported) in records.iter().zip(&exported) {
+            assert_eq!(record.key.key, exported.key);
+            assert_eq!(record.value, exported.value);
+            assert_eq!(record.ttl, Some(Duration::from_secs(exported.ttl_secs)));
+        }
+    }
+}
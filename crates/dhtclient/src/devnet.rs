@@ -0,0 +1,381 @@
+//! A local cluster of nodes for testing on one machine.
+//!
+//! `devnet` starts nodes on sequential ports, node `i` serving RPCs on
+//! `base_port + i` and its control interface on `control_base_port + i`, with
+//! its storage and control token in `<dir>/node-<i>`. Every node bootstraps from
+//! the ones started before it. The nodes run as tasks of this process, or with
+//! `--processes` as children running the hidden `devnet-node` command, which
+//! exit when their stdin closes so they don't outlive the devnet. On Ctrl+C,
+//! SIGTERM or after `--lifetime`, the nodes are stopped and their storage
+//! removed unless `--keep` is given.
+
+use anyhow::{anyhow, bail, Result};
+use protocol::control::{self, ControlServer};
+use protocol::storage::Storage;
+use protocol::{NetworkId, Node, NodeId};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+use crate::keys::parse_key;
+use crate::output::Report;
+
+/// Time a child node gets to exit after its stdin closed before it is killed
+const CHILD_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Line a child node prints once it has started, followed by its ID
+const READY_PREFIX: &str = "ready ";
+
+/// Where one node of the devnet runs
+#[derive(Clone, Debug)]
+pub struct NodeSpec {
+    pub addr: SocketAddr,
+    pub control_addr: SocketAddr,
+    /// Storage directory, which also holds the control token
+    pub dir: PathBuf,
+    /// Nodes to bootstrap from
+    pub seeds: Vec<SocketAddr>,
+}
+
+/// How the devnet is laid out
+pub struct DevnetOptions {
+    pub nodes: usize,
+    pub base_port: u16,
+    pub control_base_port: u16,
+    /// Directory holding the storage directories of the nodes
+    pub dir: PathBuf,
+    pub network: NetworkId,
+    /// Run every node in a child process instead of in this process
+    pub processes: bool,
+    /// Keep the storage directories after stopping
+    pub keep: bool,
+    /// Stop after this long instead of waiting for a signal
+    pub lifetime: Option<Duration>,
+    /// Time a child node gets to start
+    pub startup_timeout: Duration,
+}
+
+impl DevnetOptions {
+    /// Lay out the nodes; each bootstraps from all nodes before it
+    fn specs(&self) -> Result<Vec<NodeSpec>> {
+        if self.nodes == 0 {
+            bail!("A devnet needs at least one node");
+        }
+        let port = |base: u16, index: usize| {
+            u16::try_from(usize::from(base) + index).map_err(|_| anyhow!("Port {} + {} is out of range", base, index))
+        };
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut specs: Vec<NodeSpec> = Vec::with_capacity(self.nodes);
+        for index in 0..self.nodes {
+            specs.push(NodeSpec {
+                addr: SocketAddr::new(localhost, port(self.base_port, index)?),
+                control_addr: SocketAddr::new(localhost, port(self.control_base_port, index)?),
+                dir: self.dir.join(format!("node-{}", index)),
+                seeds: specs.iter().map(|spec| spec.addr).collect(),
+            });
+        }
+        Ok(specs)
+    }
+}
+
+/// A node started in this process
+struct LocalNode {
+    node: Arc<Node>,
+    task: JoinHandle<Result<()>>,
+}
+
+/// Start a node and its control interface, then bootstrap it from its seeds
+async fn start_node(spec: &NodeSpec, network: &NetworkId) -> Result<LocalNode> {
+    std::fs::create_dir_all(&spec.dir)?;
+    let storage = Storage::new(&spec.dir)?;
+    let node = Arc::new(Node::with_network(spec.addr, storage, network.clone()).await?);
+
+    let token = control::generate_token();
+    control::write_token_file(spec.dir.join(control::TOKEN_FILE), &token)?;
+    let control_server = ControlServer::bind(spec.control_addr, token).await?;
+
+    let task = {
+        let node = Arc::clone(&node);
+        tokio::spawn(async move {
+            tokio::try_join!(node.run(), control_server.start(&node))?;
+            Ok(())
+        })
+    };
+    let local = LocalNode { node, task };
+    if !spec.seeds.is_empty() {
+        if let Err(e) = local.node.bootstrap(&spec.seeds).await {
+            local.stop();
+            return Err(e);
+        }
+    }
+    Ok(local)
+}
+
+impl LocalNode {
+    fn stop(self) {
+        self.task.abort();
+        if let Err(e) = self.node.storage().flush() {
+            eprintln!("Failed to flush the storage of {}: {}", self.node.addr(), e);
+        }
+    }
+}
+
+/// A node of the devnet, in this process or a child
+enum Member {
+    Local(LocalNode),
+    Child(Child),
+}
+
+/// Start one node in a child process running `devnet-node`
+async fn spawn_child(spec: &NodeSpec, network: &NetworkId, timeout: Duration) -> Result<(Child, NodeId)> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg("--network")
+        .arg(network.to_string())
+        .arg("devnet-node")
+        .arg("--addr")
+        .arg(spec.addr.to_string())
+        .arg("--control-addr")
+        .arg(spec.control_addr.to_string())
+        .arg("--dir")
+        .arg(&spec.dir);
+    for seed in &spec.seeds {
+        command.arg("--seed").arg(seed.to_string());
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("Child node has no stdout"))?;
+    let mut lines = BufReader::new(stdout).lines();
+    let ready = tokio::time::timeout(timeout, lines.next_line()).await;
+    match ready {
+        Ok(Ok(Some(line))) if line.starts_with(READY_PREFIX) => {
+            let id = parse_key(line[READY_PREFIX.len()..].trim())?;
+            Ok((child, id))
+        }
+        Ok(Ok(_)) => bail!("Node on {} exited before it was ready", spec.addr),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => bail!("Node on {} didn't start within {}s", spec.addr, timeout.as_secs()),
+    }
+}
+
+/// One node in the cluster table
+#[derive(Serialize)]
+pub struct DevnetNodeReport {
+    pub index: usize,
+    pub id: String,
+    pub addr: SocketAddr,
+    pub control: SocketAddr,
+    pub dir: String,
+    pub token_file: String,
+    /// Process ID of the child running the node, if any
+    pub pid: Option<u32>,
+}
+
+/// The started devnet
+#[derive(Serialize)]
+pub struct DevnetReport {
+    pub network: String,
+    pub nodes: Vec<DevnetNodeReport>,
+}
+
+impl Report for DevnetReport {
+    fn print_text(&self) -> Result<()> {
+        println!("Devnet of {} nodes on network {}", self.nodes.len(), self.network);
+        println!(
+            "{:>3}  {:<40}  {:<21}  {:<21}  {:>7}  dir",
+            "#", "id", "rpc", "control", "pid"
+        );
+        for node in &self.nodes {
+            let pid = node.pid.map(|pid| pid.to_string()).unwrap_or_else(|| "-".to_string());
+            println!(
+                "{:>3}  {:<40}  {:<21}  {:<21}  {:>7}  {}",
+                node.index, node.id, node.addr, node.control, pid, node.dir
+            );
+        }
+        if let Some(first) = self.nodes.first() {
+            println!();
+            println!(
+                "Try: dhtclient -n {} --control {} --token-file {} info",
+                first.addr, first.control, first.token_file
+            );
+        }
+        println!("Press Ctrl+C to stop the devnet");
+        Ok(())
+    }
+}
+
+/// A running devnet
+pub struct Devnet {
+    members: Vec<Member>,
+    specs: Vec<NodeSpec>,
+    dir: PathBuf,
+}
+
+impl Devnet {
+    /// Start every node, stopping the ones already started if one fails
+    pub async fn start(options: &DevnetOptions) -> Result<(Devnet, DevnetReport)> {
+        let specs = options.specs()?;
+        // Storage left from an earlier devnet would bring back stale records
+        for spec in &specs {
+            remove_dir(&spec.dir)?;
+        }
+
+        let mut devnet = Devnet {
+            members: Vec::with_capacity(specs.len()),
+            specs: specs.clone(),
+            dir: options.dir.clone(),
+        };
+        let mut nodes = Vec::with_capacity(specs.len());
+        for (index, spec) in specs.iter().enumerate() {
+            let started = if options.processes {
+                spawn_child(spec, &options.network, options.startup_timeout)
+                    .await
+                    .map(|(child, id)| (child.id(), id, Member::Child(child)))
+            } else {
+                start_node(spec, &options.network)
+                    .await
+                    .map(|local| (None, local.node.id(), Member::Local(local)))
+            };
+            let (pid, id, member) = match started {
+                Ok(started) => started,
+                Err(e) => {
+                    devnet.stop(options.keep).await;
+                    return Err(e.context(format!("Failed to start node {} on {}", index, spec.addr)));
+                }
+            };
+            devnet.members.push(member);
+            nodes.push(DevnetNodeReport {
+                index,
+                id: id.to_string(),
+                addr: spec.addr,
+                control: spec.control_addr,
+                dir: spec.dir.display().to_string(),
+                token_file: spec.dir.join(control::TOKEN_FILE).display().to_string(),
+                pid,
+            });
+        }
+
+        let report = DevnetReport { network: options.network.to_string(), nodes };
+        Ok((devnet, report))
+    }
+
+    /// Stop every node, newest first, and remove their storage unless kept
+    pub async fn stop(self, keep: bool) {
+        for member in self.members.into_iter().rev() {
+            match member {
+                Member::Local(local) => local.stop(),
+                Member::Child(mut child) => {
+                    // Closing stdin asks the child to exit on its own
+                    drop(child.stdin.take());
+                    if tokio::time::timeout(CHILD_EXIT_TIMEOUT, child.wait()).await.is_err() {
+                        let _ = child.kill().await;
+                    }
+                }
+            }
+        }
+        if !keep {
+            for spec in &self.specs {
+                if let Err(e) = remove_dir(&spec.dir) {
+                    eprintln!("Failed to remove {}: {}", spec.dir.display(), e);
+                }
+            }
+            // Only removed if nothing else was kept in it
+            let _ = std::fs::remove_dir(&self.dir);
+        }
+    }
+}
+
+fn remove_dir(dir: &Path) -> Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Wait for Ctrl+C, SIGTERM or the end of the lifetime
+pub async fn wait_for_shutdown(lifetime: Option<Duration>) -> Result<()> {
+    let lifetime = async {
+        match lifetime {
+            Some(lifetime) => tokio::time::sleep(lifetime).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        result = terminate() => result?,
+        _ = lifetime => {}
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn terminate() -> Result<()> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?.recv().await;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn terminate() -> Result<()> {
+    std::future::pending().await
+}
+
+/// Run one node for a devnet in this process until stdin closes or a signal arrives
+pub async fn run_child_node(spec: &NodeSpec, network: &NetworkId) -> Result<()> {
+    let local = start_node(spec, network).await?;
+    println!("{}{}", READY_PREFIX, local.node.id());
+
+    let stdin_closed = async {
+        let mut stdin = tokio::io::stdin();
+        let mut buffer = [0u8; 64];
+        while stdin.read(&mut buffer).await.is_ok_and(|read| read > 0) {}
+    };
+    tokio::select! {
+        _ = stdin_closed => {}
+        result = wait_for_shutdown(None) => result?,
+    }
+    local.stop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(nodes: usize, base_port: u16) -> DevnetOptions {
+        DevnetOptions {
+            nodes,
+            base_port,
+            control_base_port: 9100,
+            dir: PathBuf::from("devnet"),
+            network: NetworkId::default(),
+            processes: false,
+            keep: false,
+            lifetime: None,
+            startup_timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_specs() {
+        let specs = options(3, 9000).specs().unwrap();
+        assert_eq!(specs.len(), 3);
+        assert_eq!(specs[2].addr, "127.0.0.1:9002".parse().unwrap());
+        assert_eq!(specs[2].control_addr, "127.0.0.1:9102".parse().unwrap());
+        assert_eq!(specs[2].dir, Path::new("devnet").join("node-2"));
+        assert!(specs[0].seeds.is_empty());
+        assert_eq!(specs[2].seeds, vec![specs[0].addr, specs[1].addr]);
+
+        assert!(options(0, 9000).specs().is_err());
+        assert!(options(3, 65534).specs().is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Key arguments and the schemes keys are derived with.
+//!
+//! Every key argument accepts one of these forms:
+//! - `<40 hex digits>` or `hex:<40 hex digits>`: the key itself
+//! - `name:<string>`: the SHA-1 of the string, as `generate-key --from` makes
+//! - `hash-of-file:<path>`: the SHA-1 of the file's content, the key `put-file` stores it under
+//! - `app/<namespace>/<name>`: the SHA-1 of the whole argument, so apps can derive
+//!   the same key from their namespace and record name
+
+use anyhow::{bail, Result};
+use protocol::{integrity, Key};
+use sha1::{Digest, Sha1};
+use std::fmt;
+
+/// A key given on the command line, with how it was derived.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyArg {
+    /// The key
+    pub key: Key,
+    /// The argument the key was derived from, unless it was given as hex
+    pub derived_from: Option<String>,
+}
+
+impl KeyArg {
+    /// Return the key as hex
+    pub fn hex(&self) -> String {
+        hex::encode(self.key.as_bytes())
+    }
+}
+
+impl fmt::Display for KeyArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.derived_from {
+            Some(source) => write!(f, "{} (from {})", self.hex(), source),
+            None => write!(f, "{}", self.hex()),
+        }
+    }
+}
+
+/// Parse a key argument in any of the supported forms
+pub fn parse_key_arg(arg: &str) -> Result<KeyArg> {
+    if let Some(name) = arg.strip_prefix("name:") {
+        return Ok(derived(generate_key_from_string(name), arg));
+    }
+    if let Some(hex_key) = arg.strip_prefix("hex:") {
+        return Ok(derived(parse_key(hex_key)?, arg));
+    }
+    if let Some(path) = arg.strip_prefix("hash-of-file:") {
+        let content = std::fs::read(path)?;
+        return Ok(derived(integrity::content_key(&content), arg));
+    }
+    if let Some(rest) = arg.strip_prefix("app/") {
+        match rest.split_once('/') {
+            Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+                return Ok(derived(generate_key_from_string(arg), arg));
+            }
+            _ => bail!("Namespaced keys have the form app/<namespace>/<name>"),
+        }
+    }
+    Ok(KeyArg {
+        key: parse_key(arg)?,
+        derived_from: None,
+    })
+}
+
+fn derived(key: Key, arg: &str) -> KeyArg {
+    KeyArg {
+        key,
+        derived_from: Some(arg.to_string()),
+    }
+}
+
+/// Create a Key from a hex string
+pub fn parse_key(hex_key: &str) -> Result<Key> {
+    let key_bytes = hex::decode(hex_key)?;
+    if key_bytes.len() != 20 {
+        bail!("Invalid key length. Expected 20 bytes (40 hex characters)");
+    }
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(&key_bytes);
+    Ok(Key::new(bytes))
+}
+
+/// Generate a key from input string using SHA-1
+pub fn generate_key_from_string(input: &str) -> Key {
+    let mut hasher = Sha1::new();
+    hasher.update(input.as_bytes());
+    let result = hasher.finalize();
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(&result);
+    Key::new(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX_KEY: &str = "00112233445566778899aabbccddeeff00112233";
+
+    #[test]
+    fn test_hex_forms() {
+        let plain = parse_key_arg(HEX_KEY).unwrap();
+        assert_eq!(plain.hex(), HEX_KEY);
+        assert_eq!(plain.derived_from, None);
+
+        let prefixed = parse_key_arg(&format!("hex:{}", HEX_KEY)).unwrap();
+        assert_eq!(prefixed.key, plain.key);
+        assert!(parse_key_arg("hex:0011").is_err());
+    }
+
+    #[test]
+    fn test_derived_forms() {
+        let named = parse_key_arg("name:config").unwrap();
+        assert_eq!(named.key, generate_key_from_string("config"));
+        assert_eq!(named.derived_from.as_deref(), Some("name:config"));
+
+        let app = parse_key_arg("app/billing/invoices").unwrap();
+        assert_eq!(app.key, generate_key_from_string("app/billing/invoices"));
+        assert!(parse_key_arg("app/billing").is_err());
+        assert!(parse_key_arg("app//invoices").is_err());
+    }
+}
@@ -1,16 +1,42 @@
-use anyhow::Result;
-use protocol::{Key, NodeId};
+mod bench;
+mod bulk;
+mod devnet;
+mod keys;
+mod output;
+mod shell;
+mod trace;
+mod watch;
+
+use anyhow::{anyhow, Result};
+use bench::{parse_mix, BenchOptions, BenchTarget, KeyDistribution, Mix};
+use devnet::{run_child_node, wait_for_shutdown, Devnet, DevnetOptions, NodeSpec};
+use bulk::{import, read_records, ExportReport, ImportOptions, RecordFormat, RecordWriter};
+use keys::{generate_key_from_string, parse_key_arg, KeyArg};
+use shell::Shell;
+use trace::{ping_sweep, TraceReport, TraceRender};
+use watch::WatchOptions;
+use output::{
+    emit, network_error, report_error, with_timeout, DeleteReport, ErrorKind, Failure,
+    GeneratedKeysReport, GetReport, InfoReport, ListReport, ListedKey, OutputFormat,
+    RefreshReport, RepublishReport, StoreReport, ValueFormat,
+};
+use protocol::{ClientSession, ControlClient, Key, NodeId, ValueMode};
+use protocol::integrity;
+use protocol::control::{self, ControlRequest, ControlResponse};
 use protocol::rpc::RpcClient;
+use std::io::{BufWriter, Read, Write};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::time::Duration;
-use tokio::time::timeout;
 use clap::{Parser, Subcommand};
-use sha1::{Sha1, Digest};
 
 #[derive(Parser)]
 #[command(name = "dhtclient")]
 #[command(about = "A client tool for Kademlia DHT")]
 #[command(version)]
+#[command(after_help = "Exit codes: 0 success, 1 error, 2 invalid arguments, 3 key not found, \
+4 timeout, 5 store refused, 6 network error")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -19,42 +45,285 @@ struct Cli {
     #[arg(short, long, default_value = "127.0.0.1:8000")]
     node: String,
 
+    /// Overlay network the node belongs to
+    #[arg(long, default_value = protocol::NetworkId::DEFAULT)]
+    network: String,
+
     /// Timeout in seconds for operations
     #[arg(short, long, default_value = "5")]
     timeout: u64,
+
+    /// Address of the node's local control interface
+    #[arg(long, default_value = protocol::DEFAULT_CONTROL_ADDR)]
+    control: String,
+
+    /// Control token (read from --token-file if not given)
+    #[arg(long)]
+    token: Option<String>,
+
+    /// File containing the node's control token
+    #[arg(long, default_value = "./.compute-dht/control.token")]
+    token_file: String,
+
+    /// How to print results
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// List all key-value pairs
+    /// List the keys stored on the node
     List {
-        /// Optional filter pattern
-        #[arg(short, long)]
-        pattern: Option<String>,
+        /// Only list keys starting with this hex prefix
+        #[arg(short, long, default_value = "")]
+        prefix: String,
+
+        /// Maximum number of keys to list
+        #[arg(short, long, default_value = "100")]
+        limit: usize,
+
+        /// Continue listing after this key
+        #[arg(short, long, value_parser = parse_key_arg)]
+        after: Option<KeyArg>,
     },
 
-    /// Get a value by key
+    /// Get a value by key from the nodes closest to it
     Get {
-        /// Key to lookup (in hex format)
-        key: String,
+        /// Key to lookup: hex, hex:, name:, hash-of-file: or app/<namespace>/<name>
+        #[arg(value_parser = parse_key_arg)]
+        key: KeyArg,
+
+        /// How to print the value
+        #[arg(short, long, value_enum, default_value_t = ValueFormat::Text)]
+        format: ValueFormat,
+
+        /// Write the value to this file instead of printing it
+        #[arg(short, long)]
+        out: Option<PathBuf>,
     },
 
-    /// Store a key-value pair
+    /// Store a key-value pair on the nodes closest to the key
     Put {
-        /// Key to store (in hex format)
-        key: String,
-        /// Value to store
-        value: String,
+        /// Key to store: hex, hex:, name:, hash-of-file: or app/<namespace>/<name>
+        #[arg(value_parser = parse_key_arg)]
+        key: KeyArg,
+
+        /// Value to store; read from --file or stdin if not given
+        value: Option<String>,
+
+        /// Read the value from this file
+        #[arg(short, long, conflicts_with = "value")]
+        file: Option<PathBuf>,
+    },
+
+    /// Store the contents of a file under the hash of its content
+    PutFile {
+        /// File to store
+        path: PathBuf,
     },
 
     /// Delete a key-value pair
     Delete {
-        /// Key to delete (in hex format)
-        key: String,
+        /// Key to delete: hex, hex:, name:, hash-of-file: or app/<namespace>/<name>
+        #[arg(value_parser = parse_key_arg)]
+        key: KeyArg,
+    },
+
+    /// Store records from newline-delimited JSON or CSV
+    Import {
+        /// File to read the records from; stdin if not given
+        path: Option<PathBuf>,
+
+        /// Record format; by default CSV for .csv files and NDJSON otherwise
+        #[arg(short, long, value_enum)]
+        format: Option<RecordFormat>,
+
+        /// Maximum number of records stored at the same time
+        #[arg(short, long, default_value = "16")]
+        concurrency: usize,
+
+        /// Number of times a failed store is retried
+        #[arg(short, long, default_value = "3")]
+        retries: u32,
+
+        /// Don't print progress to stderr
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Write the values stored on the node as newline-delimited JSON or CSV
+    Export {
+        /// File to write the records to; stdout if not given
+        #[arg(long)]
+        out: Option<PathBuf>,
+
+        /// Record format; by default CSV for .csv files and NDJSON otherwise
+        #[arg(short, long, value_enum)]
+        format: Option<RecordFormat>,
+
+        /// Only export keys starting with this hex prefix
+        #[arg(short, long, default_value = "")]
+        prefix: String,
+
+        /// Don't print progress to stderr
+        #[arg(short, long)]
+        quiet: bool,
     },
 
     /// Show information about the DHT node
-    Info,
+    Info {
+        /// Also dump the node's routing table
+        #[arg(short, long)]
+        routes: bool,
+    },
+
+    /// Republish all values stored on the node
+    Republish,
+
+    /// Refresh the node's k-buckets
+    Refresh,
+
+    /// Trace the lookup of a key, showing every queried peer
+    Trace {
+        /// Key to trace: hex, hex:, name:, hash-of-file: or app/<namespace>/<name>
+        #[arg(value_parser = parse_key_arg)]
+        key: KeyArg,
+
+        /// How to print the trace as text
+        #[arg(short, long, value_enum, default_value_t = TraceRender::Timeline)]
+        render: TraceRender,
+    },
+
+    /// Check the liveness of every contact the node returns
+    PingSweep {
+        /// Ask for the contacts closest to this key instead of to the node itself
+        #[arg(long, value_parser = parse_key_arg)]
+        target: Option<KeyArg>,
+    },
+
+    /// Print every new version of a key's value as it appears
+    Watch {
+        /// Key to watch: hex, hex:, name:, hash-of-file: or app/<namespace>/<name>
+        #[arg(value_parser = parse_key_arg)]
+        key: KeyArg,
+
+        /// Seconds between polls of the nodes holding the key
+        #[arg(short, long, default_value = "2")]
+        interval: u64,
+
+        /// Milliseconds a new version has to stay current before it's printed
+        #[arg(short, long, default_value = "0")]
+        debounce: u64,
+
+        /// Stop after this many changes
+        #[arg(short, long)]
+        count: Option<usize>,
+
+        /// How to print values
+        #[arg(short, long, value_enum, default_value_t = ValueFormat::Text)]
+        format: ValueFormat,
+    },
+
+    /// Measure throughput and latency under a mix of puts, gets and lookups
+    Bench {
+        /// Relative weights of the operations
+        #[arg(short, long, value_parser = parse_mix, default_value = "put=1,get=8,find_node=1")]
+        mix: Mix,
+
+        /// Seconds to start operations for
+        #[arg(short, long, default_value = "10")]
+        duration: u64,
+
+        /// Operations to start per second; as many as --concurrency allows if not given
+        #[arg(short, long)]
+        rate: Option<f64>,
+
+        /// Maximum number of operations in flight
+        #[arg(short, long, default_value = "16")]
+        concurrency: usize,
+
+        /// Number of distinct keys
+        #[arg(short, long, default_value = "1000")]
+        keys: usize,
+
+        /// How keys are picked
+        #[arg(long, value_enum, default_value_t = KeyDistribution::Uniform)]
+        distribution: KeyDistribution,
+
+        /// Exponent of the Zipf distribution
+        #[arg(long, default_value = "1.0")]
+        zipf_exponent: f64,
+
+        /// Size of the values put, in bytes
+        #[arg(long, default_value = "100")]
+        value_size: usize,
+
+        /// Seed for picking operations, keys and values
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Put every key once before measuring
+        #[arg(long)]
+        prefill: bool,
+
+        /// Send operations straight to the node instead of through lookups
+        #[arg(long)]
+        direct: bool,
+
+        /// Don't print progress to stderr
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Start a local cluster of nodes on sequential ports
+    Devnet {
+        /// Number of nodes
+        #[arg(long, default_value = "5")]
+        nodes: usize,
+
+        /// RPC port of the first node
+        #[arg(long, default_value = "9000")]
+        base_port: u16,
+
+        /// Control port of the first node
+        #[arg(long, default_value = "9100")]
+        control_base_port: u16,
+
+        /// Directory holding the storage of every node
+        #[arg(long, default_value = "./.devnet")]
+        dir: PathBuf,
+
+        /// Run every node in its own child process
+        #[arg(long)]
+        processes: bool,
+
+        /// Keep the nodes' storage after stopping
+        #[arg(long)]
+        keep: bool,
+
+        /// Stop after this many seconds instead of waiting for Ctrl+C
+        #[arg(long)]
+        lifetime: Option<u64>,
+    },
+
+    /// Run one node of a devnet; started by `devnet --processes`
+    #[command(hide = true)]
+    DevnetNode {
+        #[arg(long)]
+        addr: SocketAddr,
+
+        #[arg(long)]
+        control_addr: SocketAddr,
+
+        #[arg(long)]
+        dir: PathBuf,
+
+        #[arg(long)]
+        seed: Vec<SocketAddr>,
+    },
+
+    /// Start an interactive shell keeping one session with the network
+    Shell,
 
     /// Generate a new key
     GenerateKey {
@@ -68,136 +337,352 @@ enum Commands {
     },
 }
 
-/// Create a Key from a hex string
-fn parse_key(hex_key: &str) -> Result<Key> {
-    let key_bytes = hex::decode(hex_key)?;
-    if key_bytes.len() != 20 {
-        anyhow::bail!("Invalid key length. Expected 20 bytes (40 hex characters)");
+/// Read the value to store from the argument, a file or stdin
+fn read_value(value: Option<&str>, file: Option<&Path>) -> Result<Vec<u8>> {
+    if let Some(value) = value {
+        return Ok(value.as_bytes().to_vec());
+    }
+    if let Some(file) = file {
+        return Ok(std::fs::read(file)?);
+    }
+    let mut value = Vec::new();
+    std::io::stdin().read_to_end(&mut value)?;
+    Ok(value)
+}
+
+/// Join the network through the node as a non-storing participant
+async fn open_session(args: &Cli, addr: SocketAddr) -> Result<ClientSession> {
+    let session = ClientSession::new(args.network.parse()?).await?;
+    session.bootstrap(&[addr]).await.map_err(network_error)?;
+    Ok(session)
+}
+
+/// Connect to the control interface of the node
+async fn connect_control(args: &Cli) -> Result<ControlClient> {
+    let token = match &args.token {
+        Some(token) => token.clone(),
+        None => control::read_token_file(&args.token_file)?,
+    };
+    ControlClient::connect(args.control.parse()?, token).await.map_err(network_error)
+}
+
+/// Send a request to the node's control interface
+async fn control_request(control: &mut ControlClient, request: ControlRequest, duration: Duration) -> Result<ControlResponse> {
+    match with_timeout(duration, control.request(request)).await? {
+        ControlResponse::Error(e) => Err(anyhow!("Control request failed: {}", e)),
+        response => Ok(response),
     }
-    let mut bytes = [0u8; 20];
-    bytes.copy_from_slice(&key_bytes);
-    Ok(Key::new(bytes))
 }
 
-/// Generate a key from input string using SHA-1
-fn generate_key_from_string(input: &str) -> Key {
-    let mut hasher = Sha1::new();
-    hasher.update(input.as_bytes());
-    let result = hasher.finalize();
-    let mut bytes = [0u8; 20];
-    bytes.copy_from_slice(&result);
-    Key::new(bytes)
+/// Parse a hex key prefix, allowing an odd number of digits
+fn parse_prefix(prefix: &str) -> Result<Vec<u8>> {
+    // An odd trailing digit can't be matched on byte boundaries, so the
+    // listing is filtered on the full hex string afterwards
+    let even = &prefix[..prefix.len() - prefix.len() % 2];
+    Ok(hex::decode(even)?)
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     // Parse command line arguments
     let args = Cli::parse();
 
+    match run(&args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(args.output, &e),
+    }
+}
+
+async fn run(args: &Cli) -> Result<()> {
     // Parse node address
     let addr: SocketAddr = args.node.parse()?;
     let timeout_duration = Duration::from_secs(args.timeout);
 
-    // Create RPC client
-    let client = RpcClient::new().await?;
+    // Create RPC client; it doesn't serve requests, so keep it out of routing tables
+    let client = RpcClient::with_network(args.network.parse()?).await?;
+    client.set_client_only(true);
     let node_id = NodeId::random();
 
-    match args.command {
-        Commands::List { pattern } => {
-            println!("Listing key-value pairs...");
-            let result = timeout(
-                timeout_duration,
-                client.find_node(node_id, Key::random(), addr)
-            ).await??;
-
-            for (node_id, node_addr) in result {
-                if let Ok(Ok(value)) = client.find_value(node_id, node_id, node_addr).await {
-                    let key_hex = hex::encode(node_id.as_bytes());
-                    let value_str = String::from_utf8_lossy(&value);
-
-                    // Apply pattern filter if specified
-                    if let Some(ref pattern) = pattern {
-                        if !key_hex.contains(pattern) && !value_str.contains(pattern) {
-                            continue;
-                        }
-                    }
-
-                    println!("Key: {}", key_hex);
-                    println!("Value: {}", value_str);
-                    println!("---");
-                }
+    match &args.command {
+        Commands::List { prefix, limit, after } => {
+            let prefix_bytes = parse_prefix(prefix)?;
+            let start_after = after.as_ref().map(|after| after.key);
+            let mut control = connect_control(args).await?;
+
+            let request = ControlRequest::ListKeys { prefix: prefix_bytes, start_after, limit: *limit };
+            if let ControlResponse::Keys { keys, next } = control_request(&mut control, request, timeout_duration).await? {
+                let keys = keys
+                    .into_iter()
+                    .map(|(key, bytes)| ListedKey { key: hex::encode(key.as_bytes()), bytes })
+                    .filter(|listed| listed.key.starts_with(prefix.as_str()))
+                    .collect();
+                let next = next.map(|next| next.to_string());
+                emit(args.output, &ListReport { keys, next })?;
+            }
+        },
+
+        Commands::Get { key: key_arg, format, out } => {
+            let found = with_timeout(timeout_duration, async {
+                let session = open_session(args, addr).await?;
+                session.get(key_arg.key, ValueMode::Plain).await.map_err(network_error)
+            }).await?;
+
+            let Some(found) = found else {
+                return Err(Failure::error(ErrorKind::NotFound, format!("Key not found: {}", key_arg)));
+            };
+            if let Some(out) = out {
+                std::fs::write(out, format.encode(&found.value))?;
+            }
+            let out = out.as_ref().map(|out| out.display().to_string());
+            emit(args.output, &GetReport::new(key_arg, found, *format, out))?;
+        },
+
+        Commands::Put { key: key_arg, value, file } => {
+            let value = read_value(value.as_deref(), file.as_deref())?;
+            let outcomes = with_timeout(timeout_duration, async {
+                let session = open_session(args, addr).await?;
+                session.put(key_arg.key, value, ValueMode::Plain).await.map_err(network_error)
+            }).await?;
+
+            let report = StoreReport::new(key_arg, outcomes);
+            emit(args.output, &report)?;
+            if let Some(failure) = report.failure() {
+                return Err(failure);
+            }
+        },
+
+        Commands::PutFile { path } => {
+            let value = std::fs::read(path)?;
+            let key_arg = KeyArg {
+                key: integrity::content_key(&value),
+                derived_from: Some(format!("hash-of-file:{}", path.display())),
+            };
+            let outcomes = with_timeout(timeout_duration, async {
+                let session = open_session(args, addr).await?;
+                session.put(key_arg.key, value, ValueMode::ContentAddressed).await.map_err(network_error)
+            }).await?;
+
+            let report = StoreReport::new(&key_arg, outcomes);
+            emit(args.output, &report)?;
+            if let Some(failure) = report.failure() {
+                return Err(failure);
             }
         },
 
-        Commands::Get { key } => {
-            let key = parse_key(&key)?;
-            match timeout(
+        Commands::Delete { key: key_arg } => {
+            let deleted = with_timeout(
                 timeout_duration,
-                client.find_value(node_id, key, addr)
-            ).await?? {
-                Ok(value) => {
-                    println!("Value: {}", String::from_utf8_lossy(&value));
-                },
-                Err(_) => {
-                    println!("Key not found");
+                async {
+                    // Empty value for deletion
+                    client.store(node_id, addr, key_arg.key, Vec::new(), ValueMode::Plain).await.map_err(network_error)
                 }
+            ).await?;
+
+            emit(args.output, &DeleteReport { key: key_arg.hex(), derived_from: key_arg.derived_from.clone(), deleted })?;
+            if !deleted {
+                return Err(Failure::reported(ErrorKind::StoreRefused, "The node refused to delete the key"));
             }
         },
 
-        Commands::Put { key, value } => {
-            let key = parse_key(&key)?;
-            let success = timeout(
-                timeout_duration,
-                client.store(node_id, node_id, addr, key, value.into_bytes())
-            ).await??;
+        Commands::Import { path, format, concurrency, retries, quiet } => {
+            let format = format.unwrap_or_else(|| RecordFormat::for_path(path.as_deref()));
+            let input: Box<dyn Read> = match path {
+                Some(path) => Box::new(std::fs::File::open(path)?),
+                None => Box::new(std::io::stdin()),
+            };
+            let session = with_timeout(timeout_duration, open_session(args, addr)).await?;
 
-            if success {
-                println!("Value stored successfully");
-            } else {
-                println!("Failed to store value");
+            let options = ImportOptions { concurrency: *concurrency, retries: *retries, timeout: timeout_duration, progress: !quiet };
+            let report = import(&session, read_records(input, format), &options).await;
+            emit(args.output, &report)?;
+            if report.failed > 0 {
+                return Err(Failure::reported(ErrorKind::Error, format!("{} of {} records failed", report.failed, report.records)));
             }
         },
 
-        Commands::Delete { key } => {
-            let key = parse_key(&key)?;
-            let success = timeout(
-                timeout_duration,
-                client.store(node_id, node_id, addr, key, Vec::new())  // Empty value for deletion
-            ).await??;
+        Commands::Export { out, format, prefix, quiet } => {
+            let format = format.unwrap_or_else(|| RecordFormat::for_path(out.as_deref()));
+            let output: Box<dyn Write> = match out {
+                Some(out) => Box::new(BufWriter::new(std::fs::File::create(out)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut writer = RecordWriter::new(output, format)?;
+            let prefix_bytes = parse_prefix(prefix)?;
+            let mut control = connect_control(args).await?;
 
-            if success {
-                println!("Key deleted successfully");
-            } else {
-                println!("Failed to delete key");
+            let mut exported = 0;
+            let mut start_after = None;
+            loop {
+                let request = ControlRequest::ExportRecords { prefix: prefix_bytes.clone(), start_after, limit: control::MAX_EXPORT_PAGE_SIZE };
+                let ControlResponse::Records { records, next } = control_request(&mut control, request, timeout_duration).await? else {
+                    return Err(anyhow!("Unexpected response to an export request"));
+                };
+                for record in records.iter().filter(|record| hex::encode(record.key.as_bytes()).starts_with(prefix.as_str())) {
+                    writer.write(record)?;
+                    exported += 1;
+                }
+                if !quiet {
+                    eprintln!("{} records exported", exported);
+                }
+                match next {
+                    Some(next) => start_after = Some(next),
+                    None => break,
+                }
+            }
+            writer.finish()?;
+
+            // Records written to stdout can't share it with the report
+            if let Some(out) = out {
+                emit(args.output, &ExportReport { records: exported, out: out.display().to_string() })?;
             }
         },
 
-        Commands::Info => {
+        Commands::Info { routes } => {
             // Try to ping the node
-            let ping_result = timeout(
+            let online = with_timeout(
                 timeout_duration,
-                client.ping(node_id, addr)
-            ).await??;
+                async { client.ping(node_id, addr).await.map_err(network_error) }
+            ).await?;
 
-            println!("DHT Node Information");
-            println!("-------------------");
-            println!("Address: {}", addr);
-            println!("Status: {}", if ping_result { "Online" } else { "Offline" });
+            let mut report = InfoReport { address: addr, online, node: None, storage: None, routing_table: None };
 
-            // Get routing table information
-            let nodes = timeout(
-                timeout_duration,
-                client.find_node(node_id, Key::random(), addr)
-            ).await??;
+            // Get node state through the control interface
+            let mut control = connect_control(args).await?;
+            if let ControlResponse::Info(status) = control_request(&mut control, ControlRequest::Info, timeout_duration).await? {
+                report.node = Some(status.into());
+            }
+            if let ControlResponse::StorageStats(stats) = control_request(&mut control, ControlRequest::StorageStats, timeout_duration).await? {
+                report.storage = Some(stats);
+            }
+
+            if *routes {
+                if let ControlResponse::RoutingTable(buckets) = control_request(&mut control, ControlRequest::RoutingTable, timeout_duration).await? {
+                    report.routing_table = Some(buckets.into_iter().map(Into::into).collect());
+                }
+            }
+            emit(args.output, &report)?;
+        },
+
+        Commands::Republish => {
+            let mut control = connect_control(args).await?;
+            if let ControlResponse::Republished(republished) = control.request(ControlRequest::Republish).await? {
+                emit(args.output, &RepublishReport { republished })?;
+            }
+        },
+
+        Commands::Refresh => {
+            let mut control = connect_control(args).await?;
+            if let ControlResponse::Refreshed(refreshed) = control.request(ControlRequest::Refresh).await? {
+                emit(args.output, &RefreshReport { refreshed })?;
+            }
+        },
+
+        Commands::Trace { key: key_arg, render } => {
+            let trace = with_timeout(timeout_duration, async {
+                let session = open_session(args, addr).await?;
+                session.trace(key_arg.key, ValueMode::Plain).await.map_err(network_error)
+            }).await?;
+
+            let report = TraceReport::new(key_arg, trace, *render);
+            emit(args.output, &report)?;
+            if !report.found {
+                return Err(Failure::reported(ErrorKind::NotFound, format!("Key not found: {}", key_arg)));
+            }
+        },
+
+        Commands::PingSweep { target } => {
+            let report = with_timeout(timeout_duration, async {
+                ping_sweep(&client, node_id, addr, target.as_ref().map(|target| target.key)).await.map_err(network_error)
+            }).await?;
+            emit(args.output, &report)?;
+        },
+
+        Commands::Watch { key: key_arg, interval, debounce, count, format } => {
+            if *interval == 0 {
+                return Err(anyhow!("The interval has to be at least one second"));
+            }
+            let options = WatchOptions {
+                interval: Duration::from_secs(*interval),
+                debounce: Duration::from_millis(*debounce),
+                count: *count,
+                timeout: timeout_duration,
+                format: *format,
+                output: args.output,
+            };
+            let session = with_timeout(timeout_duration, open_session(args, addr)).await?;
+            watch::run(&session, key_arg, &options).await?;
+        },
+
+        Commands::Bench { mix, duration, rate, concurrency, keys, distribution, zipf_exponent, value_size, seed, prefill, direct, quiet } => {
+            if rate.is_some_and(|rate| !rate.is_finite() || rate <= 0.0) {
+                return Err(anyhow!("The rate has to be a positive number"));
+            }
+            let options = BenchOptions {
+                mix: mix.clone(),
+                duration: Duration::from_secs(*duration),
+                rate: *rate,
+                concurrency: *concurrency,
+                keys: *keys,
+                distribution: *distribution,
+                zipf_exponent: *zipf_exponent,
+                value_size: *value_size,
+                timeout: timeout_duration,
+                seed: *seed,
+                prefill: *prefill,
+                progress: !quiet,
+            };
+
+            let session;
+            let target = if *direct {
+                BenchTarget::Direct { client: &client, own_id: node_id, addr }
+            } else {
+                session = with_timeout(timeout_duration, open_session(args, addr)).await?;
+                BenchTarget::Network(&session)
+            };
+            let report = bench::run(&target, &options).await?;
+            emit(args.output, &report)?;
+        },
+
+        Commands::Devnet { nodes, base_port, control_base_port, dir, processes, keep, lifetime } => {
+            let options = DevnetOptions {
+                nodes: *nodes,
+                base_port: *base_port,
+                control_base_port: *control_base_port,
+                dir: dir.clone(),
+                network: args.network.parse()?,
+                processes: *processes,
+                keep: *keep,
+                lifetime: lifetime.map(Duration::from_secs),
+                startup_timeout: timeout_duration,
+            };
+            let (devnet, report) = Devnet::start(&options).await?;
+            emit(args.output, &report)?;
+
+            let waited = wait_for_shutdown(options.lifetime).await;
+            if args.output == OutputFormat::Text {
+                println!("Stopping {} nodes", report.nodes.len());
+            }
+            devnet.stop(options.keep).await;
+            waited?;
+        },
+
+        Commands::DevnetNode { addr, control_addr, dir, seed } => {
+            let spec = NodeSpec { addr: *addr, control_addr: *control_addr, dir: dir.clone(), seeds: seed.clone() };
+            run_child_node(&spec, &args.network.parse()?).await?;
+        },
 
-            println!("Known nodes: {}", nodes.len());
+        Commands::Shell => {
+            let session = with_timeout(timeout_duration, open_session(args, addr)).await?;
+            let shell = Shell { session: &session, seed: addr, output: args.output, timeout: timeout_duration };
+            shell.run().await?;
         },
 
         Commands::GenerateKey { from, count } => {
-            for i in 0..count {
+            let mut keys = Vec::with_capacity(*count);
+            for i in 0..*count {
                 let key = if let Some(input) = &from {
                     // If count > 1, append a number to the input string
-                    let input = if count > 1 {
+                    let input = if *count > 1 {
                         format!("{}-{}", input, i + 1)
                     } else {
                         input.clone()
@@ -207,8 +692,9 @@ async fn main() -> Result<()> {
                     Key::random()
                 };
 
-                println!("Generated Key: {}", hex::encode(key.as_bytes()));
+                keys.push(hex::encode(key.as_bytes()));
             }
+            emit(args.output, &GeneratedKeysReport { keys })?;
         }
     }
 
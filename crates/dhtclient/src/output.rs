@@ -0,0 +1,622 @@
+//! Printing command results as text or JSON, and the exit codes of failures.
+//!
+//! Every command builds a report and prints it in the format chosen with
+//! `--output`. JSON reports are printed as one object per line. Failures exit
+//! with a code telling scripts what went wrong:
+//!
+//! | Code | Meaning                                   |
+//! |------|-------------------------------------------|
+//! | 0    | Success                                   |
+//! | 1    | Any other error                           |
+//! | 2    | Invalid command line                      |
+//! | 3    | Key not found                             |
+//! | 4    | Timed out                                 |
+//! | 5    | Store refused by every node               |
+//! | 6    | Network error, such as an unreachable node |
+
+use anyhow::{Error, Result};
+use base64::Engine;
+use clap::ValueEnum;
+use protocol::control::{BucketDump, NodeStatus};
+use protocol::storage::StorageStats;
+use protocol::{StoreOutcome, StoreStatus};
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use crate::keys::KeyArg;
+
+/// How command results are printed
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// One JSON object per result
+    Json,
+}
+
+/// How values are printed or written
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ValueFormat {
+    /// As text, replacing invalid UTF-8
+    Text,
+    /// The bytes unchanged; base64 encoded in JSON output
+    Raw,
+    /// Hex encoded
+    Hex,
+    /// Base64 encoded
+    Base64,
+}
+
+impl ValueFormat {
+    /// Encode a value in this format
+    pub fn encode(self, value: &[u8]) -> Vec<u8> {
+        match self {
+            ValueFormat::Text | ValueFormat::Raw => value.to_vec(),
+            ValueFormat::Hex => hex::encode(value).into_bytes(),
+            ValueFormat::Base64 => base64::engine::general_purpose::STANDARD.encode(value).into_bytes(),
+        }
+    }
+
+    /// Encode a value for a JSON string, returning the encoding used
+    pub fn encode_json(self, value: &[u8]) -> (&'static str, String) {
+        match self {
+            ValueFormat::Text => ("text", String::from_utf8_lossy(value).into_owned()),
+            ValueFormat::Hex => ("hex", hex::encode(value)),
+            ValueFormat::Raw | ValueFormat::Base64 => {
+                ("base64", base64::engine::general_purpose::STANDARD.encode(value))
+            }
+        }
+    }
+}
+
+/// What kind of failure a command ended with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Any other error
+    Error,
+    /// The key has no value
+    NotFound,
+    /// The command didn't finish within --timeout
+    Timeout,
+    /// Every node refused the value
+    StoreRefused,
+    /// Nodes couldn't be reached
+    Network,
+}
+
+impl ErrorKind {
+    /// Return the exit code of this kind of failure
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            ErrorKind::Error => 1,
+            ErrorKind::NotFound => 3,
+            ErrorKind::Timeout => 4,
+            ErrorKind::StoreRefused => 5,
+            ErrorKind::Network => 6,
+        })
+    }
+}
+
+/// A failure with the kind it exits with
+#[derive(Debug)]
+pub struct Failure {
+    kind: ErrorKind,
+    message: String,
+    /// Whether the command's report already describes the failure
+    reported: bool,
+}
+
+impl Failure {
+    /// Create a failure of the given kind
+    pub fn error(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error::new(Failure { kind, message: message.into(), reported: false })
+    }
+
+    /// Create a failure that the printed report already describes
+    pub fn reported(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error::new(Failure { kind, message: message.into(), reported: true })
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Mark an error from talking to nodes as a network error
+pub fn network_error(error: Error) -> Error {
+    if error.is::<Failure>() {
+        return error;
+    }
+    Failure::error(ErrorKind::Network, format!("{:#}", error))
+}
+
+/// Run an operation, failing with a timeout once the duration passed
+pub async fn with_timeout<T>(duration: Duration, operation: impl Future<Output = Result<T>>) -> Result<T> {
+    match tokio::time::timeout(duration, operation).await {
+        Ok(result) => result,
+        Err(_) => Err(Failure::error(ErrorKind::Timeout, format!("Timed out after {}s", duration.as_secs()))),
+    }
+}
+
+/// Print the error a command failed with and return the exit code
+pub fn report_error(format: OutputFormat, error: &Error) -> ExitCode {
+    let (kind, reported) = match error.downcast_ref::<Failure>() {
+        Some(failure) => (failure.kind, failure.reported),
+        None => (ErrorKind::Error, false),
+    };
+    match format {
+        OutputFormat::Text => eprintln!("Error: {:#}", error),
+        OutputFormat::Json if !reported => {
+            let object = serde_json::json!({ "error": format!("{:#}", error), "kind": kind });
+            println!("{}", object);
+        }
+        OutputFormat::Json => {}
+    }
+    kind.exit_code()
+}
+
+/// A command result that can be printed as text or JSON
+pub trait Report: Serialize {
+    /// Print the result for people
+    fn print_text(&self) -> Result<()>;
+}
+
+/// Print a report in the chosen format
+pub fn emit(format: OutputFormat, report: &impl Report) -> Result<()> {
+    match format {
+        OutputFormat::Text => report.print_text(),
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string(report)?);
+            Ok(())
+        }
+    }
+}
+
+/// A stored key and the size of its value
+#[derive(Serialize)]
+pub struct ListedKey {
+    pub key: String,
+    pub bytes: usize,
+}
+
+/// Result of `list`
+#[derive(Serialize)]
+pub struct ListReport {
+    pub keys: Vec<ListedKey>,
+    pub next: Option<String>,
+}
+
+impl Report for ListReport {
+    fn print_text(&self) -> Result<()> {
+        for listed in &self.keys {
+            println!("{}  {} bytes", listed.key, listed.bytes);
+        }
+        if let Some(next) = &self.next {
+            println!("--- more keys available, continue with --after {}", next);
+        }
+        Ok(())
+    }
+}
+
+/// Result of `get`
+#[derive(Serialize)]
+pub struct GetReport {
+    pub key: String,
+    pub derived_from: Option<String>,
+    /// How `value` is encoded; absent if the value was written to a file
+    pub encoding: Option<&'static str>,
+    pub value: Option<String>,
+    /// File the value was written to
+    pub out: Option<String>,
+    pub bytes: usize,
+    pub holder: String,
+    pub holder_addr: Option<SocketAddr>,
+    #[serde(skip)]
+    raw: Vec<u8>,
+    #[serde(skip)]
+    format: ValueFormat,
+}
+
+impl GetReport {
+    /// Describe a found value, printed in the given format unless it was written to `out`
+    pub fn new(key: &KeyArg, found: protocol::FoundValue, format: ValueFormat, out: Option<String>) -> Self {
+        let (encoding, value) = match out {
+            Some(_) => (None, None),
+            None => {
+                let (encoding, value) = format.encode_json(&found.value);
+                (Some(encoding), Some(value))
+            }
+        };
+        GetReport {
+            key: key.hex(),
+            derived_from: key.derived_from.clone(),
+            encoding,
+            value,
+            out,
+            bytes: found.value.len(),
+            holder: found.holder.to_string(),
+            holder_addr: found.holder_addr,
+            raw: found.value,
+            format,
+        }
+    }
+
+    fn holder(&self) -> String {
+        match self.holder_addr {
+            Some(addr) => format!("{} ({})", self.holder, addr),
+            None => self.holder.clone(),
+        }
+    }
+
+    fn key(&self) -> String {
+        match &self.derived_from {
+            Some(source) => format!("{} (from {})", self.key, source),
+            None => self.key.clone(),
+        }
+    }
+}
+
+impl Report for GetReport {
+    fn print_text(&self) -> Result<()> {
+        if let Some(out) = &self.out {
+            println!("Key: {}", self.key());
+            println!("Wrote {} bytes to {}", self.format.encode(&self.raw).len(), out);
+            println!("Held by: {}", self.holder());
+        } else if self.format == ValueFormat::Text {
+            println!("Key: {}", self.key());
+            println!("Value: {}", String::from_utf8_lossy(&self.raw));
+            println!("Held by: {}", self.holder());
+        } else {
+            // Keep stdout to the value alone, so it can be piped
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&self.format.encode(&self.raw))?;
+            if self.format != ValueFormat::Raw {
+                stdout.write_all(b"\n")?;
+            }
+            eprintln!("Key: {}", self.key());
+            eprintln!("Held by: {}", self.holder());
+        }
+        Ok(())
+    }
+}
+
+/// Answer of one node to a store request
+#[derive(Serialize)]
+pub struct NodeStoreReport {
+    pub node: String,
+    pub addr: SocketAddr,
+    /// "accepted", "refused" or "failed"
+    pub status: &'static str,
+    pub error: Option<String>,
+}
+
+/// Result of `put` and `put-file`
+#[derive(Serialize)]
+pub struct StoreReport {
+    pub key: String,
+    pub derived_from: Option<String>,
+    pub stored_on: usize,
+    pub nodes: Vec<NodeStoreReport>,
+}
+
+impl StoreReport {
+    /// Describe the answers of the nodes asked to store a value
+    pub fn new(key: &KeyArg, outcomes: Vec<StoreOutcome>) -> Self {
+        let nodes: Vec<NodeStoreReport> = outcomes
+            .into_iter()
+            .map(|outcome| {
+                let (status, error) = match outcome.status {
+                    StoreStatus::Accepted => ("accepted", None),
+                    StoreStatus::Refused => ("refused", None),
+                    StoreStatus::Failed(e) => ("failed", Some(e)),
+                };
+                NodeStoreReport { node: outcome.node.to_string(), addr: outcome.addr, status, error }
+            })
+            .collect();
+        StoreReport {
+            key: key.hex(),
+            derived_from: key.derived_from.clone(),
+            stored_on: nodes.iter().filter(|node| node.status == "accepted").count(),
+            nodes,
+        }
+    }
+
+    /// Return the failure if no node accepted the value
+    pub fn failure(&self) -> Option<Error> {
+        if self.stored_on > 0 {
+            return None;
+        }
+        if self.nodes.iter().any(|node| node.status == "refused") {
+            Some(Failure::reported(ErrorKind::StoreRefused, "Every node refused the value"))
+        } else {
+            Some(Failure::reported(ErrorKind::Network, "No node could be reached to store the value"))
+        }
+    }
+}
+
+impl Report for StoreReport {
+    fn print_text(&self) -> Result<()> {
+        match &self.derived_from {
+            Some(source) => println!("Key: {} (from {})", self.key, source),
+            None => println!("Key: {}", self.key),
+        }
+        for node in &self.nodes {
+            let status = match &node.error {
+                Some(e) => format!("{}: {}", node.status, e),
+                None => node.status.to_string(),
+            };
+            println!("  {}  {}  {}", node.node, node.addr, status);
+        }
+        if self.stored_on > 0 {
+            println!("Value stored on {} of {} nodes", self.stored_on, self.nodes.len());
+        } else {
+            println!("Failed to store value");
+        }
+        Ok(())
+    }
+}
+
+/// Result of `delete`
+#[derive(Serialize)]
+pub struct DeleteReport {
+    pub key: String,
+    pub derived_from: Option<String>,
+    pub deleted: bool,
+}
+
+impl Report for DeleteReport {
+    fn print_text(&self) -> Result<()> {
+        match &self.derived_from {
+            Some(source) => println!("Key: {} (from {})", self.key, source),
+            None => println!("Key: {}", self.key),
+        }
+        if self.deleted {
+            println!("Key deleted successfully");
+        } else {
+            println!("Failed to delete key");
+        }
+        Ok(())
+    }
+}
+
+/// General information about a node, as reported by `info`
+#[derive(Serialize)]
+pub struct NodeReport {
+    pub id: String,
+    pub addr: SocketAddr,
+    pub network: String,
+    pub uptime_secs: u64,
+    pub client_only: bool,
+    pub known_nodes: usize,
+}
+
+impl From<NodeStatus> for NodeReport {
+    fn from(status: NodeStatus) -> Self {
+        NodeReport {
+            id: status.id.to_string(),
+            addr: status.addr,
+            network: status.network.to_string(),
+            uptime_secs: status.uptime_secs,
+            client_only: status.client_only,
+            known_nodes: status.known_nodes,
+        }
+    }
+}
+
+/// A contact in a routing table dump
+#[derive(Serialize)]
+pub struct ContactReport {
+    pub id: String,
+    pub addr: SocketAddr,
+    pub last_seen_secs: u64,
+    pub successes: u32,
+    pub timeouts: u32,
+    pub bad_data: u32,
+    pub latency_ms: Option<u64>,
+}
+
+/// A non-empty k-bucket in a routing table dump
+#[derive(Serialize)]
+pub struct BucketReport {
+    pub index: usize,
+    pub depth: usize,
+    pub contacts: Vec<ContactReport>,
+}
+
+impl From<BucketDump> for BucketReport {
+    fn from(bucket: BucketDump) -> Self {
+        BucketReport {
+            index: bucket.index,
+            depth: bucket.depth,
+            contacts: bucket
+                .contacts
+                .into_iter()
+                .map(|contact| ContactReport {
+                    id: contact.id.to_string(),
+                    addr: contact.addr,
+                    last_seen_secs: contact.last_seen_secs,
+                    successes: contact.successes,
+                    timeouts: contact.timeouts,
+                    bad_data: contact.bad_data,
+                    latency_ms: contact.latency_ms,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Print the buckets of a routing table
+pub fn print_buckets(buckets: &[BucketReport]) {
+    for bucket in buckets {
+        println!(
+            "Bucket {} (depth {}, {} nodes)",
+            bucket.index,
+            bucket.depth,
+            bucket.contacts.len()
+        );
+        for contact in &bucket.contacts {
+            let latency = contact
+                .latency_ms
+                .map_or_else(|| "-".to_string(), |ms| format!("{}ms", ms));
+            println!(
+                "  {}  {}  seen {}s ago  ok {}  timeouts {}  bad {}  latency {}",
+                contact.id,
+                contact.addr,
+                contact.last_seen_secs,
+                contact.successes,
+                contact.timeouts,
+                contact.bad_data,
+                latency
+            );
+        }
+    }
+}
+
+/// Result of `info`
+#[derive(Serialize)]
+pub struct InfoReport {
+    pub address: SocketAddr,
+    pub online: bool,
+    pub node: Option<NodeReport>,
+    pub storage: Option<StorageStats>,
+    pub routing_table: Option<Vec<BucketReport>>,
+}
+
+impl Report for InfoReport {
+    fn print_text(&self) -> Result<()> {
+        println!("DHT Node Information");
+        println!("-------------------");
+        println!("Address: {}", self.address);
+        println!("Status: {}", if self.online { "Online" } else { "Offline" });
+        if let Some(node) = &self.node {
+            println!("Node ID: {}", node.id);
+            println!("Bound address: {}", node.addr);
+            println!("Network: {}", node.network);
+            println!("Uptime: {}s", node.uptime_secs);
+            println!("Client-only: {}", node.client_only);
+            println!("Known nodes: {}", node.known_nodes);
+        }
+        if let Some(stats) = &self.storage {
+            println!("Stored entries: {}", stats.entries);
+            println!("Stored bytes: {}", stats.value_bytes);
+            println!("Disk usage: {} bytes", stats.disk_bytes);
+        }
+        if let Some(buckets) = &self.routing_table {
+            println!();
+            println!("Routing Table");
+            println!("-------------");
+            print_buckets(buckets);
+        }
+        Ok(())
+    }
+}
+
+/// Result of `republish`
+#[derive(Serialize)]
+pub struct RepublishReport {
+    pub republished: usize,
+}
+
+impl Report for RepublishReport {
+    fn print_text(&self) -> Result<()> {
+        println!("Republished {} values", self.republished);
+        Ok(())
+    }
+}
+
+/// Result of `refresh`
+#[derive(Serialize)]
+pub struct RefreshReport {
+    pub refreshed: usize,
+}
+
+impl Report for RefreshReport {
+    fn print_text(&self) -> Result<()> {
+        println!("Refreshed {} buckets", self.refreshed);
+        Ok(())
+    }
+}
+
+/// Result of `generate-key`
+#[derive(Serialize)]
+pub struct GeneratedKeysReport {
+    pub keys: Vec<String>,
+}
+
+impl Report for GeneratedKeysReport {
+    fn print_text(&self) -> Result<()> {
+        for key in &self.keys {
+            println!("Generated Key: {}", key);
+        }
+        Ok(())
+    }
+}
+
+/// Result of `ping` in the shell
+#[derive(Serialize)]
+pub struct PingReport {
+    pub addr: SocketAddr,
+    pub node: String,
+    pub latency_ms: u64,
+}
+
+impl Report for PingReport {
+    fn print_text(&self) -> Result<()> {
+        println!("{} ({}) answered in {}ms", self.addr, self.node, self.latency_ms);
+        Ok(())
+    }
+}
+
+/// A node found by a lookup
+#[derive(Serialize)]
+pub struct FoundNode {
+    pub id: String,
+    pub addr: SocketAddr,
+}
+
+/// Result of `lookup` in the shell
+#[derive(Serialize)]
+pub struct LookupReport {
+    pub key: String,
+    pub derived_from: Option<String>,
+    pub nodes: Vec<FoundNode>,
+}
+
+impl Report for LookupReport {
+    fn print_text(&self) -> Result<()> {
+        match &self.derived_from {
+            Some(source) => println!("Closest nodes to {} (from {})", self.key, source),
+            None => println!("Closest nodes to {}", self.key),
+        }
+        for node in &self.nodes {
+            println!("  {}  {}", node.id, node.addr);
+        }
+        Ok(())
+    }
+}
+
+/// Result of `routes` in the shell
+#[derive(Serialize)]
+pub struct RoutesReport {
+    pub buckets: Vec<BucketReport>,
+}
+
+impl Report for RoutesReport {
+    fn print_text(&self) -> Result<()> {
+        if self.buckets.is_empty() {
+            println!("No known nodes");
+        }
+        print_buckets(&self.buckets);
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Interactive shell keeping one client session across commands.
+//!
+//! The session is bootstrapped once, and the contacts it learns from each
+//! lookup speed up the next one. Lines are read with history and tab
+//! completion of command names; results are printed as each command finishes,
+//! in the format chosen with `--output`.
+
+use anyhow::{anyhow, bail, Result};
+use protocol::{ClientSession, ValueMode};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::keys::parse_key_arg;
+use crate::output::{
+    emit, network_error, report_error, with_timeout, ErrorKind, Failure, FoundNode, GetReport,
+    LookupReport, OutputFormat, PingReport, RoutesReport, StoreReport, ValueFormat,
+};
+
+/// Commands understood by the shell, with their usage
+const COMMANDS: &[(&str, &str)] = &[
+    ("get", "get <key> [text|hex|base64]  Get a value from the nodes closest to the key"),
+    ("put", "put <key> <value>             Store a value on the nodes closest to the key"),
+    ("ping", "ping [address]                Ping a node, by default the bootstrap node"),
+    ("lookup", "lookup <key>                  Find the nodes closest to a key"),
+    ("routes", "routes                        Show the session's routing table"),
+    ("help", "help                          Show this help"),
+    ("exit", "exit                          Leave the shell"),
+];
+
+/// Name of the history file in the home directory
+const HISTORY_FILE: &str = ".dhtclient_history";
+
+/// Completes command names at the start of a line
+struct ShellHelper;
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = COMMANDS
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| format!("{} ", name))
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// What the shell works with
+pub struct Shell<'a> {
+    pub session: &'a ClientSession,
+    /// Node the session was bootstrapped from
+    pub seed: SocketAddr,
+    pub output: OutputFormat,
+    pub timeout: Duration,
+}
+
+impl Shell<'_> {
+    /// Read and run commands until the user leaves
+    pub async fn run(&self) -> Result<()> {
+        let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+        editor.set_helper(Some(ShellHelper));
+        let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(history) = &history {
+            // A missing history file just means a fresh history
+            let _ = editor.load_history(history);
+        }
+
+        println!("Connected through {}; type help for commands", self.seed);
+        loop {
+            let line = match tokio::task::block_in_place(|| editor.readline("dht> ")) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line)?;
+            if line == "exit" || line == "quit" {
+                break;
+            }
+            if let Err(e) = self.execute(line).await {
+                report_error(self.output, &e);
+            }
+        }
+
+        if let Some(history) = &history {
+            editor.save_history(history)?;
+        }
+        Ok(())
+    }
+
+    /// Run one command line
+    async fn execute(&self, line: &str) -> Result<()> {
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "get" => {
+                let (key, format) = rest.split_once(char::is_whitespace).unwrap_or((rest, "text"));
+                let key_arg = parse_key_arg(key)?;
+                let format = match format.trim() {
+                    "text" => ValueFormat::Text,
+                    "hex" => ValueFormat::Hex,
+                    "base64" => ValueFormat::Base64,
+                    other => bail!("Unknown value format {}", other),
+                };
+                let found = with_timeout(self.timeout, async {
+                    self.session.get(key_arg.key, ValueMode::Plain).await.map_err(network_error)
+                }).await?;
+                match found {
+                    Some(found) => emit(self.output, &GetReport::new(&key_arg, found, format, None)),
+                    None => Err(Failure::error(ErrorKind::NotFound, format!("Key not found: {}", key_arg))),
+                }
+            },
+
+            "put" => {
+                let Some((key, value)) = rest.split_once(char::is_whitespace) else {
+                    bail!("Usage: put <key> <value>");
+                };
+                let key_arg = parse_key_arg(key)?;
+                let value = value.trim().as_bytes().to_vec();
+                let outcomes = with_timeout(self.timeout, async {
+                    self.session.put(key_arg.key, value, ValueMode::Plain).await.map_err(network_error)
+                }).await?;
+                let report = StoreReport::new(&key_arg, outcomes);
+                emit(self.output, &report)?;
+                report.failure().map_or(Ok(()), Err)
+            },
+
+            "ping" => {
+                let addr = if rest.is_empty() { self.seed } else { rest.parse()? };
+                let started = Instant::now();
+                let node = with_timeout(self.timeout, async {
+                    self.session.client().identify(self.session.id(), addr).await.map_err(network_error)
+                }).await?;
+                let latency_ms = started.elapsed().as_millis() as u64;
+                emit(self.output, &PingReport { addr, node: node.to_string(), latency_ms })
+            },
+
+            "lookup" => {
+                let key_arg = parse_key_arg(rest)?;
+                let nodes = with_timeout(self.timeout, async {
+                    self.session.lookup_nodes(key_arg.key).await.map_err(network_error)
+                }).await?;
+                let nodes = nodes
+                    .into_iter()
+                    .map(|(id, addr)| FoundNode { id: id.to_string(), addr })
+                    .collect();
+                emit(self.output, &LookupReport { key: key_arg.hex(), derived_from: key_arg.derived_from, nodes })
+            },
+
+            "routes" => {
+                let buckets = self.session.routing_table_dump().await.into_iter().map(Into::into).collect();
+                emit(self.output, &RoutesReport { buckets })
+            },
+
+            "help" => {
+                for (_, usage) in COMMANDS {
+                    println!("{}", usage);
+                }
+                Ok(())
+            },
+
+            other => Err(anyhow!("Unknown command {}; type help for commands", other)),
+        }
+    }
+}
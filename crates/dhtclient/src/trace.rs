@@ -0,0 +1,277 @@
+//! Lookup tracing and routing diagnostics.
+//!
+//! `trace` runs a value lookup and reports every query: the peer, its XOR
+//! distance to the target, how long it took and the contacts it returned. The
+//! trace prints as a timeline or as a Graphviz graph of which peer led to which.
+//! `ping-sweep` checks that the contacts a node hands out are alive.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use futures::future::join_all;
+use protocol::{Distance, HopOutcome, Key, LookupTrace, NodeId, RpcClient};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::keys::KeyArg;
+use crate::output::Report;
+
+/// How a trace is printed as text
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceRender {
+    /// The queries in the order they finished
+    Timeline,
+    /// A Graphviz graph of which peer led to which
+    Dot,
+}
+
+/// XOR distance of a node to the lookup target
+#[derive(Serialize)]
+pub struct DistanceReport {
+    /// The distance as hex
+    pub distance: String,
+    /// Number of leading bits the node shares with the target
+    pub shared_bits: u32,
+}
+
+impl DistanceReport {
+    fn new(node: &NodeId, target: &Key) -> Self {
+        let distance = Distance::between(node, target);
+        DistanceReport {
+            distance: hex::encode(distance.as_bytes()),
+            shared_bits: distance.leading_zeros(),
+        }
+    }
+}
+
+/// A contact returned by a queried peer
+#[derive(Serialize)]
+pub struct ContactReport {
+    pub id: String,
+    pub addr: SocketAddr,
+    #[serde(flatten)]
+    pub distance: DistanceReport,
+}
+
+/// One query of a traced lookup
+#[derive(Serialize)]
+pub struct HopReport {
+    pub peer: String,
+    pub addr: SocketAddr,
+    #[serde(flatten)]
+    pub distance: DistanceReport,
+    pub sent_after_ms: u64,
+    pub latency_ms: u64,
+    /// "nodes", "value", "invalid_value" or "failed"
+    pub outcome: &'static str,
+    pub contacts: Vec<ContactReport>,
+    pub error: Option<String>,
+}
+
+/// Result of `trace`
+#[derive(Serialize)]
+pub struct TraceReport {
+    pub key: String,
+    pub derived_from: Option<String>,
+    pub found: bool,
+    pub holder: Option<String>,
+    pub hops: Vec<HopReport>,
+    /// Closest nodes that answered, if no value was found
+    pub closest: Vec<ContactReport>,
+    #[serde(skip)]
+    render: TraceRender,
+}
+
+impl TraceReport {
+    /// Describe a traced lookup of a key
+    pub fn new(key: &KeyArg, trace: LookupTrace, render: TraceRender) -> Self {
+        let contact = |(id, addr): (NodeId, SocketAddr)| ContactReport {
+            id: id.to_string(),
+            addr,
+            distance: DistanceReport::new(&id, &key.key),
+        };
+        let hops = trace
+            .hops
+            .into_iter()
+            .map(|hop| {
+                let (outcome, contacts, error) = match hop.outcome {
+                    HopOutcome::Nodes(nodes) => ("nodes", nodes.into_iter().map(contact).collect(), None),
+                    HopOutcome::Value => ("value", Vec::new(), None),
+                    HopOutcome::InvalidValue => ("invalid_value", Vec::new(), None),
+                    HopOutcome::Failed(e) => ("failed", Vec::new(), Some(e)),
+                };
+                HopReport {
+                    peer: hop.peer.to_string(),
+                    addr: hop.addr,
+                    distance: DistanceReport::new(&hop.peer, &key.key),
+                    sent_after_ms: hop.sent_after.as_millis() as u64,
+                    latency_ms: hop.latency.as_millis() as u64,
+                    outcome,
+                    contacts,
+                    error,
+                }
+            })
+            .collect();
+        TraceReport {
+            key: key.hex(),
+            derived_from: key.derived_from.clone(),
+            found: trace.found.is_some(),
+            holder: trace.found.map(|found| found.holder.to_string()),
+            hops,
+            closest: trace.closest.into_iter().map(contact).collect(),
+            render,
+        }
+    }
+
+    fn print_timeline(&self) {
+        match &self.derived_from {
+            Some(source) => println!("Trace of {} (from {})", self.key, source),
+            None => println!("Trace of {}", self.key),
+        }
+        for hop in &self.hops {
+            let result = match (hop.outcome, &hop.error) {
+                ("nodes", _) => format!("{} contacts", hop.contacts.len()),
+                ("value", _) => "value".to_string(),
+                ("invalid_value", _) => "invalid value".to_string(),
+                (_, Some(e)) => format!("failed: {}", e),
+                (outcome, None) => outcome.to_string(),
+            };
+            println!(
+                "  +{:>5}ms {:>5}ms  {}  {:<21}  shared bits {:>3}  -> {}",
+                hop.sent_after_ms,
+                hop.latency_ms,
+                hop.peer,
+                hop.addr,
+                hop.distance.shared_bits,
+                result
+            );
+            for contact in &hop.contacts {
+                println!(
+                    "                     {}  {:<21}  shared bits {:>3}",
+                    contact.id, contact.addr, contact.distance.shared_bits
+                );
+            }
+        }
+        match &self.holder {
+            Some(holder) => println!("Value found at {} after {} queries", holder, self.hops.len()),
+            None => {
+                println!("Value not found after {} queries; closest nodes:", self.hops.len());
+                for contact in &self.closest {
+                    println!("  {}  {}  shared bits {}", contact.id, contact.addr, contact.distance.shared_bits);
+                }
+            }
+        }
+    }
+
+    fn print_dot(&self) {
+        // A peer's parent is the first peer that returned it before it was
+        // queried; peers from the session's own routing table hang off the client
+        let parent_of = |hop: &HopReport| {
+            self.hops
+                .iter()
+                .find(|earlier| {
+                    earlier.sent_after_ms + earlier.latency_ms <= hop.sent_after_ms
+                        && earlier.contacts.iter().any(|contact| contact.id == hop.peer)
+                })
+                .map_or("client", |earlier| earlier.peer.as_str())
+        };
+
+        println!("digraph lookup {{");
+        println!("  rankdir=LR;");
+        println!("  node [shape=box, fontname=monospace];");
+        println!("  \"client\" [shape=ellipse];");
+        println!("  \"target\" [shape=doubleoctagon, label=\"target\\n{}\"];", &self.key[..8]);
+        for hop in &self.hops {
+            let color = match hop.outcome {
+                "value" => "green",
+                "nodes" => "black",
+                _ => "red",
+            };
+            println!(
+                "  \"{}\" [color={}, label=\"{}\\n{}\\nshared bits {}\"];",
+                hop.peer,
+                color,
+                &hop.peer[..8],
+                hop.addr,
+                hop.distance.shared_bits
+            );
+            let parent = parent_of(hop);
+            println!(
+                "  \"{}\" -> \"{}\" [label=\"+{}ms, {}ms\"];",
+                parent, hop.peer, hop.sent_after_ms, hop.latency_ms
+            );
+        }
+        if let Some(holder) = &self.holder {
+            println!("  \"{}\" -> \"target\" [style=bold, color=green];", holder);
+        }
+        println!("}}");
+    }
+}
+
+impl Report for TraceReport {
+    fn print_text(&self) -> Result<()> {
+        match self.render {
+            TraceRender::Timeline => self.print_timeline(),
+            TraceRender::Dot => self.print_dot(),
+        }
+        Ok(())
+    }
+}
+
+/// Liveness of one contact, as checked by `ping-sweep`
+#[derive(Serialize)]
+pub struct ContactCheck {
+    pub id: String,
+    pub addr: SocketAddr,
+    /// "alive", "id_mismatch" or "unreachable"
+    pub status: &'static str,
+    /// ID the contact answered with, if it differs from the advertised one
+    pub answered_id: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Result of `ping-sweep`
+#[derive(Serialize)]
+pub struct PingSweepReport {
+    pub node: String,
+    pub addr: SocketAddr,
+    pub contacts: Vec<ContactCheck>,
+    pub alive: usize,
+}
+
+impl Report for PingSweepReport {
+    fn print_text(&self) -> Result<()> {
+        println!("Contacts returned by {} ({})", self.node, self.addr);
+        for contact in &self.contacts {
+            let detail = match (contact.status, &contact.answered_id, &contact.error) {
+                ("alive", _, _) => format!("alive  {}ms", contact.latency_ms.unwrap_or(0)),
+                ("id_mismatch", Some(id), _) => format!("answered as {}", id),
+                (_, _, Some(e)) => format!("unreachable: {}", e),
+                (status, _, _) => status.to_string(),
+            };
+            println!("  {}  {:<21}  {}", contact.id, contact.addr, detail);
+        }
+        println!("{} of {} contacts alive", self.alive, self.contacts.len());
+        Ok(())
+    }
+}
+
+/// Ask a node for its contacts closest to `target` and ping each of them
+pub async fn ping_sweep(client: &RpcClient, own_id: NodeId, addr: SocketAddr, target: Option<Key>) -> Result<PingSweepReport> {
+    let node = client.identify(own_id, addr).await?;
+    let contacts = client.find_node(own_id, target.unwrap_or(node), addr).await?;
+
+    let checks = contacts.into_iter().map(|(id, contact_addr)| async move {
+        let started = Instant::now();
+        let (status, answered_id, latency_ms, error) = match client.identify(own_id, contact_addr).await {
+            Ok(answered) if answered == id => ("alive", None, Some(started.elapsed().as_millis() as u64), None),
+            Ok(answered) => ("id_mismatch", Some(answered.to_string()), Some(started.elapsed().as_millis() as u64), None),
+            Err(e) => ("unreachable", None, None, Some(e.to_string())),
+        };
+        ContactCheck { id: id.to_string(), addr: contact_addr, status, answered_id, latency_ms, error }
+    });
+    let contacts: Vec<ContactCheck> = join_all(checks).await;
+    let alive = contacts.iter().filter(|contact| contact.status == "alive").count();
+    Ok(PingSweepReport { node: node.to_string(), addr, contacts, alive })
+}
@@ -0,0 +1,188 @@
+//! Watching a key for changes.
+//!
+//! `watch` polls the nodes responsible for a key for the version of its value
+//! they hold and prints every new version as it appears. A debounce interval
+//! holds back a change until the value has stayed the same for that long, so a
+//! burst of updates prints only the last one.
+
+use anyhow::Result;
+use protocol::{ClientSession, KeyWatcher, ValueMode, WatchEvent};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::keys::KeyArg;
+use crate::output::{emit, OutputFormat, Report, ValueFormat};
+
+/// How a key is watched
+pub struct WatchOptions {
+    /// Time between polls of the nodes
+    pub interval: Duration,
+    /// How long a new version has to stay current before it's printed
+    pub debounce: Duration,
+    /// Stop after this many changes
+    pub count: Option<usize>,
+    /// Longest time a poll may take
+    pub timeout: Duration,
+    /// How values are printed
+    pub format: ValueFormat,
+    pub output: OutputFormat,
+}
+
+/// One change of a watched key
+#[derive(Serialize)]
+pub struct WatchReport {
+    pub key: String,
+    pub derived_from: Option<String>,
+    /// "initial", "changed" or "removed"
+    pub event: &'static str,
+    /// Seconds since the watch started
+    pub elapsed_secs: f64,
+    /// SHA-1 hash of the value as hex
+    pub digest: Option<String>,
+    pub bytes: Option<usize>,
+    /// Sequence number of a signed record
+    pub sequence: Option<u64>,
+    /// Public key of the publisher of a signed record as hex
+    pub publisher: Option<String>,
+    pub holder: Option<String>,
+    pub holder_addr: Option<SocketAddr>,
+    /// Number of nodes holding the version when it was seen
+    pub held_by: Option<usize>,
+    /// Number of nodes that answered the poll
+    pub answered: Option<usize>,
+    /// How `value` is encoded
+    pub encoding: Option<&'static str>,
+    pub value: Option<String>,
+    #[serde(skip)]
+    raw: Vec<u8>,
+    #[serde(skip)]
+    format: ValueFormat,
+}
+
+impl WatchReport {
+    fn new(key: &KeyArg, event: WatchEvent, initial: bool, elapsed: Duration, format: ValueFormat) -> Self {
+        let mut report = WatchReport {
+            key: key.hex(),
+            derived_from: key.derived_from.clone(),
+            event: "removed",
+            elapsed_secs: elapsed.as_secs_f64(),
+            digest: None,
+            bytes: None,
+            sequence: None,
+            publisher: None,
+            holder: None,
+            holder_addr: None,
+            held_by: None,
+            answered: None,
+            encoding: None,
+            value: None,
+            raw: Vec::new(),
+            format,
+        };
+        if let WatchEvent::Changed(update) = event {
+            let (encoding, value) = format.encode_json(&update.value);
+            report.event = if initial { "initial" } else { "changed" };
+            report.digest = Some(update.version.digest.to_string());
+            report.bytes = Some(update.version.size);
+            report.sequence = update.version.sequence;
+            report.publisher = update.version.publisher.as_ref().map(hex::encode);
+            report.holder = Some(update.holder.to_string());
+            report.holder_addr = Some(update.holder_addr);
+            report.held_by = Some(update.held_by);
+            report.answered = Some(update.answered);
+            report.encoding = Some(encoding);
+            report.value = Some(value);
+            report.raw = update.value;
+        }
+        report
+    }
+}
+
+impl Report for WatchReport {
+    fn print_text(&self) -> Result<()> {
+        let (Some(digest), Some(bytes)) = (&self.digest, self.bytes) else {
+            println!("[{:.1}s] removed", self.elapsed_secs);
+            return Ok(());
+        };
+        let sequence = self.sequence.map(|sequence| format!(", sequence {}", sequence)).unwrap_or_default();
+        println!("[{:.1}s] {}: version {}{}, {} bytes", self.elapsed_secs, self.event, digest, sequence, bytes);
+        if let Some(publisher) = &self.publisher {
+            println!("  Publisher: {}", publisher);
+        }
+        if let (Some(holder), Some(addr)) = (&self.holder, self.holder_addr) {
+            let held_by = self.held_by.unwrap_or_default();
+            let answered = self.answered.unwrap_or_default();
+            println!("  Held by: {} ({}), {} of {} nodes", holder, addr, held_by, answered);
+        }
+        println!("  Value: {}", String::from_utf8_lossy(&self.format.encode(&self.raw)));
+        Ok(())
+    }
+}
+
+/// Watch a key until `count` changes were printed or Ctrl+C is pressed
+pub async fn run(session: &ClientSession, key: &KeyArg, options: &WatchOptions) -> Result<()> {
+    let started = Instant::now();
+    let mut watcher = KeyWatcher::new(session, key.key, ValueMode::Plain);
+    let mut initial = true;
+    let mut changes = 0;
+    // A change waiting out the debounce interval, and when it was seen
+    let mut pending: Option<(WatchEvent, Instant)> = None;
+
+    if options.output == OutputFormat::Text {
+        println!("Watching {} (Ctrl+C to stop)", key);
+    }
+    // Created once, so a Ctrl+C pressed during a poll isn't missed
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let polled = tokio::select! {
+            polled = tokio::time::timeout(options.timeout, watcher.poll()) => polled,
+            result = &mut ctrl_c => {
+                result?;
+                return Ok(());
+            },
+        };
+        match polled {
+            Ok(Ok(event)) => {
+                // The value found by the first poll isn't a change, so it isn't debounced
+                match event {
+                    Some(event) if initial => {
+                        emit(options.output, &WatchReport::new(key, event, true, started.elapsed(), options.format))?;
+                    },
+                    // A newer change restarts the debounce interval
+                    Some(event) => pending = Some((event, Instant::now())),
+                    None => {},
+                }
+                initial = false;
+            },
+            Ok(Err(error)) => eprintln!("Poll failed: {:#}", error),
+            Err(_) => eprintln!("Poll timed out after {:?}", options.timeout),
+        }
+
+        let mut next_poll = Instant::now() + options.interval;
+        if let Some((_, seen)) = &pending {
+            let due = *seen + options.debounce;
+            if due <= Instant::now() {
+                let (event, _) = pending.take().expect("a change is pending");
+                emit(options.output, &WatchReport::new(key, event, false, started.elapsed(), options.format))?;
+                changes += 1;
+                if options.count.is_some_and(|count| changes >= count) {
+                    return Ok(());
+                }
+            } else {
+                // Poll again when the debounce interval ends
+                next_poll = next_poll.min(due);
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_poll) => {},
+            result = &mut ctrl_c => {
+                result?;
+                return Ok(());
+            },
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Application messages sent directly from one node to another.
+//!
+//! Besides the DHT RPCs, nodes exchange messages on behalf of the applications
+//! embedded in them. Every message names an application protocol, and the
+//! receiving node passes it to the handler registered for that protocol. A
+//! message is either a request, whose handler result is sent back to the
+//! sender, or a one-way message, whose handler result is dropped.
+//!
+//! Requests share the RPC timeout, so handlers have to answer within
+//! [`crate::RPC_TIMEOUT`].
+
+use crate::NodeId;
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Maximum length of an application protocol name in bytes.
+pub const MAX_PROTOCOL_NAME_LEN: usize = 128;
+
+/// Maximum number of application handlers running at once; further messages
+/// wait in the queue of the RPC server, which drops them once it is full.
+pub const MAX_APP_HANDLERS: usize = 64;
+
+/// A message received for an application protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppRequest {
+    /// ID the sending node claims for itself
+    ///
+    /// The ID isn't authenticated, so any node can send messages in the name
+    /// of another; handlers needing to know who they talk to must check that
+    /// in the payload.
+    pub sender: NodeId,
+    /// Address the message was received from
+    pub sender_addr: SocketAddr,
+    /// Protocol the message belongs to
+    pub protocol: String,
+    /// The application payload
+    pub payload: Vec<u8>,
+}
+
+/// Handles the messages of an application protocol.
+///
+/// The returned bytes answer requests; for one-way messages they are dropped.
+pub type AppHandler = Arc<dyn Fn(AppRequest) -> BoxFuture<'static, Result<Vec<u8>>> + Send + Sync>;
+
+/// Checks that a protocol name can be sent.
+pub(crate) fn check_protocol_name(protocol: &str) -> Result<()> {
+    if protocol.is_empty() || protocol.len() > MAX_PROTOCOL_NAME_LEN {
+        bail!(
+            "Protocol name must be 1 to {} bytes long",
+            MAX_PROTOCOL_NAME_LEN
+        );
+    }
+    Ok(())
+}
+
+/// Handlers registered on a node, by protocol.
+#[derive(Default)]
+pub(crate) struct AppHandlers {
+    handlers: RwLock<HashMap<String, AppHandler>>,
+}
+
+impl AppHandlers {
+    /// Registers the handler of a protocol, replacing any previous one.
+    pub(crate) fn register<F, Fut>(&self, protocol: &str, handler: F) -> Result<()>
+    where
+        F: Fn(AppRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>>> + Send + 'static,
+    {
+        check_protocol_name(protocol)?;
+        let handler: AppHandler = Arc::new(move |request| Box::pin(handler(request)));
+        self.handlers.write().insert(protocol.to_string(), handler);
+        Ok(())
+    }
+
+    /// Removes the handler of a protocol, returning whether one was registered.
+    pub(crate) fn unregister(&self, protocol: &str) -> bool {
+        self.handlers.write().remove(protocol).is_some()
+    }
+
+    /// Returns the handler of a protocol.
+    pub(crate) fn get(&self, protocol: &str) -> Option<AppHandler> {
+        self.handlers.read().get(protocol).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registered_handler_is_called() {
+        let handlers = AppHandlers::default();
+        handlers
+            .register(
+                "echo",
+                |request: AppRequest| async move { Ok(request.payload) },
+            )
+            .unwrap();
+
+        let request = AppRequest {
+            sender: NodeId::random(),
+            sender_addr: "127.0.0.1:4000".parse().unwrap(),
+            protocol: "echo".to_string(),
+            payload: b"ping".to_vec(),
+        };
+        let handler = handlers.get("echo").unwrap();
+        assert_eq!(handler(request).await.unwrap(), b"ping");
+
+        assert!(handlers.unregister("echo"));
+        assert!(handlers.get("echo").is_none());
+    }
+
+    #[test]
+    fn test_protocol_names_are_bounded() {
+        let handlers = AppHandlers::default();
+        assert!(handlers.register("", |_| async { Ok(Vec::new()) }).is_err());
+        let long = "x".repeat(MAX_PROTOCOL_NAME_LEN + 1);
+        assert!(handlers
+            .register(&long, |_| async { Ok(Vec::new()) })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_through_rpc() {
+        use crate::node::Node;
+        use crate::storage::Storage;
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let server = Arc::new(
+            Node::with_storage(addr, Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        let client = Arc::new(
+            Node::with_storage(addr, Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        server
+            .register_handler("reverse", |request: AppRequest| async move {
+                let mut payload = request.payload;
+                payload.reverse();
+                Ok(payload)
+            })
+            .unwrap();
+        let running: Vec<_> = [&server, &client]
+            .into_iter()
+            .map(|node| {
+                let node = Arc::clone(node);
+                tokio::spawn(async move { node.run().await })
+            })
+            .collect();
+
+        assert!(client.add_peer(server.id(), server.addr()).await.unwrap());
+        let answer = client
+            .send_request(server.id(), "reverse", b"abc".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(answer, b"cba");
+        assert!(client
+            .send_request(server.id(), "missing", Vec::new())
+            .await
+            .is_err());
+        for task in running {
+            task.abort();
+        }
+    }
+}
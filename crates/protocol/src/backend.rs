@@ -0,0 +1,452 @@
+//! Storage backends holding the raw bytes behind [`crate::storage::Storage`].
+//!
+//! A backend stores opaque values under byte keys, grouped into namespaces. Record
+//! encoding, expiry and quotas are handled by `Storage` on top of it, so backends
+//! only need to provide ordered key-value maps. Three backends are available:
+//! - [`SledBackend`]: the default, persistent backend built on sled
+//! - [`MemoryBackend`]: keeps everything in memory, for tests and mobile devices
+//! - [`LogBackend`](crate::log_backend::LogBackend): an append-only log with an
+//!   in-memory index, suited to write-heavy workloads on simple file systems
+
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::Path;
+use std::time::Duration;
+
+/// Key-value pairs returned by [`StorageBackend::iterate`].
+pub type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Operations a storage backend has to provide.
+pub trait StorageBackend: Send + Sync {
+    /// Stores a value, returning the value previously stored under the key.
+    fn store(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>>;
+
+    /// Retrieves the value stored under a key.
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Removes a key, returning the value that was stored under it.
+    fn remove(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Removes a key only if it still holds `expected`, as one atomic step.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether the key was removed
+    fn remove_if(&self, namespace: &str, key: &[u8], expected: &[u8]) -> Result<bool>;
+
+    /// Returns up to `limit` entries in ascending key order, starting at `start`.
+    fn iterate(&self, namespace: &str, start: Bound<Vec<u8>>, limit: usize) -> Result<Entries>;
+
+    /// Reclaims space held by removed or overwritten entries.
+    fn cleanup(&self) -> Result<()>;
+
+    /// Returns the names of all namespaces holding data.
+    fn namespaces(&self) -> Result<Vec<String>>;
+
+    /// Makes all previous writes durable.
+    fn flush(&self) -> Result<()>;
+
+    /// Returns the number of bytes the backend occupies on disk.
+    fn size_on_disk(&self) -> Result<u64>;
+}
+
+/// When writes are made durable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every write; safest, but limits write throughput
+    EveryWrite,
+    /// Flush at most this long after a write
+    Interval(Duration),
+    /// Only flush when [`StorageBackend::flush`] is called
+    Manual,
+}
+
+/// Which backend a [`StorageConfig`] opens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// [`SledBackend`]
+    Sled,
+    /// [`MemoryBackend`]; the storage path is ignored
+    Memory,
+    /// [`LogBackend`](crate::log_backend::LogBackend)
+    Log,
+}
+
+/// Configuration for opening a storage backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageConfig {
+    /// The backend to use
+    pub backend: BackendKind,
+    /// Size of the backend's read cache in bytes, where applicable
+    pub cache_capacity: u64,
+    /// When writes are made durable
+    pub flush_policy: FlushPolicy,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: BackendKind::Sled,
+            cache_capacity: 1024 * 1024 * 64, // 64MB cache
+            flush_policy: FlushPolicy::Interval(Duration::from_millis(500)),
+        }
+    }
+}
+
+/// Prefix of the sled trees backing namespaces.
+const NAMESPACE_TREE_PREFIX: &str = "ns/";
+
+/// Persistent backend built on the sled embedded database.
+pub struct SledBackend {
+    /// Sled database instance
+    db: sled::Db,
+    /// Trees opened so far, by namespace
+    trees: RwLock<HashMap<String, sled::Tree>>,
+    /// Whether every write is flushed immediately
+    flush_every_write: bool,
+}
+
+impl SledBackend {
+    /// Opens a sled database at the given path.
+    ///
+    /// # Arguments
+    /// * `path` - Path where the database files will be stored
+    /// * `config` - Cache size and flush policy to use
+    pub fn open(path: impl AsRef<Path>, config: &StorageConfig) -> Result<Self> {
+        let flush_every_ms = match config.flush_policy {
+            FlushPolicy::Interval(interval) => Some(interval.as_millis().max(1) as u64),
+            FlushPolicy::EveryWrite | FlushPolicy::Manual => None,
+        };
+
+        let db = sled::Config::new()
+            .path(path)
+            .mode(sled::Mode::HighThroughput) // Optimize for DHT workload
+            .cache_capacity(config.cache_capacity)
+            .flush_every_ms(flush_every_ms)
+            .open()?;
+
+        Ok(Self::from_db(db, config.flush_policy))
+    }
+
+    /// Opens a sled database that is deleted when dropped.
+    pub fn temporary() -> Result<Self> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(Self::from_db(db, FlushPolicy::Manual))
+    }
+
+    fn from_db(db: sled::Db, flush_policy: FlushPolicy) -> Self {
+        SledBackend {
+            db,
+            trees: RwLock::new(HashMap::new()),
+            flush_every_write: flush_policy == FlushPolicy::EveryWrite,
+        }
+    }
+
+    /// Returns the tree backing a namespace, opening it if necessary.
+    fn tree(&self, namespace: &str) -> Result<sled::Tree> {
+        if let Some(tree) = self.trees.read().get(namespace) {
+            return Ok(tree.clone());
+        }
+
+        let tree = self
+            .db
+            .open_tree(format!("{}{}", NAMESPACE_TREE_PREFIX, namespace))?;
+        self.trees
+            .write()
+            .insert(namespace.to_string(), tree.clone());
+        Ok(tree)
+    }
+
+    /// Returns the entries in sled's default tree, where values were kept
+    /// before namespaces were introduced.
+    pub(crate) fn legacy_entries(&self) -> Result<Entries> {
+        let mut entries = Vec::new();
+        for item in self.db.iter() {
+            let (key, value) = item?;
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    /// Removes the entries returned by [`SledBackend::legacy_entries`].
+    pub(crate) fn clear_legacy_entries(&self) -> Result<()> {
+        self.db.clear()?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn after_write(&self, tree: &sled::Tree) -> Result<()> {
+        if self.flush_every_write {
+            tree.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageBackend for SledBackend {
+    fn store(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let tree = self.tree(namespace)?;
+        let old = tree.insert(key, value)?;
+        self.after_write(&tree)?;
+        Ok(old.map(|old| old.to_vec()))
+    }
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.tree(namespace)?.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn remove(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let tree = self.tree(namespace)?;
+        let old = tree.remove(key)?;
+        self.after_write(&tree)?;
+        Ok(old.map(|old| old.to_vec()))
+    }
+
+    fn remove_if(&self, namespace: &str, key: &[u8], expected: &[u8]) -> Result<bool> {
+        let tree = self.tree(namespace)?;
+        let removed = tree
+            .compare_and_swap(key, Some(expected), None::<&[u8]>)?
+            .is_ok();
+        if removed {
+            self.after_write(&tree)?;
+        }
+        Ok(removed)
+    }
+
+    fn iterate(&self, namespace: &str, start: Bound<Vec<u8>>, limit: usize) -> Result<Entries> {
+        let mut entries = Vec::new();
+        for item in self
+            .tree(namespace)?
+            .range::<Vec<u8>, _>((start, Bound::Unbounded))
+            .take(limit)
+        {
+            let (key, value) = item?;
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    fn cleanup(&self) -> Result<()> {
+        // sled reclaims space in the background
+        Ok(())
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let mut namespaces = Vec::new();
+        for name in self.db.tree_names() {
+            let Some(namespace) = String::from_utf8(name.to_vec())
+                .ok()
+                .and_then(|name| name.strip_prefix(NAMESPACE_TREE_PREFIX).map(str::to_string))
+            else {
+                continue;
+            };
+            // Reading a namespace creates its tree, so trees may exist without data
+            if !self.tree(&namespace)?.is_empty() {
+                namespaces.push(namespace);
+            }
+        }
+        Ok(namespaces)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+}
+
+/// Ordered maps of entries, by namespace.
+type NamespaceMaps = HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// Volatile backend keeping all data in memory.
+#[derive(Default)]
+pub struct MemoryBackend {
+    /// Ordered maps of the stored entries, by namespace
+    namespaces: RwLock<NamespaceMaps>,
+}
+
+impl MemoryBackend {
+    /// Creates an empty in-memory backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn store(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .write()
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_vec(), value))
+    }
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .read()
+            .get(namespace)
+            .and_then(|entries| entries.get(key).cloned()))
+    }
+
+    fn remove(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .write()
+            .get_mut(namespace)
+            .and_then(|entries| entries.remove(key)))
+    }
+
+    fn remove_if(&self, namespace: &str, key: &[u8], expected: &[u8]) -> Result<bool> {
+        let mut namespaces = self.namespaces.write();
+        let Some(entries) = namespaces.get_mut(namespace) else {
+            return Ok(false);
+        };
+        if entries.get(key).map(Vec::as_slice) != Some(expected) {
+            return Ok(false);
+        }
+        entries.remove(key);
+        Ok(true)
+    }
+
+    fn iterate(&self, namespace: &str, start: Bound<Vec<u8>>, limit: usize) -> Result<Entries> {
+        let namespaces = self.namespaces.read();
+        let Some(entries) = namespaces.get(namespace) else {
+            return Ok(Vec::new());
+        };
+        Ok(entries
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn cleanup(&self) -> Result<()> {
+        self.namespaces
+            .write()
+            .retain(|_, entries| !entries.is_empty());
+        Ok(())
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>> {
+        Ok(self.namespaces.read().keys().cloned().collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size_on_disk(&self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_backend::LogBackend;
+    use crate::storage::{namespaces, Storage};
+    use crate::Key;
+    use std::sync::Arc;
+
+    /// Checks the behavior every backend has to provide.
+    fn check_contract(backend: Arc<dyn StorageBackend>) {
+        assert_eq!(backend.store("a", b"k1", b"v1".to_vec()).unwrap(), None);
+        assert_eq!(
+            backend.store("a", b"k1", b"v2".to_vec()).unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(backend.get("a", b"k1").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(backend.get("b", b"k1").unwrap(), None);
+
+        backend.store("a", b"k2", b"v3".to_vec()).unwrap();
+        backend.store("a", b"k0", b"v4".to_vec()).unwrap();
+        backend.store("b", b"k1", b"v5".to_vec()).unwrap();
+        let keys = |entries: Entries| -> Vec<Vec<u8>> {
+            entries.into_iter().map(|(key, _)| key).collect()
+        };
+        assert_eq!(
+            keys(backend.iterate("a", Bound::Unbounded, 10).unwrap()),
+            [b"k0".to_vec(), b"k1".to_vec(), b"k2".to_vec()]
+        );
+        assert_eq!(
+            backend
+                .iterate("a", Bound::Excluded(b"k0".to_vec()), 1)
+                .unwrap(),
+            [(b"k1".to_vec(), b"v2".to_vec())]
+        );
+        assert!(backend
+            .iterate("c", Bound::Unbounded, 10)
+            .unwrap()
+            .is_empty());
+        let mut names = backend.namespaces().unwrap();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+
+        assert_eq!(backend.remove("a", b"k1").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(backend.remove("a", b"k1").unwrap(), None);
+        assert_eq!(backend.remove("c", b"k1").unwrap(), None);
+        assert_eq!(backend.get("a", b"k1").unwrap(), None);
+        assert_eq!(backend.get("b", b"k1").unwrap(), Some(b"v5".to_vec()));
+
+        assert!(!backend.remove_if("b", b"k1", b"v6").unwrap());
+        assert!(!backend.remove_if("c", b"k1", b"v5").unwrap());
+        assert_eq!(backend.get("b", b"k1").unwrap(), Some(b"v5".to_vec()));
+        assert!(backend.remove_if("b", b"k1", b"v5").unwrap());
+        assert_eq!(backend.get("b", b"k1").unwrap(), None);
+        backend.store("b", b"k1", b"v5".to_vec()).unwrap();
+
+        backend.cleanup().unwrap();
+        backend.flush().unwrap();
+        assert_eq!(backend.get("a", b"k0").unwrap(), Some(b"v4".to_vec()));
+
+        // Expired records are removed by the storage on top of the backend
+        let storage = Storage::with_backend(Arc::clone(&backend));
+        let (fresh, expired) = (Key::random(), Key::random());
+        storage
+            .store(fresh, b"fresh".to_vec(), Duration::from_secs(3600))
+            .unwrap();
+        storage
+            .store(expired, b"expired".to_vec(), Duration::ZERO)
+            .unwrap();
+        storage.cleanup().unwrap();
+        assert!(backend
+            .get(namespaces::VALUES, fresh.as_bytes())
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            backend.get(namespaces::VALUES, expired.as_bytes()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_memory_backend_contract() {
+        check_contract(Arc::new(MemoryBackend::new()));
+    }
+
+    #[test]
+    fn test_sled_backend_contract() {
+        check_contract(Arc::new(SledBackend::temporary().unwrap()));
+    }
+
+    #[test]
+    fn test_log_backend_contract() {
+        let dir = std::env::temp_dir().join(format!(
+            "backend-contract-{}-{}",
+            std::process::id(),
+            crate::NodeId::random()
+        ));
+        let config = StorageConfig {
+            backend: BackendKind::Log,
+            ..Default::default()
+        };
+        check_contract(Arc::new(LogBackend::open(&dir, &config).unwrap()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
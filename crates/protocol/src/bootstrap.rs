@@ -1,41 +1,71 @@
+use crate::control::{self, ControlServer};
+use crate::discovery::{DiscoveryConfig, LanDiscovery};
 use crate::{Key, Node};
 use anyhow::Result;
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::{thread, time::Duration};
 
 // Constants for storage configuration
-const STORAGE_PERSISTENCE_FILE: &str = "persistencies.cksv";
 const DEFAULT_STORAGE_PATH: &str = "./.compute-dht";
 
+/// Default address of the local control interface
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:8001";
+
+/// Default address the node's RPC socket is bound to, on all interfaces so
+/// that peers on the local network can reach it
+pub const DEFAULT_NODE_ADDR: &str = "0.0.0.0:8000";
+
 /// Bootstrap a new Kademlia DHT node
 ///
 /// This function:
 /// 1. Creates a new node with the specified address and storage path
 /// 2. Stores some initial data in the DHT
-/// 3. Starts the node's RPC server
+/// 3. Starts the local control interface, writing its token to the storage path
+/// 4. Starts LAN discovery, if multicast is available
+/// 5. Starts the node's RPC server
+///
+/// # Arguments
+/// * `addr` - Address to bind the node's RPC socket to, e.g. [`DEFAULT_NODE_ADDR`]
 ///
 /// # Returns
 /// * `Result<()>` - Success or error
-pub async fn bootstrap_node() -> Result<()> {
+pub async fn bootstrap_node(addr: SocketAddr) -> Result<()> {
     // Create the storage directory if it doesn't exist
     let storage_path = PathBuf::from(DEFAULT_STORAGE_PATH);
     std::fs::create_dir_all(&storage_path)?;
 
-    // Parse the socket address
-    let addr: SocketAddr = "127.0.0.1:8000".parse()?;
-
     // Initialize the node
-    let mut node = Node::new(addr, storage_path).await?;
+    let mut node = Node::new(addr, &storage_path).await?;
 
     // Store some initial data
     let key = Key::random();
     let value = b"Hello, DHT!".to_vec();
     node.store(key, value).await?;
 
+    // Start the control interface with a fresh token
+    let token = control::generate_token();
+    control::write_token_file(storage_path.join(control::TOKEN_FILE), &token)?;
+    let control_server = ControlServer::bind(DEFAULT_CONTROL_ADDR.parse()?, token).await?;
+
+    // Find peers on the local network; the node still works without it
+    let discovery = match LanDiscovery::bind(DiscoveryConfig::default()).await {
+        Ok(discovery) => Some(discovery),
+        Err(e) => {
+            log::warn!("LAN discovery unavailable: {}", e);
+            None
+        }
+    };
+    let discovery = async {
+        match &discovery {
+            Some(discovery) => discovery.start(&node).await,
+            None => std::future::pending().await,
+        }
+    };
+
     // Start the RPC server
     println!("Starting Kademlia DHT node on {}", addr);
-    node.run().await?;
+    println!("Control interface on {}", control_server.local_addr()?);
+    tokio::try_join!(node.run(), control_server.start(&node), discovery)?;
 
     Ok(())
 }
@@ -0,0 +1,511 @@
+//! Local control interface for a running node.
+//!
+//! Operators and tools like `dhtclient` use this interface to inspect and manage
+//! a node: its identity, routing table and stored keys, as well as triggering
+//! maintenance tasks such as republishing and bucket refreshes.
+//!
+//! The interface listens on a TCP port, normally on localhost only. Every request
+//! carries a secret token that the node generates at startup and writes to a file
+//! only the node's user can read. Messages are bincode-encoded and prefixed with
+//! their length as a big-endian `u32`.
+
+use crate::storage::{namespaces, RecordKind, StorageStats};
+use crate::{Key, NetworkId, Node, NodeId, RoutingTable};
+use anyhow::{anyhow, bail, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Name of the file in the storage directory that holds the control token.
+pub const TOKEN_FILE: &str = "control.token";
+
+/// Largest control message accepted, to bound memory use on bad input.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Longest a control connection may stay idle before it is closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of control connections served at the same time.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Maximum number of keys returned in one page of a key listing.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Maximum number of records returned in one page of an export, small enough
+/// for a page of the largest values to fit in one control message.
+pub const MAX_EXPORT_PAGE_SIZE: usize = 100;
+
+/// A request sent to the control interface of a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ControlRequest {
+    /// Returns general information about the node
+    Info,
+    /// Returns the contents of the routing table
+    RoutingTable,
+    /// Lists stored keys, one page at a time
+    ListKeys {
+        /// Only keys starting with these bytes are listed
+        prefix: Vec<u8>,
+        /// Last key of the previous page, if any
+        start_after: Option<Key>,
+        /// Maximum number of keys to return, capped at [`MAX_PAGE_SIZE`]
+        limit: usize,
+    },
+    /// Returns statistics about the node's storage
+    StorageStats,
+    /// Returns stored values with their remaining TTL, one page at a time
+    ExportRecords {
+        /// Only keys starting with these bytes are exported
+        prefix: Vec<u8>,
+        /// Last key of the previous page, if any
+        start_after: Option<Key>,
+        /// Maximum number of keys to return, capped at [`MAX_EXPORT_PAGE_SIZE`]
+        limit: usize,
+    },
+    /// Republishes all stored values to the network
+    Republish,
+    /// Refreshes all non-empty k-buckets
+    Refresh,
+}
+
+/// A response from the control interface of a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ControlResponse {
+    /// Response to [`ControlRequest::Info`]
+    Info(NodeStatus),
+    /// Response to [`ControlRequest::RoutingTable`]
+    RoutingTable(Vec<BucketDump>),
+    /// Response to [`ControlRequest::ListKeys`]
+    Keys {
+        /// Keys on this page and the sizes of their values
+        keys: Vec<(Key, usize)>,
+        /// Key to continue the listing after, if more keys may follow
+        next: Option<Key>,
+    },
+    /// Response to [`ControlRequest::StorageStats`]
+    StorageStats(StorageStats),
+    /// Response to [`ControlRequest::ExportRecords`]
+    Records {
+        /// Values on this page
+        records: Vec<ExportedRecord>,
+        /// Key to continue the export after, if more records may follow
+        next: Option<Key>,
+    },
+    /// Response to [`ControlRequest::Republish`] with the number of values republished
+    Republished(usize),
+    /// Response to [`ControlRequest::Refresh`] with the number of buckets refreshed
+    Refreshed(usize),
+    /// The request failed
+    Error(String),
+}
+
+/// General information about a running node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeStatus {
+    /// ID of the node
+    pub id: NodeId,
+    /// Address the node's RPC socket is bound to
+    pub addr: SocketAddr,
+    /// Name of the overlay network the node belongs to
+    pub network: NetworkId,
+    /// Seconds since the node was created
+    pub uptime_secs: u64,
+    /// Whether the node participates as a client-only node
+    pub client_only: bool,
+    /// Number of nodes in the routing table
+    pub known_nodes: usize,
+}
+
+/// Contents of one non-empty k-bucket.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BucketDump {
+    /// Position of the bucket in the routing table, farthest buckets first
+    pub index: usize,
+    /// Number of prefix bits shared by all IDs the bucket covers
+    pub depth: usize,
+    /// Nodes in the bucket, least-recently seen first
+    pub contacts: Vec<ContactDump>,
+}
+
+impl BucketDump {
+    /// Returns the contents of all non-empty k-buckets of a routing table.
+    pub(crate) fn of(routing_table: &RoutingTable) -> Vec<BucketDump> {
+        routing_table
+            .buckets()
+            .map(|(index, bucket)| BucketDump {
+                index,
+                depth: bucket.depth(),
+                contacts: bucket
+                    .nodes()
+                    .map(|info| ContactDump {
+                        id: info.node_id,
+                        addr: info.sock_addr,
+                        last_seen_secs: info.last_seen.elapsed().as_secs(),
+                        successes: info.stats.successes,
+                        timeouts: info.stats.timeouts,
+                        bad_data: info.stats.bad_data,
+                        latency_ms: info.stats.latency.map(|l| l.as_millis() as u64),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// A node in a [`BucketDump`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContactDump {
+    /// ID of the node
+    pub id: NodeId,
+    /// Address the node was last seen from
+    pub addr: SocketAddr,
+    /// Seconds since the node was last seen
+    pub last_seen_secs: u64,
+    /// Number of RPCs the node answered
+    pub successes: u32,
+    /// Number of RPCs the node didn't answer in time
+    pub timeouts: u32,
+    /// Number of responses from the node that failed verification
+    pub bad_data: u32,
+    /// Average response latency in milliseconds, if measured
+    pub latency_ms: Option<u64>,
+}
+
+/// A stored value as returned by [`ControlRequest::ExportRecords`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedRecord {
+    /// Key of the value
+    pub key: Key,
+    /// The stored bytes
+    pub value: Vec<u8>,
+    /// Seconds until the value expires
+    pub ttl_secs: u64,
+}
+
+/// A control request together with the token authenticating it.
+#[derive(Serialize, Deserialize)]
+struct AuthenticatedRequest {
+    token: String,
+    request: ControlRequest,
+}
+
+/// Generates a new random control token.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes the control token to a file readable only by the current user.
+pub fn write_token_file(path: impl AsRef<Path>, token: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // New files are never readable by others, not even before the token is written
+        options.mode(0o600);
+    }
+    let mut file = options.open(path.as_ref())?;
+
+    // An existing file keeps its mode, so it is restricted before the token goes in
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(token.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the control token from a file written by [`write_token_file`].
+pub fn read_token_file(path: impl AsRef<Path>) -> Result<String> {
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Server exposing the control interface of a node.
+pub struct ControlServer {
+    /// Listener accepting control connections
+    listener: TcpListener,
+    /// Token every request has to present
+    token: String,
+}
+
+impl ControlServer {
+    /// Creates a control server bound to the given address.
+    ///
+    /// # Arguments
+    /// * `addr` - Address to listen on, which should be a loopback address
+    /// * `token` - Secret token clients have to present
+    pub async fn bind(addr: SocketAddr, token: String) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(ControlServer { listener, token })
+    }
+
+    /// Returns the address the control server is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves control connections for the given node until an error occurs.
+    ///
+    /// Up to [`MAX_CONNECTIONS`] connections are served concurrently, so a
+    /// client that stays idle doesn't block others. Connections are closed when
+    /// they stay idle for [`IDLE_TIMEOUT`] or present an invalid token.
+    pub async fn start(&self, node: &Node) -> Result<()> {
+        let mut connections = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept(), if connections.len() < MAX_CONNECTIONS => {
+                    let (mut stream, peer) = accepted?;
+                    connections.push(async move {
+                        if let Err(e) = self.serve_connection(&mut stream, node).await {
+                            log::debug!("Control connection from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Some(()) = connections.next() => {}
+            }
+        }
+    }
+
+    async fn serve_connection(&self, stream: &mut TcpStream, node: &Node) -> Result<()> {
+        loop {
+            let frame = tokio::time::timeout(IDLE_TIMEOUT, read_frame(stream))
+                .await
+                .map_err(|_| anyhow!("Idle for {:?}", IDLE_TIMEOUT))??;
+            let Some(AuthenticatedRequest { token, request }) = frame else {
+                return Ok(());
+            };
+
+            // Closing the connection makes every guess at the token cost a new one
+            if !tokens_match(&token, &self.token) {
+                let response = ControlResponse::Error("invalid control token".to_string());
+                write_frame(stream, &response).await?;
+                bail!("Invalid control token");
+            }
+            let response = handle_request(node, request).await;
+            write_frame(stream, &response).await?;
+        }
+    }
+}
+
+/// Executes a single control request against the node.
+async fn handle_request(node: &Node, request: ControlRequest) -> ControlResponse {
+    let result = match request {
+        ControlRequest::Info => node.status().await.map(ControlResponse::Info),
+        ControlRequest::RoutingTable => Ok(ControlResponse::RoutingTable(
+            node.routing_table_dump().await,
+        )),
+        ControlRequest::ListKeys {
+            prefix,
+            start_after,
+            limit,
+        } => {
+            let limit = limit.clamp(1, MAX_PAGE_SIZE);
+            node.storage()
+                .list(&prefix, start_after.as_ref(), limit)
+                .map(|page| ControlResponse::Keys {
+                    keys: page.keys,
+                    next: page.next,
+                })
+        }
+        ControlRequest::StorageStats => node.storage().stats().map(ControlResponse::StorageStats),
+        ControlRequest::ExportRecords {
+            prefix,
+            start_after,
+            limit,
+        } => export_records(node, &prefix, start_after.as_ref(), limit),
+        ControlRequest::Republish => node.republish().await.map(ControlResponse::Republished),
+        ControlRequest::Refresh => node.refresh_buckets().await.map(ControlResponse::Refreshed),
+    };
+
+    result.unwrap_or_else(|e| ControlResponse::Error(e.to_string()))
+}
+
+/// Reads one page of stored values for [`ControlRequest::ExportRecords`].
+fn export_records(
+    node: &Node,
+    prefix: &[u8],
+    start_after: Option<&Key>,
+    limit: usize,
+) -> Result<ControlResponse> {
+    let limit = limit.clamp(1, MAX_EXPORT_PAGE_SIZE);
+    let values = node.storage().namespace(namespaces::VALUES)?;
+    let page = values.list(prefix, start_after, limit)?;
+
+    // Tombstones are listed too but are not values to export
+    let now = SystemTime::now();
+    let mut records = Vec::with_capacity(page.keys.len());
+    for (key, _) in page.keys {
+        if let Some(record) = values.get(&key)? {
+            if record.kind == RecordKind::Value {
+                let ttl = record.expires_at.duration_since(now).unwrap_or_default();
+                records.push(ExportedRecord {
+                    key,
+                    value: record.value,
+                    ttl_secs: ttl.as_secs(),
+                });
+            }
+        }
+    }
+    Ok(ControlResponse::Records {
+        records,
+        next: page.next,
+    })
+}
+
+/// Compares two tokens in time independent of where they differ.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Client for the control interface of a node.
+pub struct ControlClient {
+    /// Connection to the control server
+    stream: TcpStream,
+    /// Token presented with every request
+    token: String,
+}
+
+impl ControlClient {
+    /// Connects to the control interface at the given address.
+    pub async fn connect(addr: SocketAddr, token: String) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(ControlClient { stream, token })
+    }
+
+    /// Sends a request and waits for the response.
+    ///
+    /// [`ControlResponse::Error`] responses are turned into errors.
+    pub async fn request(&mut self, request: ControlRequest) -> Result<ControlResponse> {
+        let request = AuthenticatedRequest {
+            token: self.token.clone(),
+            request,
+        };
+        write_frame(&mut self.stream, &request).await?;
+
+        match read_frame(&mut self.stream).await? {
+            Some(ControlResponse::Error(message)) => Err(anyhow!(message)),
+            Some(response) => Ok(response),
+            None => bail!("Control connection closed by the node"),
+        }
+    }
+}
+
+/// Writes a length-prefixed bincode frame.
+async fn write_frame<T: Serialize>(stream: &mut TcpStream, message: &T) -> Result<()> {
+    let bytes = bincode::serialize(message)?;
+    stream.write_u32(bytes.len() as u32).await?;
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Reads a length-prefixed bincode frame, or `None` if the stream was closed.
+async fn read_frame<T: DeserializeOwned>(stream: &mut TcpStream) -> Result<Option<T>> {
+    let len = match stream.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME_SIZE {
+        bail!("Control message of {} bytes exceeds the limit", len);
+    }
+
+    let mut bytes = vec![0u8; len];
+    stream.read_exact(&mut bytes).await?;
+    Ok(Some(bincode::deserialize(&bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc123", "abc124"));
+        assert!(!tokens_match("abc", "abc123"));
+        assert!(tokens_match("", ""));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_is_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        client.write_u32(MAX_FRAME_SIZE as u32 + 1).await.unwrap();
+        let frame: Result<Option<ControlRequest>> = read_frame(&mut server).await;
+        assert!(frame.is_err());
+    }
+
+    #[test]
+    fn test_token_file_is_private() {
+        let path = std::env::temp_dir().join(format!(
+            "control-token-{}-{}",
+            std::process::id(),
+            NodeId::random()
+        ));
+        std::fs::write(&path, "old token, longer than the new one").unwrap();
+        write_token_file(&path, "secret").unwrap();
+        assert_eq!(read_token_file(&path).unwrap(), "secret");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_token_closes_connection() {
+        let node = Arc::new(
+            Node::with_storage("127.0.0.1:0".parse().unwrap(), Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        let server = Arc::new(
+            ControlServer::bind("127.0.0.1:0".parse().unwrap(), "secret".to_string())
+                .await
+                .unwrap(),
+        );
+        let addr = server.local_addr().unwrap();
+        let serving = tokio::spawn({
+            let (server, node) = (Arc::clone(&server), Arc::clone(&node));
+            async move { server.start(&node).await }
+        });
+
+        let mut client = ControlClient::connect(addr, "guess".to_string())
+            .await
+            .unwrap();
+        let refused = client.request(ControlRequest::Info).await.unwrap_err();
+        assert_eq!(refused.to_string(), "invalid control token");
+        assert!(client.request(ControlRequest::Info).await.is_err());
+
+        let mut client = ControlClient::connect(addr, "secret".to_string())
+            .await
+            .unwrap();
+        let response = client.request(ControlRequest::Info).await.unwrap();
+        assert!(matches!(response, ControlResponse::Info(status) if status.id == node.id()));
+        serving.abort();
+    }
+}
@@ -0,0 +1,222 @@
+//! Discovery of peers on the local network through UDP multicast.
+//!
+//! Every node running the discovery service periodically announces its ID, RPC
+//! address and network to a multicast group, and listens for the announcements
+//! of others. Announced peers of the same network are pinged and added to the
+//! routing table; the first one found is used to join the network, so devices on
+//! one LAN find each other without configured seed nodes.
+//!
+//! Announcements are not trusted: a peer only enters the routing table after it
+//! answered a ping with the announced ID.
+
+use crate::{NetworkId, Node, NodeId};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Multicast group announcements are sent to unless configured otherwise.
+pub const DEFAULT_MULTICAST_GROUP: SocketAddrV4 =
+    SocketAddrV4::new(Ipv4Addr::new(239, 255, 67, 79), 6886);
+
+/// Interval between announcements unless configured otherwise.
+pub const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Marks datagrams as announcements of this protocol and version.
+const ANNOUNCEMENT_MAGIC: [u8; 4] = *b"COD1";
+
+/// Configuration of the LAN discovery service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    /// Multicast group and port to announce to and listen on
+    pub group: SocketAddrV4,
+    /// Interval between announcements
+    pub announce_interval: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            group: DEFAULT_MULTICAST_GROUP,
+            announce_interval: DEFAULT_ANNOUNCE_INTERVAL,
+        }
+    }
+}
+
+/// A node announcing itself on the local network.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Announcement {
+    /// Always [`ANNOUNCEMENT_MAGIC`]
+    magic: [u8; 4],
+    /// Network the node belongs to
+    network: NetworkId,
+    /// ID of the node
+    node_id: NodeId,
+    /// Address the node's RPC socket is bound to
+    addr: SocketAddr,
+}
+
+impl Announcement {
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Decodes an announcement, rejecting datagrams of other protocols.
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let announcement: Announcement = bincode::deserialize(bytes)?;
+        if announcement.magic != ANNOUNCEMENT_MAGIC {
+            return Err(anyhow!("Not a discovery announcement"));
+        }
+        Ok(announcement)
+    }
+
+    /// Returns the RPC address of the announced node as seen from here.
+    ///
+    /// Nodes bound to a wildcard or loopback address announce it as is, which
+    /// means nothing to other hosts, so the source address of the datagram is
+    /// used instead.
+    fn peer_addr(&self, src: SocketAddr) -> SocketAddr {
+        if self.addr.ip().is_unspecified() || self.addr.ip().is_loopback() {
+            SocketAddr::new(src.ip(), self.addr.port())
+        } else {
+            self.addr
+        }
+    }
+}
+
+/// Service announcing a node on the local network and adding the peers it hears.
+pub struct LanDiscovery {
+    /// Socket joined to the multicast group
+    socket: UdpSocket,
+    /// Configuration of the service
+    config: DiscoveryConfig,
+}
+
+impl LanDiscovery {
+    /// Joins the multicast group on all interfaces.
+    ///
+    /// The port is shared, so several nodes on one host, or one process taking
+    /// part in several overlays, can run discovery at the same time.
+    ///
+    /// # Arguments
+    /// * `config` - Multicast group and announcement interval to use
+    pub async fn bind(config: DiscoveryConfig) -> Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.group.port()).into())?;
+        socket.join_multicast_v4(config.group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(LanDiscovery {
+            socket: UdpSocket::from_std(socket.into())?,
+            config,
+        })
+    }
+
+    /// Announces the node and adds discovered peers until an error occurs.
+    pub async fn start(&self, node: &Node) -> Result<()> {
+        let announcement = Announcement {
+            magic: ANNOUNCEMENT_MAGIC,
+            network: node.network().clone(),
+            node_id: node.id(),
+            addr: node.addr(),
+        }
+        .encode()?;
+
+        let mut interval = tokio::time::interval(self.config.announce_interval);
+        let mut buf = vec![0u8; 1024];
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = self.socket.send_to(&announcement, self.config.group).await {
+                        log::debug!("Failed to send discovery announcement: {}", e);
+                    }
+                }
+                received = self.socket.recv_from(&mut buf) => {
+                    let (size, src) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            log::debug!("Failed to receive discovery datagram: {}", e);
+                            continue;
+                        }
+                    };
+                    match Announcement::decode(&buf[..size]) {
+                        Ok(peer) => self.handle_announcement(node, peer, src).await,
+                        Err(e) => log::debug!("Ignoring datagram from {}: {}", src, e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds an announced peer of our network, joining through it if it's the
+    /// first node we know.
+    async fn handle_announcement(&self, node: &Node, peer: Announcement, src: SocketAddr) {
+        if peer.node_id == node.id()
+            || peer.network != *node.network()
+            || node.knows_peer(&peer.node_id).await
+        {
+            return;
+        }
+
+        let addr = peer.peer_addr(src);
+        log::debug!(
+            "Discovered {} at {} on the local network",
+            peer.node_id,
+            addr
+        );
+        let result = match node.status().await {
+            Ok(status) if status.known_nodes == 0 => node.bootstrap(&[addr]).await.map(|_| true),
+            _ => node.add_peer(peer.node_id, addr).await,
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => log::debug!("{} answered with another ID", addr),
+            Err(e) => log::debug!("Failed to add discovered peer {}: {}", addr, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn announcement(addr: SocketAddr) -> Announcement {
+        Announcement {
+            magic: ANNOUNCEMENT_MAGIC,
+            network: NetworkId::default(),
+            node_id: NodeId::random(),
+            addr,
+        }
+    }
+
+    #[test]
+    fn test_announcement_roundtrip() {
+        let original = announcement("192.168.1.7:8000".parse().unwrap());
+        let decoded = Announcement::decode(&original.encode().unwrap()).unwrap();
+        assert_eq!(decoded, original);
+
+        let mut foreign = original.clone();
+        foreign.magic = *b"XXXX";
+        assert!(Announcement::decode(&foreign.encode().unwrap()).is_err());
+        assert!(Announcement::decode(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_address_uses_source() {
+        let src: SocketAddr = "192.168.1.7:6886".parse().unwrap();
+        let wildcard = announcement("0.0.0.0:8000".parse().unwrap());
+        assert_eq!(wildcard.peer_addr(src), "192.168.1.7:8000".parse().unwrap());
+
+        let loopback = announcement("127.0.0.1:8000".parse().unwrap());
+        assert_eq!(loopback.peer_addr(src), "192.168.1.7:8000".parse().unwrap());
+
+        let explicit = announcement("10.0.0.2:8000".parse().unwrap());
+        assert_eq!(explicit.peer_addr(src), explicit.addr);
+    }
+}
@@ -0,0 +1,172 @@
+//! Reed-Solomon erasure coding of stored objects.
+//!
+//! Replicating every chunk of an object on k nodes multiplies its size by k.
+//! Erasure coding instead turns each stripe of the object into `data_shards`
+//! data shards and `parity_shards` parity shards, any `data_shards` of which can
+//! rebuild the stripe. Each shard is stored once, on its own node, so an object
+//! survives the loss of `parity_shards` nodes per stripe while taking only
+//! `(data_shards + parity_shards) / data_shards` times its size.
+
+use crate::K;
+use anyhow::{anyhow, bail, Result};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+
+/// How many shards a stripe is coded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureConfig {
+    /// Number of shards holding the data; this many suffice to rebuild a stripe
+    pub data_shards: u8,
+    /// Number of additional parity shards; this many may be lost
+    pub parity_shards: u8,
+}
+
+impl ErasureConfig {
+    /// Creates a configuration, checking that the shards fit on distinct close nodes.
+    ///
+    /// # Arguments
+    /// * `data_shards` - Number of shards needed to rebuild a stripe, at least 1
+    /// * `parity_shards` - Number of shards that may be lost, at least 1
+    pub fn new(data_shards: u8, parity_shards: u8) -> Result<Self> {
+        if data_shards == 0 || parity_shards == 0 {
+            bail!("Erasure coding needs at least one data and one parity shard");
+        }
+        if data_shards as usize + parity_shards as usize > K {
+            bail!(
+                "{} shards can't be placed on distinct nodes among the {} closest",
+                data_shards as usize + parity_shards as usize,
+                K
+            );
+        }
+        Ok(ErasureConfig {
+            data_shards,
+            parity_shards,
+        })
+    }
+
+    /// Returns the number of shards per stripe.
+    pub fn total_shards(&self) -> usize {
+        self.data_shards as usize + self.parity_shards as usize
+    }
+
+    fn codec(&self) -> Result<ReedSolomon> {
+        ReedSolomon::new(self.data_shards as usize, self.parity_shards as usize)
+            .map_err(|e| anyhow!("Invalid erasure configuration: {}", e))
+    }
+
+    /// Returns the length of every shard of a stripe holding `data_len` bytes.
+    pub fn shard_len(&self, data_len: usize) -> usize {
+        data_len.div_ceil(self.data_shards as usize).max(1)
+    }
+
+    /// Codes a stripe into its data shards followed by its parity shards.
+    ///
+    /// The stripe is padded with zeros to a multiple of the data shard count.
+    pub fn encode(&self, stripe: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let shard_len = self.shard_len(stripe.len());
+        let mut shards: Vec<Vec<u8>> = (0..self.total_shards())
+            .map(|index| {
+                let start = (index * shard_len).min(stripe.len());
+                let end = ((index + 1) * shard_len).min(stripe.len());
+                let mut shard = stripe[start..end].to_vec();
+                shard.resize(shard_len, 0);
+                shard
+            })
+            .collect();
+        self.codec()?
+            .encode(&mut shards)
+            .map_err(|e| anyhow!("Failed to encode stripe: {}", e))?;
+        Ok(shards)
+    }
+
+    /// Restores the missing shards of a stripe in place.
+    ///
+    /// # Arguments
+    /// * `shards` - All shards of the stripe in order, `None` for missing ones;
+    ///   at least `data_shards` must be present
+    pub fn reconstruct(&self, shards: &mut [Option<Vec<u8>>]) -> Result<()> {
+        let present = shards.iter().filter(|shard| shard.is_some()).count();
+        if present < self.data_shards as usize {
+            bail!(
+                "Only {} of the {} shards needed to rebuild the stripe are available",
+                present,
+                self.data_shards
+            );
+        }
+        self.codec()?
+            .reconstruct(shards)
+            .map_err(|e| anyhow!("Failed to reconstruct stripe: {}", e))
+    }
+
+    /// Rebuilds a stripe of `data_len` bytes from any `data_shards` of its shards.
+    pub fn decode(&self, mut shards: Vec<Option<Vec<u8>>>, data_len: usize) -> Result<Vec<u8>> {
+        self.reconstruct(&mut shards)?;
+        let mut stripe: Vec<u8> = shards
+            .into_iter()
+            .take(self.data_shards as usize)
+            .flatten()
+            .flatten()
+            .collect();
+        stripe.truncate(data_len);
+        Ok(stripe)
+    }
+}
+
+impl Default for ErasureConfig {
+    /// Four data shards and two parity shards: 1.5 times the object size,
+    /// surviving the loss of any two nodes holding a stripe.
+    fn default() -> Self {
+        ErasureConfig {
+            data_shards: 4,
+            parity_shards: 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_any_data_shards_rebuild_the_stripe() {
+        let config = ErasureConfig::default();
+        let stripe: Vec<u8> = (0..1001u32).map(|i| (i % 251) as u8).collect();
+        let shards = config.encode(&stripe).unwrap();
+        assert_eq!(shards.len(), 6);
+        assert!(shards.iter().all(|shard| shard.len() == 251));
+
+        // Lose one data and one parity shard
+        let mut partial: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+        partial[1] = None;
+        partial[5] = None;
+        assert_eq!(config.decode(partial, stripe.len()).unwrap(), stripe);
+    }
+
+    #[test]
+    fn test_too_many_lost_shards() {
+        let config = ErasureConfig::default();
+        let shards = config.encode(b"stripe").unwrap();
+        let mut partial: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        partial[0] = None;
+        partial[2] = None;
+        partial[4] = None;
+        assert!(config.decode(partial, 6).is_err());
+    }
+
+    #[test]
+    fn test_reconstruct_restores_parity() {
+        let config = ErasureConfig::new(2, 1).unwrap();
+        let shards = config.encode(b"some stripe data").unwrap();
+        let mut partial: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+        partial[2] = None;
+        config.reconstruct(&mut partial).unwrap();
+        assert_eq!(partial[2].as_ref(), Some(&shards[2]));
+    }
+
+    #[test]
+    fn test_config_limits() {
+        assert!(ErasureConfig::new(0, 2).is_err());
+        assert!(ErasureConfig::new(4, 0).is_err());
+        assert!(ErasureConfig::new(16, 8).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Handing stored keys over to nodes that join our neighborhood.
+//!
+//! Values are stored on the k nodes closest to their key. When a node joins that is
+//! closer to some of our keys than their current holders, it should hold them too,
+//! instead of waiting for the next republish (section 2.5 of the Kademlia paper).
+//! Newly seen contacts are checked against the stored keys, and the resulting
+//! STOREs are queued and sent at a bounded rate, so a joining node or a burst of
+//! new contacts can't flood the network.
+
+use crate::{Distance, Key, NodeId, RoutingTable, K};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Interval at which queued handoffs are sent.
+pub const HANDOFF_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of keys handed off per [`HANDOFF_INTERVAL`].
+pub const HANDOFF_STORES_PER_INTERVAL: usize = 32;
+
+/// Maximum number of stored keys checked against new contacts per
+/// [`HANDOFF_INTERVAL`], bounding how long planning holds the routing table.
+pub const HANDOFF_CHECKS_PER_INTERVAL: usize = 1024;
+
+/// Maximum number of queued handoffs; further ones are left to republishing.
+const MAX_PENDING_HANDOFFS: usize = 4096;
+
+/// A key to store on a contact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Handoff {
+    pub(crate) key: Key,
+    pub(crate) node_id: NodeId,
+    pub(crate) addr: SocketAddr,
+}
+
+/// Handoffs waiting to be sent, oldest first.
+#[derive(Default)]
+pub(crate) struct HandoffQueue {
+    pending: VecDeque<Handoff>,
+    queued: HashSet<(Key, NodeId)>,
+    /// New contacts whose keys are still to be planned, oldest first
+    contacts: VecDeque<(NodeId, SocketAddr)>,
+    /// Index of the next key to check for the first contact
+    next_key: usize,
+}
+
+impl HandoffQueue {
+    /// Queues new contacts for planning, keeping those that may take over keys.
+    pub(crate) fn add_contacts(
+        &mut self,
+        routing_table: &RoutingTable,
+        contacts: Vec<(NodeId, SocketAddr)>,
+    ) {
+        self.contacts.extend(
+            contacts
+                .into_iter()
+                .filter(|(node_id, _)| in_neighborhood(routing_table, node_id)),
+        );
+    }
+
+    /// Returns whether contacts are waiting to be planned.
+    pub(crate) fn is_planning(&self) -> bool {
+        !self.contacts.is_empty()
+    }
+
+    /// Plans the queued contacts, checking at most `budget` keys.
+    ///
+    /// Planning continues where it stopped on the next call, so `keys` should
+    /// stay the same while [`HandoffQueue::is_planning`] is true.
+    ///
+    /// # Returns
+    /// The number of handoffs queued
+    pub(crate) fn plan_contacts(
+        &mut self,
+        routing_table: &RoutingTable,
+        keys: &[Key],
+        mut budget: usize,
+    ) -> usize {
+        let mut planned = 0;
+        while budget > 0 {
+            let Some(&(node_id, addr)) = self.contacts.front() else {
+                break;
+            };
+            let start = self.next_key.min(keys.len());
+            let end = keys.len().min(start + budget);
+            planned += self.plan(routing_table, node_id, addr, &keys[start..end]);
+            budget -= end - start;
+
+            if end == keys.len() {
+                self.contacts.pop_front();
+                self.next_key = 0;
+            } else {
+                self.next_key = end;
+            }
+        }
+        planned
+    }
+
+    /// Queues the keys a new contact should hold.
+    ///
+    /// A key is handed over if the contact is among the k closest known nodes to
+    /// it and we are closer to it than any other contact. The second condition
+    /// lets the holder closest to the key do the handoff while the others, who
+    /// see the same new contact, stay quiet.
+    ///
+    /// # Arguments
+    /// * `routing_table` - The routing table, already containing the contact
+    /// * `node_id` - ID of the new contact
+    /// * `addr` - Address of the new contact
+    /// * `keys` - Keys stored locally
+    pub(crate) fn plan(
+        &mut self,
+        routing_table: &RoutingTable,
+        node_id: NodeId,
+        addr: SocketAddr,
+        keys: &[Key],
+    ) -> usize {
+        let own_id = routing_table.node_id();
+        if !in_neighborhood(routing_table, &node_id) {
+            return 0;
+        }
+
+        let mut planned = 0;
+        for key in keys {
+            if self.pending.len() >= MAX_PENDING_HANDOFFS {
+                log::debug!("Handoff queue full, leaving keys to republishing");
+                break;
+            }
+
+            let closest = routing_table.closest_nodes(key, K);
+            if !closest.iter().any(|(id, _)| *id == node_id) {
+                continue;
+            }
+            let own_distance = Distance::between(&own_id, key);
+            let closer_holder = closest
+                .iter()
+                .any(|(id, _)| *id != node_id && Distance::between(id, key) < own_distance);
+            if closer_holder || !self.queued.insert((*key, node_id)) {
+                continue;
+            }
+
+            self.pending.push_back(Handoff {
+                key: *key,
+                node_id,
+                addr,
+            });
+            planned += 1;
+        }
+        planned
+    }
+
+    /// Takes up to `limit` handoffs to send now.
+    pub(crate) fn next_batch(&mut self, limit: usize) -> Vec<Handoff> {
+        let count = limit.min(self.pending.len());
+        let batch: Vec<Handoff> = self.pending.drain(..count).collect();
+        for handoff in &batch {
+            self.queued.remove(&(handoff.key, handoff.node_id));
+        }
+        batch
+    }
+
+    /// Returns the number of queued handoffs.
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Returns whether a contact is among the k closest known nodes to our own ID.
+///
+/// Only such contacts can become responsible for keys we hold, since we hold
+/// keys close to our own ID.
+fn in_neighborhood(routing_table: &RoutingTable, node_id: &NodeId) -> bool {
+    routing_table
+        .closest_nodes(&routing_table.node_id(), K)
+        .iter()
+        .any(|(id, _)| id == node_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_new_neighbor_receives_keys() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let newcomer = NodeId::random();
+        table.update(newcomer, addr(1));
+
+        // With few contacts, everyone is in the neighborhood and we are the
+        // closest holder of keys right next to our own ID
+        let keys = vec![table.node_id()];
+        let mut queue = HandoffQueue::default();
+        assert_eq!(queue.plan(&table, newcomer, addr(1), &keys), 1);
+        // Planning the same handoff again doesn't queue it twice
+        assert_eq!(queue.plan(&table, newcomer, addr(1), &keys), 0);
+
+        let batch = queue.next_batch(10);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].node_id, newcomer);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_closer_holder_hands_off_instead() {
+        let own_id = NodeId::random();
+        let mut table = RoutingTable::new(own_id);
+        let key = NodeId::random();
+        // A contact sitting exactly on the key is closer to it than we are
+        let holder = key;
+        let newcomer = NodeId::random();
+        table.update(holder, addr(1));
+        table.update(newcomer, addr(2));
+
+        let mut queue = HandoffQueue::default();
+        assert_eq!(queue.plan(&table, newcomer, addr(2), &[key]), 0);
+    }
+
+    #[test]
+    fn test_batches_are_bounded() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let newcomer = NodeId::random();
+        table.update(newcomer, addr(1));
+
+        let own_id = table.node_id();
+        let keys: Vec<Key> = (0..10u8)
+            .map(|i| {
+                let mut bytes = [0u8; 20];
+                bytes.copy_from_slice(own_id.as_bytes());
+                bytes[19] ^= i;
+                NodeId::new(bytes)
+            })
+            .collect();
+
+        let mut queue = HandoffQueue::default();
+        assert_eq!(queue.plan(&table, newcomer, addr(1), &keys), 10);
+        assert_eq!(queue.next_batch(4).len(), 4);
+        assert_eq!(queue.len(), 6);
+    }
+
+    #[test]
+    fn test_planning_is_bounded() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let newcomer = NodeId::random();
+        table.update(newcomer, addr(1));
+        let keys = vec![table.node_id(); 10];
+
+        let mut queue = HandoffQueue::default();
+        queue.add_contacts(&table, vec![(newcomer, addr(1))]);
+        assert_eq!(queue.plan_contacts(&table, &keys, 4), 1);
+        assert!(queue.is_planning());
+        queue.plan_contacts(&table, &keys, 4);
+        queue.plan_contacts(&table, &keys, 4);
+        assert!(!queue.is_planning());
+    }
+}
@@ -0,0 +1,226 @@
+//! Integrity checks for values stored in the DHT.
+//!
+//! Plain values can't be verified: any peer may answer a FIND_VALUE with arbitrary
+//! bytes. Two self-certifying modes close that gap:
+//! - Content-addressed values are stored under the SHA-1 hash of their bytes, so
+//!   they are immutable and anyone can check that a value matches its key.
+//! - Signed records are stored under a key derived from the publisher's public key
+//!   and a record name. They carry a sequence number and an Ed25519 signature, so
+//!   only the publisher can update them and stale versions can't replace newer ones.
+//!
+//! Nodes verify values when they are stored and requesters verify them again when
+//! they are retrieved, since the responding peer is not trusted.
+
+use crate::Key;
+use anyhow::{anyhow, Result};
+use replicrypt::{sha1_digest, verify_signature};
+use serde::{Deserialize, Serialize};
+
+pub use replicrypt::Keypair;
+
+/// Prefix of every signed message, so record signatures can't be confused with
+/// signatures the same key makes for other purposes.
+const SIGNATURE_DOMAIN: &[u8] = b"coreoverlay/signed-record/v1";
+
+/// How the value stored under a key can be verified.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueMode {
+    /// An unverifiable value under an arbitrary key
+    #[default]
+    Plain,
+    /// An immutable value stored under the hash of its bytes
+    ContentAddressed,
+    /// An encoded [`SignedRecord`] stored under [`SignedRecord::key`]
+    Signed,
+}
+
+impl ValueMode {
+    /// Determines the strongest mode a stored value satisfies.
+    ///
+    /// Used where the mode a value was stored with is not known, for example when
+    /// republishing values from storage.
+    pub fn detect(key: &Key, value: &[u8]) -> Self {
+        if verify(key, ValueMode::ContentAddressed, value) {
+            ValueMode::ContentAddressed
+        } else if verify(key, ValueMode::Signed, value) {
+            ValueMode::Signed
+        } else {
+            ValueMode::Plain
+        }
+    }
+}
+
+/// Returns the content-addressed key of a value.
+pub fn content_key(value: &[u8]) -> Key {
+    Key::new(sha1_digest(value))
+}
+
+/// A mutable record signed by its publisher.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedRecord {
+    /// Ed25519 public key of the publisher
+    pub public_key: Vec<u8>,
+    /// Name distinguishing the records of one publisher
+    pub name: Vec<u8>,
+    /// Version of the record; updates must increase it
+    pub sequence: u64,
+    /// The published value
+    pub value: Vec<u8>,
+    /// Signature over the key, sequence number and value
+    pub signature: Vec<u8>,
+}
+
+impl SignedRecord {
+    /// Creates and signs a record.
+    ///
+    /// # Arguments
+    /// * `keypair` - Key pair of the publisher
+    /// * `name` - Name of the record among the publisher's records
+    /// * `sequence` - Version of the record, higher than any previously published
+    /// * `value` - The value to publish
+    pub fn new(keypair: &Keypair, name: &[u8], sequence: u64, value: Vec<u8>) -> Self {
+        let public_key = keypair.public_key().to_vec();
+        let key = Self::key_for(&public_key, name);
+        let signature = keypair
+            .sign(&signed_message(&key, sequence, &value))
+            .to_vec();
+
+        SignedRecord {
+            public_key,
+            name: name.to_vec(),
+            sequence,
+            value,
+            signature,
+        }
+    }
+
+    /// Returns the key a publisher's record with the given name is stored under.
+    pub fn key_for(public_key: &[u8], name: &[u8]) -> Key {
+        let mut bytes = Vec::with_capacity(public_key.len() + name.len());
+        bytes.extend_from_slice(public_key);
+        bytes.extend_from_slice(name);
+        Key::new(sha1_digest(bytes))
+    }
+
+    /// Returns the key this record is stored under.
+    pub fn key(&self) -> Key {
+        Self::key_for(&self.public_key, &self.name)
+    }
+
+    /// Checks the signature of the record.
+    pub fn verify(&self) -> bool {
+        let message = signed_message(&self.key(), self.sequence, &self.value);
+        verify_signature(&self.public_key, &message, &self.signature)
+    }
+
+    /// Encodes the record as stored in the DHT.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Decodes a record stored in the DHT, without verifying it.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|e| anyhow!("Malformed signed record: {}", e))
+    }
+}
+
+/// Builds the message covered by a record signature.
+fn signed_message(key: &Key, sequence: u64, value: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNATURE_DOMAIN.len() + 28 + value.len());
+    message.extend_from_slice(SIGNATURE_DOMAIN);
+    message.extend_from_slice(key.as_bytes());
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message.extend_from_slice(value);
+    message
+}
+
+/// Checks that a value is valid under a key in the given mode.
+///
+/// Plain values always pass.
+pub fn verify(key: &Key, mode: ValueMode, value: &[u8]) -> bool {
+    match mode {
+        ValueMode::Plain => true,
+        ValueMode::ContentAddressed => content_key(value) == *key,
+        ValueMode::Signed => {
+            SignedRecord::decode(value).is_ok_and(|record| record.key() == *key && record.verify())
+        }
+    }
+}
+
+/// Decides whether a verified value may replace the value currently stored under a key.
+///
+/// Verifiable values are protected: a plain value never replaces them, and a
+/// signed record is only replaced by a newer version of itself. Storing the same
+/// bytes again is always allowed, so republishing is idempotent.
+pub fn may_replace(key: &Key, existing: &[u8], mode: ValueMode, value: &[u8]) -> bool {
+    if existing == value {
+        return true;
+    }
+    match ValueMode::detect(key, existing) {
+        ValueMode::Plain => true,
+        // Another value with the same hash would be a SHA-1 collision
+        ValueMode::ContentAddressed => false,
+        ValueMode::Signed => {
+            mode == ValueMode::Signed
+                && matches!(
+                    (SignedRecord::decode(existing), SignedRecord::decode(value)),
+                    (Ok(old), Ok(new)) if new.sequence > old.sequence
+                )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_addressed_verification() {
+        let value = b"immutable".to_vec();
+        let key = content_key(&value);
+        assert!(verify(&key, ValueMode::ContentAddressed, &value));
+        assert!(!verify(&key, ValueMode::ContentAddressed, b"tampered"));
+        assert_eq!(ValueMode::detect(&key, &value), ValueMode::ContentAddressed);
+    }
+
+    #[test]
+    fn test_signed_record_verification() {
+        let keypair = Keypair::generate();
+        let record = SignedRecord::new(&keypair, b"profile", 1, b"v1".to_vec());
+        let key = record.key();
+        let bytes = record.encode().unwrap();
+        assert!(verify(&key, ValueMode::Signed, &bytes));
+        assert_eq!(ValueMode::detect(&key, &bytes), ValueMode::Signed);
+
+        // A record moved to another key or with a changed value fails
+        assert!(!verify(&Key::random(), ValueMode::Signed, &bytes));
+        let mut tampered = record.clone();
+        tampered.value = b"v2".to_vec();
+        assert!(!verify(
+            &key,
+            ValueMode::Signed,
+            &tampered.encode().unwrap()
+        ));
+        assert!(!verify(&key, ValueMode::Signed, b"garbage"));
+    }
+
+    #[test]
+    fn test_signed_record_replacement() {
+        let keypair = Keypair::generate();
+        let v1 = SignedRecord::new(&keypair, b"profile", 1, b"v1".to_vec());
+        let v2 = SignedRecord::new(&keypair, b"profile", 2, b"v2".to_vec());
+        let key = v1.key();
+        let (v1, v2) = (v1.encode().unwrap(), v2.encode().unwrap());
+
+        assert!(may_replace(&key, &v1, ValueMode::Signed, &v2));
+        assert!(!may_replace(&key, &v2, ValueMode::Signed, &v1));
+        assert!(!may_replace(&key, &v1, ValueMode::Plain, b"plain"));
+        assert!(may_replace(&key, &v2, ValueMode::Signed, &v2));
+    }
+
+    #[test]
+    fn test_plain_values_are_replaceable() {
+        let key = Key::random();
+        assert!(may_replace(&key, b"old", ValueMode::Plain, b"new"));
+    }
+}
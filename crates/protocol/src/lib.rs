@@ -10,14 +10,27 @@
 //! - k-bucket routing tables for efficient node lookup
 //! - Iterative node lookups with parallel queries
 //! - Decentralized key-value storage
+//! - Verifiable content-addressed and signed values
 //!
 //! # Architecture
 //! The library is organized into several modules:
+//! - `app`: Application messages sent directly between nodes
+//! - `backend`: Pluggable storage backends (sled, in-memory, append-only log)
+//! - `control`: Local control interface for operating a running node
+//! - `discovery`: Finding peers on the local network through multicast
+//! - `erasure`: Reed-Solomon coding of stored objects
+//! - `handoff`: Handing stored keys over to nodes joining our neighborhood
+//! - `integrity`: Content-addressed and signed values that can be verified
+//! - `nat`: NAT detection and hole punching support
 //! - `node`: Core node implementation and network operations
+//! - `object`: Chunked objects larger than a single value
+//! - `pubsub`: Topic-based publish/subscribe through gossip
 //! - `routing`: k-bucket routing table implementation
 //! - `rpc`: Network communication protocol
+//! - `session`: Non-storing participation for clients and tools
 //! - `storage`: Key-value data storage
-//! - `types`: Core type definitions (NodeId, Key, Distance)
+//! - `types`: Core type definitions (NodeId, Key, Distance, NetworkId)
+//! - `watch`: Following a key for new versions of its value
 //!
 //! # Example
 //! ```rust,no_run
@@ -27,7 +40,7 @@
 //! #[tokio::main]
 //! async fn main() {
 //!     let addr = "127.0.0.1:8000".parse().unwrap();
-//!     let mut node = Node::new(addr).await.unwrap();
+//!     let mut node = Node::new(addr, "./.compute-dht").await.unwrap();
 //!
 //!     // Store a value
 //!     let key = Key::random();
@@ -38,18 +51,44 @@
 
 use std::time::Duration;
 
+pub mod app;
+pub mod backend;
 mod bootstrap;
+pub mod control;
+pub mod discovery;
+pub mod erasure;
+mod handoff;
+pub mod integrity;
+pub mod log_backend;
+mod lookup;
+pub mod nat;
 pub mod node;
+pub mod object;
+pub mod pubsub;
 pub mod routing;
 pub mod rpc;
+pub mod session;
 pub mod storage;
 pub mod types;
-pub use bootstrap::bootstrap_node;
+pub mod watch;
+pub use bootstrap::{bootstrap_node, DEFAULT_CONTROL_ADDR, DEFAULT_NODE_ADDR};
 
+pub use app::AppRequest;
+pub use backend::{BackendKind, FlushPolicy, StorageBackend, StorageConfig};
+pub use control::{ControlClient, ControlServer};
+pub use discovery::{DiscoveryConfig, LanDiscovery};
+pub use erasure::ErasureConfig;
+pub use integrity::{SignedRecord, ValueMode};
+pub use lookup::{HopOutcome, LookupHop};
+pub use nat::Reachability;
 pub use node::Node;
+pub use object::{ObjectManifest, Redundancy};
+pub use pubsub::{topic_key, TopicMessage};
 pub use routing::RoutingTable;
 pub use rpc::{RpcClient, RpcServer};
-pub use types::{Distance, Key, NodeId};
+pub use session::{ClientSession, FoundValue, LookupTrace, StoreOutcome, StoreStatus};
+pub use types::{Distance, Key, NetworkId, NodeId};
+pub use watch::{KeyWatcher, ValueVersion, VersionUpdate, WatchEvent};
 
 /// The size of a k-bucket (k) in the Kademlia routing table.
 ///
@@ -95,3 +134,9 @@ pub const REPUBLISH_INTERVAL: Duration = Duration::from_secs(3600); // 1 hour
 /// - Helps detect failed nodes
 /// - Keeps routing information current
 pub const BUCKET_REFRESH_INTERVAL: Duration = Duration::from_secs(3600); // 1 hour
+
+/// How long to wait for the response to an RPC before giving up.
+///
+/// UDP gives no delivery guarantees, so every request is bounded by this timeout.
+/// Nodes that don't answer in time are treated as unreachable for that request.
+pub const RPC_TIMEOUT: Duration = Duration::from_secs(5);
@@ -0,0 +1,691 @@
+//! Append-only, log-structured storage backend.
+//!
+//! Every write appends an entry to a single log file, and an in-memory index maps
+//! each live key to the position of its latest value in the log. Reads cost one
+//! seek, writes never rewrite existing data, and a crash can at worst lose a torn
+//! entry at the end of the log, which is discarded when the log is replayed.
+//! Overwritten and removed values stay in the log until [`LogBackend`]'s cleanup
+//! compacts it into a new file containing only the live entries.
+//!
+//! Each log entry is laid out as follows (integers are big-endian):
+//!
+//! | entry CRC-32 (u32) | header CRC-32 (u32) | op (u8) | namespace length (u16) | key length (u32) | value length (u32) | namespace | key | value |
+//!
+//! The entry checksum covers everything after it, the header checksum the fixed
+//! fields after it, so lengths are trusted only once they are known to be intact.
+//! Entries are checked when the log is replayed and whenever a value is read, so
+//! a damaged entry in the middle of the log fails loudly instead of being served
+//! or silently cut off.
+
+use crate::backend::{Entries, FlushPolicy, StorageBackend, StorageConfig};
+use anyhow::{anyhow, bail, Result};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// Name of the log file inside the storage directory.
+const LOG_FILE: &str = "data.log";
+
+/// Name of the file a compaction writes before it replaces the log.
+const COMPACT_FILE: &str = "data.log.compact";
+
+/// Size of the fixed part of an entry header.
+const HEADER_SIZE: usize = 19;
+
+/// Size of each of the two checksums at the start of every entry.
+const CHECKSUM_SIZE: usize = 4;
+
+/// Offset of the fields covered by the header checksum.
+const HEADER_FIELDS: usize = 2 * CHECKSUM_SIZE;
+
+/// Entry operation storing a value.
+const OP_STORE: u8 = 0;
+
+/// Entry operation removing a key.
+const OP_REMOVE: u8 = 1;
+
+/// Position of a value inside the log.
+#[derive(Clone, Copy)]
+struct ValuePointer {
+    /// Offset of the entry holding the value
+    offset: u64,
+    /// Size of the entry before the value: header, namespace and key
+    value_start: u64,
+    /// Length of the value
+    len: u32,
+}
+
+/// Mutable state of the log, guarded by a single lock.
+struct LogState {
+    /// The log file, opened for reading and appending
+    file: File,
+    /// Position of the latest value of every live key, by namespace
+    index: HashMap<String, BTreeMap<Vec<u8>, ValuePointer>>,
+    /// Length of the log in bytes
+    len: u64,
+    /// Bytes in the log that belong to overwritten or removed entries
+    garbage: u64,
+    /// Whether entries were appended since the last sync to disk
+    dirty: bool,
+}
+
+/// Storage backend writing all changes to an append-only log.
+pub struct LogBackend {
+    /// Directory containing the log
+    dir: PathBuf,
+    /// When appended entries are synced to disk
+    flush_policy: FlushPolicy,
+    /// The log and its index, shared with the background sync
+    state: Arc<Mutex<LogState>>,
+}
+
+impl LogBackend {
+    /// Opens the log in the given directory, replaying it to rebuild the index.
+    ///
+    /// # Arguments
+    /// * `dir` - Directory holding the log, created if missing
+    /// * `config` - Flush policy to use; the cache capacity is not used
+    pub fn open(dir: impl AsRef<Path>, config: &StorageConfig) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG_FILE))?;
+        let state = Arc::new(Mutex::new(LogState::replay(file)?));
+        if let FlushPolicy::Interval(interval) = config.flush_policy {
+            spawn_sync(Arc::downgrade(&state), interval)?;
+        }
+
+        Ok(LogBackend {
+            dir,
+            flush_policy: config.flush_policy,
+            state,
+        })
+    }
+
+    /// Syncs the log if the flush policy asks for it after every write.
+    fn after_write(&self, state: &mut LogState) -> Result<()> {
+        if self.flush_policy == FlushPolicy::EveryWrite {
+            state.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Drops an indexed entry and appends its removal to the log.
+    fn remove_entry(
+        &self,
+        state: &mut LogState,
+        namespace: &str,
+        key: &[u8],
+        pointer: ValuePointer,
+    ) -> Result<()> {
+        if let Some(entries) = state.index.get_mut(namespace) {
+            entries.remove(key);
+        }
+        state.append(OP_REMOVE, namespace, key, &[])?;
+        // Both the removed entry and the removal itself are dropped by compaction
+        state.garbage += entry_size(namespace, key, pointer.len) + entry_size(namespace, key, 0);
+        self.after_write(state)
+    }
+}
+
+/// Starts a thread syncing appended entries every `interval` until the log is closed.
+fn spawn_sync(state: Weak<Mutex<LogState>>, interval: Duration) -> Result<()> {
+    std::thread::Builder::new()
+        .name("log-sync".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            let mut state = state.lock();
+            if state.dirty {
+                if let Err(e) = state.sync() {
+                    log::warn!("Failed to sync the log: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+impl LogState {
+    /// Rebuilds the index from the log, dropping a torn entry at its end.
+    ///
+    /// # Errors
+    /// Fails if an entry before the end of the log is damaged, since cutting
+    /// the log there would throw away every intact entry after it.
+    fn replay(file: File) -> Result<Self> {
+        let file_len = file.metadata()?.len();
+        let mut reader = std::io::BufReader::new(&file);
+        let mut index: HashMap<String, BTreeMap<Vec<u8>, ValuePointer>> = HashMap::new();
+        let mut offset = 0u64;
+        let mut garbage = 0u64;
+
+        loop {
+            let entry = match read_entry(&mut reader, offset, file_len - offset)? {
+                NextEntry::Entry(entry) => entry,
+                NextEntry::End => break,
+                NextEntry::Torn => {
+                    log::warn!("Discarding {} bytes of torn log data", file_len - offset);
+                    file.set_len(offset)?;
+                    break;
+                }
+            };
+            let entry_end = offset + entry_size(&entry.namespace, &entry.key, entry.value_len);
+
+            let dead_size = |pointer: ValuePointer| pointer.value_start + pointer.len as u64;
+            let entries = index.entry(entry.namespace.clone()).or_default();
+            let replaced = match entry.op {
+                OP_STORE => entries.insert(
+                    entry.key.clone(),
+                    ValuePointer {
+                        offset,
+                        value_start: entry_size(&entry.namespace, &entry.key, 0),
+                        len: entry.value_len,
+                    },
+                ),
+                _ => {
+                    garbage += entry_end - offset;
+                    entries.remove(&entry.key)
+                }
+            };
+            if let Some(old) = replaced {
+                garbage += dead_size(old);
+            }
+            offset = entry_end;
+        }
+        drop(reader);
+
+        Ok(LogState {
+            file,
+            index,
+            len: offset,
+            garbage,
+            dirty: false,
+        })
+    }
+
+    /// Appends an entry and returns the position of its value.
+    fn append(
+        &mut self,
+        op: u8,
+        namespace: &str,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<ValuePointer> {
+        let entry = encode_entry(op, namespace, key, value)?;
+        self.file.write_all(&entry)?;
+        self.dirty = true;
+
+        let pointer = ValuePointer {
+            offset: self.len,
+            value_start: (entry.len() - value.len()) as u64,
+            len: value.len() as u32,
+        };
+        self.len += entry.len() as u64;
+        Ok(pointer)
+    }
+
+    /// Reads the value at the given position, checking its entry's checksum.
+    fn read(&mut self, pointer: ValuePointer) -> Result<Vec<u8>> {
+        let mut entry = vec![0u8; (pointer.value_start + pointer.len as u64) as usize];
+        self.file.seek(SeekFrom::Start(pointer.offset))?;
+        self.file.read_exact(&mut entry)?;
+        if !checksum_matches(&entry) {
+            bail!("Log entry at offset {} is corrupt", pointer.offset);
+        }
+        Ok(entry.split_off(pointer.value_start as usize))
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl StorageBackend for LogBackend {
+    fn store(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock();
+        let old = match state.index.get(namespace).and_then(|e| e.get(key)).copied() {
+            Some(pointer) => {
+                state.garbage += entry_size(namespace, key, pointer.len);
+                Some(state.read(pointer)?)
+            }
+            None => None,
+        };
+
+        let pointer = state.append(OP_STORE, namespace, key, &value)?;
+        state
+            .index
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_vec(), pointer);
+        self.after_write(&mut state)?;
+        Ok(old)
+    }
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock();
+        match state.index.get(namespace).and_then(|e| e.get(key)).copied() {
+            Some(pointer) => Ok(Some(state.read(pointer)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock();
+        let Some(pointer) = state.index.get(namespace).and_then(|e| e.get(key)).copied() else {
+            return Ok(None);
+        };
+
+        let old = state.read(pointer)?;
+        self.remove_entry(&mut state, namespace, key, pointer)?;
+        Ok(Some(old))
+    }
+
+    fn remove_if(&self, namespace: &str, key: &[u8], expected: &[u8]) -> Result<bool> {
+        let mut state = self.state.lock();
+        let Some(pointer) = state.index.get(namespace).and_then(|e| e.get(key)).copied() else {
+            return Ok(false);
+        };
+
+        if pointer.len as usize != expected.len() || state.read(pointer)? != expected {
+            return Ok(false);
+        }
+        self.remove_entry(&mut state, namespace, key, pointer)?;
+        Ok(true)
+    }
+
+    fn iterate(&self, namespace: &str, start: Bound<Vec<u8>>, limit: usize) -> Result<Entries> {
+        let mut state = self.state.lock();
+        let pointers: Vec<(Vec<u8>, ValuePointer)> = match state.index.get(namespace) {
+            Some(entries) => entries
+                .range((start, Bound::Unbounded))
+                .take(limit)
+                .map(|(key, pointer)| (key.clone(), *pointer))
+                .collect(),
+            None => return Ok(Vec::new()),
+        };
+
+        let mut entries = Vec::with_capacity(pointers.len());
+        for (key, pointer) in pointers {
+            let value = state.read(pointer)?;
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    /// Compacts the log into a new file holding only the live entries.
+    fn cleanup(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.garbage == 0 {
+            return Ok(());
+        }
+
+        let compact_path = self.dir.join(COMPACT_FILE);
+        let mut compact = File::create(&compact_path)?;
+        let mut index: HashMap<String, BTreeMap<Vec<u8>, ValuePointer>> = HashMap::new();
+        let mut len = 0u64;
+
+        let live: Vec<(String, Vec<u8>, ValuePointer)> = state
+            .index
+            .iter()
+            .flat_map(|(namespace, entries)| {
+                entries
+                    .iter()
+                    .map(move |(key, pointer)| (namespace.clone(), key.clone(), *pointer))
+            })
+            .collect();
+
+        for (namespace, key, pointer) in live {
+            let value = state.read(pointer)?;
+            let entry = encode_entry(OP_STORE, &namespace, &key, &value)?;
+            compact.write_all(&entry)?;
+            let new_pointer = ValuePointer {
+                offset: len,
+                value_start: pointer.value_start,
+                len: pointer.len,
+            };
+            len += entry.len() as u64;
+            index.entry(namespace).or_default().insert(key, new_pointer);
+        }
+        compact.sync_all()?;
+        drop(compact);
+
+        std::fs::rename(&compact_path, self.dir.join(LOG_FILE))?;
+        state.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(self.dir.join(LOG_FILE))?;
+        state.index = index;
+        state.len = len;
+        state.garbage = 0;
+        state.dirty = false;
+        Ok(())
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>> {
+        Ok(self
+            .state
+            .lock()
+            .index
+            .iter()
+            .filter(|(_, entries)| !entries.is_empty())
+            .map(|(namespace, _)| namespace.clone())
+            .collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.state.lock().sync()
+    }
+
+    fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.state.lock().len)
+    }
+}
+
+/// Encodes a log entry.
+fn encode_entry(op: u8, namespace: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+    if namespace.len() > u16::MAX as usize
+        || key.len() > u32::MAX as usize
+        || value.len() > u32::MAX as usize
+    {
+        bail!("Log entry in namespace '{}' is too large", namespace);
+    }
+
+    let mut entry = Vec::with_capacity(HEADER_SIZE + namespace.len() + key.len() + value.len());
+    entry.extend_from_slice(&[0; HEADER_FIELDS]);
+    entry.push(op);
+    entry.extend_from_slice(&(namespace.len() as u16).to_be_bytes());
+    entry.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    entry.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    entry.extend_from_slice(namespace.as_bytes());
+    entry.extend_from_slice(key);
+    entry.extend_from_slice(value);
+
+    let header_checksum = crc32fast::hash(&entry[HEADER_FIELDS..HEADER_SIZE]);
+    entry[CHECKSUM_SIZE..HEADER_FIELDS].copy_from_slice(&header_checksum.to_be_bytes());
+    let checksum = crc32fast::hash(&entry[CHECKSUM_SIZE..]);
+    entry[..CHECKSUM_SIZE].copy_from_slice(&checksum.to_be_bytes());
+    Ok(entry)
+}
+
+/// Returns whether the header checksum of an entry matches its fixed fields.
+fn header_checksum_matches(header: &[u8; HEADER_SIZE]) -> bool {
+    let checksum = &header[CHECKSUM_SIZE..HEADER_FIELDS];
+    checksum == crc32fast::hash(&header[HEADER_FIELDS..]).to_be_bytes()
+}
+
+/// Returns whether the checksum at the start of an encoded entry matches its contents.
+fn checksum_matches(entry: &[u8]) -> bool {
+    let Some((checksum, rest)) = entry.split_first_chunk::<CHECKSUM_SIZE>() else {
+        return false;
+    };
+    u32::from_be_bytes(*checksum) == crc32fast::hash(rest)
+}
+
+/// An intact entry as read back from the log, without its value.
+struct LogEntry {
+    op: u8,
+    namespace: String,
+    key: Vec<u8>,
+    value_len: u32,
+}
+
+/// Result of reading the next entry of the log.
+enum NextEntry {
+    /// An intact entry
+    Entry(LogEntry),
+    /// The log ends before the entry
+    End,
+    /// The entry at the end of the log was only partly written
+    Torn,
+}
+
+/// Reads and checks the next entry of the log.
+///
+/// # Arguments
+/// * `reader` - The log, positioned at the start of the entry
+/// * `offset` - Offset of the entry, for error messages
+/// * `remaining` - Number of bytes from the entry to the end of the log
+fn read_entry(reader: &mut impl Read, offset: u64, remaining: u64) -> Result<NextEntry> {
+    if remaining == 0 {
+        return Ok(NextEntry::End);
+    }
+    let mut header = [0u8; HEADER_SIZE];
+    if remaining < HEADER_SIZE as u64 || !read_fully(reader, &mut header)? {
+        return Ok(NextEntry::Torn);
+    }
+
+    // A complete header that doesn't match its checksum can't be told apart
+    // from damage in the middle of the log, since its lengths can't be trusted
+    if !header_checksum_matches(&header) {
+        bail!("Log entry header at offset {} is corrupt", offset);
+    }
+    let op = header[8];
+    let namespace_len = u16::from_be_bytes([header[9], header[10]]) as usize;
+    let key_len = u32::from_be_bytes([header[11], header[12], header[13], header[14]]) as usize;
+    let value_len = u32::from_be_bytes([header[15], header[16], header[17], header[18]]);
+
+    // The lengths are intact, so an entry reaching past the end was only partly written
+    let size = (HEADER_SIZE + namespace_len + key_len) as u64 + value_len as u64;
+    if size > remaining {
+        return Ok(NextEntry::Torn);
+    }
+    let mut entry = header.to_vec();
+    entry.resize(size as usize, 0);
+    if !read_fully(reader, &mut entry[HEADER_SIZE..])? {
+        return Ok(NextEntry::Torn);
+    }
+
+    if !checksum_matches(&entry) {
+        // Nothing follows a damaged last entry, so it is treated like a torn write
+        if size == remaining {
+            return Ok(NextEntry::Torn);
+        }
+        bail!("Log entry at offset {} is corrupt", offset);
+    }
+    if op > OP_REMOVE {
+        bail!(
+            "Log entry at offset {} has unknown operation {}",
+            offset,
+            op
+        );
+    }
+
+    let key_start = HEADER_SIZE + namespace_len;
+    let namespace = String::from_utf8(entry[HEADER_SIZE..key_start].to_vec())
+        .map_err(|_| anyhow!("Log entry at offset {} has an invalid namespace", offset))?;
+    Ok(NextEntry::Entry(LogEntry {
+        op,
+        namespace,
+        key: entry[key_start..key_start + key_len].to_vec(),
+        value_len,
+    }))
+}
+
+/// Fills `buf` completely, returning `false` if the input ends first.
+fn read_fully(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the number of bytes an entry occupies in the log.
+fn entry_size(namespace: &str, key: &[u8], value_len: u32) -> u64 {
+    (HEADER_SIZE + namespace.len() + key.len()) as u64 + value_len as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendKind;
+
+    fn config() -> StorageConfig {
+        StorageConfig {
+            backend: BackendKind::Log,
+            flush_policy: FlushPolicy::Manual,
+            ..Default::default()
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "log-backend-{}-{}-{}",
+            name,
+            std::process::id(),
+            crate::NodeId::random()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_replay_restores_entries() {
+        let dir = temp_dir("replay");
+        {
+            let backend = LogBackend::open(&dir, &config()).unwrap();
+            backend.store("values", b"a", b"1".to_vec()).unwrap();
+            backend.store("values", b"b", b"2".to_vec()).unwrap();
+            backend.store("values", b"a", b"3".to_vec()).unwrap();
+            backend.remove("values", b"b").unwrap();
+            backend.flush().unwrap();
+        }
+
+        let backend = LogBackend::open(&dir, &config()).unwrap();
+        assert_eq!(backend.get("values", b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(backend.get("values", b"b").unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_entry_is_discarded() {
+        let dir = temp_dir("torn");
+        {
+            let backend = LogBackend::open(&dir, &config()).unwrap();
+            backend.store("values", b"a", b"1".to_vec()).unwrap();
+            backend.store("values", b"b", b"2".to_vec()).unwrap();
+        }
+        let log = dir.join(LOG_FILE);
+        let len = std::fs::metadata(&log).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let backend = LogBackend::open(&dir, &config()).unwrap();
+        assert_eq!(backend.get("values", b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(backend.get("values", b"b").unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_entry_fails_open() {
+        let dir = temp_dir("corrupt");
+        {
+            let backend = LogBackend::open(&dir, &config()).unwrap();
+            backend.store("values", b"a", b"1".to_vec()).unwrap();
+            backend.store("values", b"b", b"2".to_vec()).unwrap();
+        }
+        let log = dir.join(LOG_FILE);
+        let mut bytes = std::fs::read(&log).unwrap();
+        let len = bytes.len();
+        // The value of the first entry
+        bytes[entry_size("values", b"a", 0) as usize] ^= 0xff;
+        std::fs::write(&log, &bytes).unwrap();
+
+        assert!(LogBackend::open(&dir, &config()).is_err());
+        assert_eq!(std::fs::metadata(&log).unwrap().len(), len as u64);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_length_fails_open() {
+        let dir = temp_dir("corrupt-length");
+        {
+            let backend = LogBackend::open(&dir, &config()).unwrap();
+            backend.store("values", b"a", b"1".to_vec()).unwrap();
+            backend.store("values", b"b", b"2".to_vec()).unwrap();
+        }
+        let log = dir.join(LOG_FILE);
+        let mut bytes = std::fs::read(&log).unwrap();
+        let len = bytes.len();
+        // The value length of the first entry, now reaching past the end of the log
+        bytes[15..19].copy_from_slice(&u32::MAX.to_be_bytes());
+        std::fs::write(&log, &bytes).unwrap();
+
+        assert!(LogBackend::open(&dir, &config()).is_err());
+        assert_eq!(std::fs::read(&log).unwrap().len(), len);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_oversized_length_at_end_is_torn() {
+        let dir = temp_dir("oversized");
+        {
+            let backend = LogBackend::open(&dir, &config()).unwrap();
+            backend.store("values", b"a", b"1".to_vec()).unwrap();
+        }
+        let log = dir.join(LOG_FILE);
+        let mut header = encode_entry(OP_STORE, "values", b"b", b"").unwrap();
+        header[11..15].copy_from_slice(&u32::MAX.to_be_bytes());
+        let checksum = crc32fast::hash(&header[HEADER_FIELDS..HEADER_SIZE]);
+        header[CHECKSUM_SIZE..HEADER_FIELDS].copy_from_slice(&checksum.to_be_bytes());
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(&header[..HEADER_SIZE]).unwrap();
+        drop(file);
+
+        let backend = LogBackend::open(&dir, &config()).unwrap();
+        assert_eq!(backend.get("values", b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(
+            std::fs::metadata(&log).unwrap().len(),
+            entry_size("values", b"a", 1)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_interval_policy_syncs_in_background() {
+        let dir = temp_dir("interval");
+        let config = StorageConfig {
+            flush_policy: FlushPolicy::Interval(Duration::from_millis(10)),
+            ..config()
+        };
+        let backend = LogBackend::open(&dir, &config).unwrap();
+        backend.store("values", b"a", b"1".to_vec()).unwrap();
+        assert!(backend.state.lock().dirty);
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!backend.state.lock().dirty);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cleanup_compacts_log() {
+        let dir = temp_dir("compact");
+        let backend = LogBackend::open(&dir, &config()).unwrap();
+        for i in 0..10u8 {
+            backend.store("values", b"a", vec![i; 100]).unwrap();
+        }
+        let before = backend.size_on_disk().unwrap();
+
+        backend.cleanup().unwrap();
+
+        assert!(backend.size_on_disk().unwrap() < before);
+        assert_eq!(backend.get("values", b"a").unwrap(), Some(vec![9; 100]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
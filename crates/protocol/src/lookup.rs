@@ -0,0 +1,512 @@
+//! Iterative Kademlia lookups.
+//!
+//! A lookup keeps [`ALPHA`] requests in flight and starts a new one as soon as any
+//! of them finishes, instead of waiting for a whole round. Peers that take longer
+//! than expected are marked as stalled: they stop counting against `ALPHA`, so the
+//! lookup widens to further peers while still accepting their late answers. The
+//! lookup is done once the k closest peers that didn't fail have all answered.
+//!
+//! [`LookupState`] does the bookkeeping; [`LookupContext`] drives lookups for
+//! nodes and for client sessions alike.
+
+use crate::integrity::{self, ValueMode};
+use crate::rpc::RpcClient;
+use crate::types::Distance;
+use crate::{Key, NodeId, RoutingTable, ALPHA, K};
+use anyhow::{bail, Result};
+use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// How long a peer without latency history may take before it is considered stalled.
+pub(crate) const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Lower bound for the stall timeout of peers with latency history.
+const MIN_STALL_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Multiple of a peer's average latency after which it is considered stalled.
+const STALL_LATENCY_FACTOR: u32 = 3;
+
+/// Returns how long to wait for a peer before treating it as stalled.
+///
+/// # Arguments
+/// * `latency` - The peer's average latency, if it answered before
+pub(crate) fn stall_timeout(latency: Option<Duration>) -> Duration {
+    match latency {
+        Some(latency) => {
+            (latency * STALL_LATENCY_FACTOR).clamp(MIN_STALL_TIMEOUT, crate::RPC_TIMEOUT)
+        }
+        None => DEFAULT_STALL_TIMEOUT,
+    }
+}
+
+/// Progress of a single peer in a lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PeerState {
+    /// Not queried yet
+    Waiting,
+    /// Queried; stalled once the deadline passes
+    InFlight { stalls_at: Instant },
+    /// Queried, slower than expected, but may still answer
+    Stalled,
+    /// Answered the query
+    Responded,
+    /// Didn't answer or answered with invalid data
+    Failed,
+}
+
+/// A peer known to a lookup.
+struct Peer {
+    id: NodeId,
+    addr: SocketAddr,
+    state: PeerState,
+}
+
+/// State of an iterative lookup for a target key.
+pub(crate) struct LookupState {
+    target: Key,
+    own_id: NodeId,
+    /// All peers learned so far, sorted by distance to the target
+    peers: Vec<Peer>,
+}
+
+impl LookupState {
+    /// Starts a lookup from the given peers.
+    pub(crate) fn new(target: Key, own_id: NodeId, initial: Vec<(NodeId, SocketAddr)>) -> Self {
+        let mut state = LookupState {
+            target,
+            own_id,
+            peers: Vec::new(),
+        };
+        state.add_peers(initial);
+        state
+    }
+
+    /// Adds peers learned from a response, ignoring known ones and ourselves.
+    pub(crate) fn add_peers(&mut self, nodes: Vec<(NodeId, SocketAddr)>) {
+        for (id, addr) in nodes {
+            if id == self.own_id || self.peers.iter().any(|peer| peer.id == id) {
+                continue;
+            }
+            let distance = Distance::between(&id, &self.target);
+            let pos = self
+                .peers
+                .partition_point(|peer| Distance::between(&peer.id, &self.target) < distance);
+            self.peers.insert(
+                pos,
+                Peer {
+                    id,
+                    addr,
+                    state: PeerState::Waiting,
+                },
+            );
+        }
+    }
+
+    /// Picks the closest peer not queried yet, if fewer than `ALPHA` queries are
+    /// active, and marks it as in flight.
+    ///
+    /// # Arguments
+    /// * `stall_timeout` - Returns how long the given peer may take before it stalls
+    pub(crate) fn next_query(
+        &mut self,
+        stall_timeout: impl Fn(&NodeId) -> Duration,
+    ) -> Option<(NodeId, SocketAddr)> {
+        let active = self
+            .peers
+            .iter()
+            .filter(|peer| matches!(peer.state, PeerState::InFlight { .. }))
+            .count();
+        if active >= ALPHA || self.is_finished() {
+            return None;
+        }
+
+        let peer = self
+            .peers
+            .iter_mut()
+            .find(|peer| peer.state == PeerState::Waiting)?;
+        peer.state = PeerState::InFlight {
+            stalls_at: Instant::now() + stall_timeout(&peer.id),
+        };
+        Some((peer.id, peer.addr))
+    }
+
+    /// Returns the earliest time an active query stalls, if any is active.
+    pub(crate) fn next_stall(&self) -> Option<Instant> {
+        self.peers
+            .iter()
+            .filter_map(|peer| match peer.state {
+                PeerState::InFlight { stalls_at } => Some(stalls_at),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Marks the active queries whose deadline has passed as stalled.
+    pub(crate) fn mark_stalled(&mut self, now: Instant) {
+        for peer in &mut self.peers {
+            if let PeerState::InFlight { stalls_at } = peer.state {
+                if stalls_at <= now {
+                    log::debug!("Lookup peer {} stalled", peer.id);
+                    peer.state = PeerState::Stalled;
+                }
+            }
+        }
+    }
+
+    /// Records that a peer answered.
+    pub(crate) fn responded(&mut self, id: &NodeId) {
+        self.set_state(id, PeerState::Responded);
+    }
+
+    /// Records that a peer didn't answer or answered with invalid data.
+    pub(crate) fn failed(&mut self, id: &NodeId) {
+        self.set_state(id, PeerState::Failed);
+    }
+
+    fn set_state(&mut self, id: &NodeId, state: PeerState) {
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.id == *id) {
+            peer.state = state;
+        }
+    }
+
+    /// Returns whether the k closest peers that didn't fail have all answered.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.peers
+            .iter()
+            .filter(|peer| peer.state != PeerState::Failed)
+            .take(K)
+            .all(|peer| peer.state == PeerState::Responded)
+    }
+
+    /// Returns the k closest peers that answered, closest first.
+    pub(crate) fn closest_responded(&self) -> Vec<(NodeId, SocketAddr)> {
+        self.peers
+            .iter()
+            .filter(|peer| peer.state == PeerState::Responded)
+            .take(K)
+            .map(|peer| (peer.id, peer.addr))
+            .collect()
+    }
+}
+
+/// Response of a peer to a lookup query: a value, or nodes closer to the target
+type PeerResponse = Result<Result<Vec<u8>, Vec<(NodeId, SocketAddr)>>>;
+
+/// What an iterative lookup asks peers for.
+#[derive(Clone, Copy)]
+pub(crate) enum LookupQuery {
+    /// FIND_NODE: only the closest nodes are wanted
+    Nodes,
+    /// FIND_VALUE: a value verifying in the given mode is wanted
+    Value(ValueMode),
+}
+
+impl LookupQuery {
+    /// Returns whether a value returned by a peer answers this query.
+    fn accepts(&self, key: &Key, value: &[u8]) -> bool {
+        match self {
+            LookupQuery::Nodes => false,
+            LookupQuery::Value(mode) => integrity::verify(key, *mode, value),
+        }
+    }
+
+    /// Returns the version of an accepted value if the lookup has to compare
+    /// versions, or `None` if any accepted value will do.
+    ///
+    /// Signed records are mutable, so a peer may hold an outdated version and
+    /// only the highest sequence number among the answers is current.
+    fn version_of(&self, value: &[u8]) -> Option<u64> {
+        match self {
+            LookupQuery::Value(ValueMode::Signed) => integrity::SignedRecord::decode(value)
+                .ok()
+                .map(|record| record.sequence),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of an iterative lookup.
+pub(crate) enum LookupResult {
+    /// The k closest nodes that answered
+    Nodes(Vec<(NodeId, SocketAddr)>),
+    /// A verified value and the node that returned it
+    Value { value: Vec<u8>, holder: NodeId },
+}
+
+/// What a queried peer answered during a lookup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HopOutcome {
+    /// The peer returned these nodes closer to the target
+    Nodes(Vec<(NodeId, SocketAddr)>),
+    /// The peer returned a value that verified
+    Value,
+    /// The peer returned a value that failed verification
+    InvalidValue,
+    /// The query failed, usually because it timed out
+    Failed(String),
+}
+
+/// One query of a traced lookup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupHop {
+    /// The queried peer
+    pub peer: NodeId,
+    /// Address the query was sent to
+    pub addr: SocketAddr,
+    /// Time from the start of the lookup until the query was sent
+    pub sent_after: Duration,
+    /// Time the peer took to answer or the query took to fail
+    pub latency: Duration,
+    /// What the peer answered
+    pub outcome: HopOutcome,
+}
+
+/// What a lookup works with: a client to send queries and a routing table to
+/// start from and to record the peers' behavior in.
+pub(crate) struct LookupContext<'a> {
+    pub(crate) client: &'a RpcClient,
+    pub(crate) routing_table: &'a Mutex<RoutingTable>,
+    pub(crate) own_id: NodeId,
+}
+
+impl LookupContext<'_> {
+    /// Runs an iterative lookup for a key.
+    ///
+    /// Value lookups stop at the first value that verifies, except for signed
+    /// records: those are collected from every peer that answers and the one
+    /// with the highest sequence number is returned. Peers returning values
+    /// that fail verification are banned from the routing table.
+    pub(crate) async fn run(&self, key: Key, query: LookupQuery) -> Result<LookupResult> {
+        self.run_traced(key, query, None).await
+    }
+
+    /// Runs an iterative lookup like [`LookupContext::run`], recording every
+    /// answered or failed query in `trace`, in the order they finished.
+    pub(crate) async fn run_traced(
+        &self,
+        key: Key,
+        query: LookupQuery,
+        mut trace: Option<&mut Vec<LookupHop>>,
+    ) -> Result<LookupResult> {
+        let started = Instant::now();
+        let initial = self.routing_table.lock().await.closest_nodes(&key, K);
+        let mut state = LookupState::new(key, self.own_id, initial);
+        let mut in_flight = FuturesUnordered::new();
+        // Newest signed record so far: its sequence number, value and holder
+        let mut newest: Option<(u64, Vec<u8>, NodeId)> = None;
+
+        loop {
+            {
+                let routing_table = self.routing_table.lock().await;
+                let stall_timeout = |node: &NodeId| {
+                    stall_timeout(routing_table.stats(node).and_then(|s| s.latency))
+                };
+                while let Some((node_id, addr)) = state.next_query(stall_timeout) {
+                    in_flight.push(self.query_peer(node_id, addr, key, query));
+                }
+            }
+            if in_flight.is_empty() || state.is_finished() {
+                break;
+            }
+
+            let stall_at = state.next_stall();
+            let stall = async move {
+                match stall_at {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                Some((node_id, addr, latency, response)) = in_flight.next() => {
+                    if let Some(trace) = trace.as_deref_mut() {
+                        trace.push(LookupHop {
+                            peer: node_id,
+                            addr,
+                            sent_after: (Instant::now() - latency).saturating_duration_since(started),
+                            latency,
+                            outcome: match &response {
+                                Ok(Ok(value)) if query.accepts(&key, value) => HopOutcome::Value,
+                                Ok(Ok(_)) => HopOutcome::InvalidValue,
+                                Ok(Err(nodes)) => HopOutcome::Nodes(nodes.clone()),
+                                Err(e) => HopOutcome::Failed(e.to_string()),
+                            },
+                        });
+                    }
+                    let mut routing_table = self.routing_table.lock().await;
+                    match response {
+                        Ok(Ok(value)) if query.accepts(&key, &value) => {
+                            routing_table.record_success(node_id, addr, latency);
+                            let Some(sequence) = query.version_of(&value) else {
+                                return Ok(LookupResult::Value {
+                                    value,
+                                    holder: node_id,
+                                });
+                            };
+                            state.responded(&node_id);
+                            if newest.as_ref().is_none_or(|(newest, _, _)| sequence > *newest) {
+                                newest = Some((sequence, value, node_id));
+                            }
+                        }
+                        Ok(Ok(_)) => {
+                            log::warn!(
+                                "Node {} ({}) returned an invalid value for {}",
+                                node_id,
+                                addr,
+                                key
+                            );
+                            routing_table.record_bad_data(&node_id);
+                            state.failed(&node_id);
+                        }
+                        Ok(Err(nodes)) => {
+                            routing_table.record_success(node_id, addr, latency);
+                            state.responded(&node_id);
+                            state.add_peers(nodes);
+                        }
+                        Err(e) => {
+                            log::debug!("Lookup query to {} failed: {}", addr, e);
+                            routing_table.record_timeout(&node_id);
+                            state.failed(&node_id);
+                        }
+                    }
+                }
+                _ = stall => state.mark_stalled(Instant::now()),
+            }
+        }
+
+        Ok(match newest {
+            Some((_, value, holder)) => LookupResult::Value { value, holder },
+            None => LookupResult::Nodes(state.closest_responded()),
+        })
+    }
+
+    /// Sends one lookup query to a peer, measuring how long it takes to answer.
+    async fn query_peer(
+        &self,
+        node_id: NodeId,
+        addr: SocketAddr,
+        key: Key,
+        query: LookupQuery,
+    ) -> (NodeId, SocketAddr, Duration, PeerResponse) {
+        let started = Instant::now();
+        let response = match query {
+            LookupQuery::Nodes => self.client.find_node(self.own_id, key, addr).await.map(Err),
+            LookupQuery::Value(_) => self.client.find_value(self.own_id, key, addr).await,
+        };
+        (node_id, addr, started.elapsed(), response)
+    }
+
+    /// Joins the network through the given seed nodes.
+    ///
+    /// Each seed is pinged to learn its ID and added to the routing table. A
+    /// lookup of our own ID then fills the routing table with the nodes
+    /// closest to us.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of seeds that answered
+    pub(crate) async fn contact_seeds(&self, seeds: &[SocketAddr]) -> Result<usize> {
+        let probes = seeds.iter().map(|seed| async move {
+            let started = Instant::now();
+            let result = self.client.identify(self.own_id, *seed).await;
+            (*seed, started.elapsed(), result)
+        });
+
+        let mut reached = 0;
+        for (seed, latency, result) in join_all(probes).await {
+            match result {
+                Ok(id) if id != self.own_id => {
+                    self.routing_table
+                        .lock()
+                        .await
+                        .record_success(id, seed, latency);
+                    reached += 1;
+                }
+                Ok(_) => {}
+                Err(e) => log::debug!("Bootstrap node {} unreachable: {}", seed, e),
+            }
+        }
+        if reached == 0 {
+            bail!("None of the {} bootstrap nodes answered", seeds.len());
+        }
+
+        self.run(self.own_id, LookupQuery::Nodes).await?;
+        Ok(reached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn peers(count: u16) -> Vec<(NodeId, SocketAddr)> {
+        (0..count).map(|i| (NodeId::random(), addr(i))).collect()
+    }
+
+    #[test]
+    fn test_keeps_alpha_queries_in_flight() {
+        let mut state = LookupState::new(Key::random(), NodeId::random(), peers(10));
+        let queried: Vec<_> =
+            std::iter::from_fn(|| state.next_query(|_| DEFAULT_STALL_TIMEOUT)).collect();
+        assert_eq!(queried.len(), ALPHA);
+
+        // One answer frees one slot
+        state.responded(&queried[0].0);
+        assert!(state.next_query(|_| DEFAULT_STALL_TIMEOUT).is_some());
+        assert!(state.next_query(|_| DEFAULT_STALL_TIMEOUT).is_none());
+    }
+
+    #[test]
+    fn test_stalled_queries_widen_the_lookup() {
+        let mut state = LookupState::new(Key::random(), NodeId::random(), peers(10));
+        while state.next_query(|_| Duration::ZERO).is_some() {}
+
+        state.mark_stalled(Instant::now());
+        assert_eq!(state.next_stall(), None);
+        assert!(state.next_query(|_| DEFAULT_STALL_TIMEOUT).is_some());
+    }
+
+    #[test]
+    fn test_failed_peers_are_not_results() {
+        let target = Key::random();
+        let mut state = LookupState::new(target, NodeId::random(), peers(2));
+        let (first, _) = state.next_query(|_| DEFAULT_STALL_TIMEOUT).unwrap();
+        let (second, second_addr) = state.next_query(|_| DEFAULT_STALL_TIMEOUT).unwrap();
+
+        state.failed(&first);
+        assert!(!state.is_finished());
+        state.responded(&second);
+        assert!(state.is_finished());
+        assert_eq!(state.closest_responded(), vec![(second, second_addr)]);
+    }
+
+    #[test]
+    fn test_ignores_own_id_and_duplicates() {
+        let own_id = NodeId::random();
+        let initial = peers(3);
+        let mut state = LookupState::new(Key::random(), own_id, initial.clone());
+        state.add_peers(initial);
+        state.add_peers(vec![(own_id, addr(99))]);
+        assert_eq!(state.peers.len(), 3);
+    }
+
+    #[test]
+    fn test_stall_timeout_follows_latency() {
+        assert_eq!(stall_timeout(None), DEFAULT_STALL_TIMEOUT);
+        assert_eq!(
+            stall_timeout(Some(Duration::from_millis(1))),
+            MIN_STALL_TIMEOUT
+        );
+        assert_eq!(
+            stall_timeout(Some(Duration::from_millis(200))),
+            Duration::from_millis(600)
+        );
+    }
+}
@@ -3,7 +3,7 @@
 //! This module provides initialization and bootstrap functionality
 //! required for joining a Kademlia network.
 
-use protocol::bootstrap_node;
+use protocol::{bootstrap_node, DEFAULT_NODE_ADDR};
 use std::thread;
 use std::time::Duration;
 
@@ -36,7 +36,8 @@ fn boot_splash() {
 ///
 /// Performs the following operations:
 /// - Displays the boot splash
-/// - Initializes and starts the node
+/// - Initializes and starts the node on the address given as the first
+///   argument, or on [`DEFAULT_NODE_ADDR`]
 /// - Runs the node in an infinite loop
 ///
 /// The node continues running until it receives a Ctrl+C signal.
@@ -54,7 +55,12 @@ async fn main() {
     boot_splash();
     println!("Welcome to the Kademlia DHT!");
     println!("Starting node...");
-    bootstrap_node().await.unwrap();
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_NODE_ADDR.to_string())
+        .parse()
+        .expect("Invalid node address");
+    bootstrap_node(addr).await.unwrap();
     println!("Node started successfully");
     println!("Press Ctrl+C to stop the node");
     loop {
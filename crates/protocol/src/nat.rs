@@ -0,0 +1,288 @@
+//! NAT detection and traversal support.
+//!
+//! Nodes behind a NAT advertise addresses other peers cannot reach. A node finds
+//! out whether it is reachable by asking several peers which address its pings
+//! arrived from (PONG reflects it). If the peers see a different endpoint than the
+//! one the node is bound to, the node is behind a NAT and should act as a
+//! client-only participant: it keeps using the DHT, but stays out of other nodes'
+//! k-buckets. Client-only peers remain reachable through hole punching, which any
+//! node they talk to can coordinate as a relay.
+
+use crate::NodeId;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How long a relay remembers the address of a client-only peer.
+///
+/// NAT mappings for UDP typically expire after one to a few minutes of
+/// inactivity, after which the remembered address is useless for punching.
+pub const NAT_MAPPING_TTL: Duration = Duration::from_secs(120);
+
+/// Maximum number of client-only peers a relay remembers at once.
+///
+/// Peers name themselves, so without a bound anyone could grow the registry by
+/// sending requests under made-up IDs.
+pub const MAX_NAT_CLIENTS: usize = 4096;
+
+/// Shortest time between two purges of expired client-only peers, so a full
+/// registry isn't scanned again for every request.
+const NAT_PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of hole punches a node starts per [`PUNCH_WINDOW`].
+///
+/// Every punch sends a packet to an address named by a relay, so the limit
+/// bounds how much traffic a misbehaving relay can reflect off this node.
+pub const MAX_PUNCHES_PER_WINDOW: usize = 16;
+
+/// Window over which [`MAX_PUNCHES_PER_WINDOW`] applies.
+pub const PUNCH_WINDOW: Duration = Duration::from_secs(10);
+
+/// Reachability of a node as determined from the addresses peers observed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reachability {
+    /// Peers see the address the node is bound to; it can accept connections
+    Public(SocketAddr),
+    /// Peers see a translated address; the node is behind a NAT
+    BehindNat(SocketAddr),
+    /// No peer answered, so reachability could not be determined
+    Unknown,
+}
+
+impl Reachability {
+    /// Classifies reachability from the endpoints observed by several peers.
+    ///
+    /// The public endpoint is the address reported by most peers. The node is
+    /// considered public only if every peer agrees on that endpoint and it
+    /// matches the local address. Disagreeing peers indicate a symmetric NAT,
+    /// which allocates a new mapping for every destination.
+    ///
+    /// # Arguments
+    /// * `local` - The address the node's socket is bound to
+    /// * `observed` - The addresses reported in PONG responses
+    pub fn classify(local: SocketAddr, observed: &[SocketAddr]) -> Self {
+        let mut counts: HashMap<SocketAddr, usize> = HashMap::new();
+        for addr in observed {
+            *counts.entry(*addr).or_default() += 1;
+        }
+
+        let Some((&endpoint, &count)) = counts.iter().max_by_key(|(_, count)| **count) else {
+            return Reachability::Unknown;
+        };
+
+        let matches_local = endpoint.port() == local.port()
+            && (local.ip().is_unspecified() || endpoint.ip() == local.ip());
+
+        if matches_local && count == observed.len() {
+            Reachability::Public(endpoint)
+        } else {
+            Reachability::BehindNat(endpoint)
+        }
+    }
+
+    /// Returns the public endpoint, if one was observed.
+    pub fn public_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Reachability::Public(addr) | Reachability::BehindNat(addr) => Some(*addr),
+            Reachability::Unknown => None,
+        }
+    }
+
+    /// Returns whether a node with this reachability should be client-only.
+    pub fn is_client_only(&self) -> bool {
+        matches!(self, Reachability::BehindNat(_))
+    }
+}
+
+/// Addresses of client-only peers, kept so that this node can act as their relay.
+///
+/// At most [`MAX_NAT_CLIENTS`] peers are remembered. A peer's address is only
+/// refreshed by requests from that same address; a request claiming its ID from
+/// elsewhere is ignored until the remembered mapping expires.
+#[derive(Default)]
+pub(crate) struct NatRegistry {
+    clients: HashMap<NodeId, (SocketAddr, Instant)>,
+    /// When expired entries were last purged
+    last_purge: Option<Instant>,
+}
+
+impl NatRegistry {
+    /// Records the address a client-only peer was last seen from.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the address was recorded
+    pub(crate) fn register(&mut self, node: NodeId, addr: SocketAddr) -> bool {
+        self.register_at(node, addr, Instant::now())
+    }
+
+    fn register_at(&mut self, node: NodeId, addr: SocketAddr, now: Instant) -> bool {
+        let live = |seen: &Instant| now.duration_since(*seen) < NAT_MAPPING_TTL;
+        if let Some((known, seen)) = self.clients.get_mut(&node) {
+            if live(seen) && *known != addr {
+                return false;
+            }
+            *known = addr;
+            *seen = now;
+            return true;
+        }
+
+        if self.clients.len() >= MAX_NAT_CLIENTS {
+            if self
+                .last_purge
+                .is_some_and(|purged| now.duration_since(purged) < NAT_PURGE_INTERVAL)
+            {
+                return false;
+            }
+            self.last_purge = Some(now);
+            self.clients.retain(|_, (_, seen)| live(seen));
+            if self.clients.len() >= MAX_NAT_CLIENTS {
+                return false;
+            }
+        }
+        self.clients.insert(node, (addr, now));
+        true
+    }
+
+    /// Returns the address of a client-only peer if its NAT mapping is likely still open.
+    pub(crate) fn lookup(&self, node: &NodeId) -> Option<SocketAddr> {
+        self.clients
+            .get(node)
+            .filter(|(_, seen)| seen.elapsed() < NAT_MAPPING_TTL)
+            .map(|(addr, _)| *addr)
+    }
+}
+
+/// Limits how many hole punches a node starts, see [`MAX_PUNCHES_PER_WINDOW`].
+#[derive(Default)]
+pub(crate) struct PunchLimiter {
+    /// When the punches of the current window were started, oldest first
+    recent: VecDeque<Instant>,
+}
+
+impl PunchLimiter {
+    /// Returns whether another punch may be started now, counting it if so.
+    pub(crate) fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        while self
+            .recent
+            .front()
+            .is_some_and(|started| now.duration_since(*started) >= PUNCH_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= MAX_PUNCHES_PER_WINDOW {
+            return false;
+        }
+        self.recent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_no_observations_is_unknown() {
+        let local = addr("192.168.1.10:8000");
+        assert_eq!(Reachability::classify(local, &[]), Reachability::Unknown);
+    }
+
+    #[test]
+    fn test_matching_endpoint_is_public() {
+        let local = addr("203.0.113.5:8000");
+        let observed = [local, local, local];
+        assert_eq!(
+            Reachability::classify(local, &observed),
+            Reachability::Public(local)
+        );
+    }
+
+    #[test]
+    fn test_unspecified_local_ip_compares_port() {
+        let local = addr("0.0.0.0:8000");
+        let public = addr("203.0.113.5:8000");
+        assert_eq!(
+            Reachability::classify(local, &[public, public]),
+            Reachability::Public(public)
+        );
+    }
+
+    #[test]
+    fn test_translated_endpoint_is_behind_nat() {
+        let local = addr("192.168.1.10:8000");
+        let public = addr("203.0.113.5:41000");
+        let reachability = Reachability::classify(local, &[public, public]);
+        assert_eq!(reachability, Reachability::BehindNat(public));
+        assert!(reachability.is_client_only());
+    }
+
+    #[test]
+    fn test_disagreeing_peers_is_behind_nat() {
+        let local = addr("0.0.0.0:8000");
+        let observed = [
+            addr("203.0.113.5:8000"),
+            addr("203.0.113.5:8000"),
+            addr("203.0.113.5:41000"),
+        ];
+        assert_eq!(
+            Reachability::classify(local, &observed),
+            Reachability::BehindNat(addr("203.0.113.5:8000"))
+        );
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        let mut registry = NatRegistry::default();
+        let node = NodeId::random();
+        registry.register(node, addr("203.0.113.5:41000"));
+        assert_eq!(registry.lookup(&node), Some(addr("203.0.113.5:41000")));
+        assert_eq!(registry.lookup(&NodeId::random()), None);
+    }
+
+    #[test]
+    fn test_registry_keeps_live_addresses() {
+        let mut registry = NatRegistry::default();
+        let node = NodeId::random();
+        let start = Instant::now();
+        assert!(registry.register_at(node, addr("203.0.113.5:41000"), start));
+        // Another address claiming the same ID doesn't take over
+        assert!(!registry.register_at(node, addr("198.51.100.7:9000"), start));
+        assert_eq!(registry.lookup(&node), Some(addr("203.0.113.5:41000")));
+        // Once the mapping expired, the peer may have moved
+        let later = start + NAT_MAPPING_TTL;
+        assert!(registry.register_at(node, addr("198.51.100.7:9000"), later));
+    }
+
+    #[test]
+    fn test_registry_is_bounded() {
+        let mut registry = NatRegistry::default();
+        let start = Instant::now();
+        for _ in 0..MAX_NAT_CLIENTS {
+            assert!(registry.register_at(NodeId::random(), addr("203.0.113.5:41000"), start));
+        }
+        assert!(!registry.register_at(NodeId::random(), addr("203.0.113.5:41000"), start));
+        // Expired peers make room again
+        let later = start + NAT_MAPPING_TTL;
+        assert!(registry.register_at(NodeId::random(), addr("203.0.113.5:41000"), later));
+        assert_eq!(registry.clients.len(), 1);
+    }
+
+    #[test]
+    fn test_punch_limiter() {
+        let mut limiter = PunchLimiter::default();
+        let start = Instant::now();
+        for _ in 0..MAX_PUNCHES_PER_WINDOW {
+            assert!(limiter.allow_at(start));
+        }
+        assert!(!limiter.allow_at(start + PUNCH_WINDOW / 2));
+        assert!(limiter.allow_at(start + PUNCH_WINDOW));
+    }
+}
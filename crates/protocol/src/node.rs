@@ -1,13 +1,32 @@
+use crate::app::{AppHandlers, AppRequest, MAX_APP_HANDLERS};
+use crate::control::{BucketDump, NodeStatus};
+use crate::handoff::{
+    HandoffQueue, HANDOFF_CHECKS_PER_INTERVAL, HANDOFF_INTERVAL, HANDOFF_STORES_PER_INTERVAL,
+};
+use crate::integrity::{self, SignedRecord, ValueMode};
+use crate::lookup::{LookupContext, LookupQuery, LookupResult};
+use crate::nat::Reachability;
+use crate::object::{
+    ChunkStream, ObjectManifest, Redundancy, Stripe, CHUNK_SIZE, MAX_CHUNKS, PARALLEL_CHUNKS,
+    REPAIR_INTERVAL,
+};
+use crate::pubsub::{Disposition, GossipEnvelope, PubSub, TopicMessage, Validator, GOSSIP_FANOUT};
 use crate::rpc::{RpcClient, RpcServer};
 use crate::storage::Storage;
-use crate::types::Distance;
-use crate::{Key, NodeId, RoutingTable, ALPHA, K};
-use anyhow::Result;
+use crate::{Distance, Key, NetworkId, NodeId, RoutingTable, K};
+use anyhow::{anyhow, bail, Result};
+use futures::future::join_all;
+use futures::stream::{self, FuturesUnordered, StreamExt, TryStreamExt};
 use std::collections::HashSet;
+use std::future::Future;
 use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex, Semaphore};
+
+/// Number of pings sent to a peer while punching a hole through its NAT
+const PUNCH_ATTEMPTS: usize = 3;
 
 /// A node in the Kademlia distributed hash table network.
 ///
@@ -17,6 +36,10 @@ use tokio::sync::Mutex;
 /// - Persistent storage for key-value pairs using Sled
 /// - RPC capabilities for network communication
 ///
+/// A node belongs to exactly one overlay network. A process joins several
+/// overlays by running one node per network; the nodes may share a
+/// [`Storage`], whose records are partitioned by network.
+///
 /// # References
 /// Based on the Kademlia DHT paper:
 /// "Kademlia: A Peer-to-peer Information System Based on the XOR Metric"
@@ -26,19 +49,30 @@ pub struct Node {
     id: NodeId,
     /// Network address of this node
     addr: SocketAddr,
-    /// k-bucket routing table storing known nodes
-    routing_table: RoutingTable,
+    /// Overlay network this node belongs to
+    network: NetworkId,
+    /// k-bucket routing table storing known nodes, shared with the RPC server
+    routing_table: Arc<Mutex<RoutingTable>>,
     /// Persistent key-value storage using Sled
     storage: Storage,
     /// Server for handling incoming RPCs
     rpc_server: RpcServer,
-    /// Client for making outgoing RPCs
+    /// Client for making outgoing RPCs, sharing the server's socket
     rpc_client: RpcClient,
+    /// Time at which this node was created
+    started: Instant,
+    /// Topic subscriptions and recently seen gossip
+    pubsub: PubSub,
+    /// Handlers of application protocols
+    app_handlers: Arc<AppHandlers>,
 }
 
 impl Node {
     /// Creates a new Kademlia node with a randomly generated NodeId.
     ///
+    /// The node's socket is bound immediately, so outgoing RPCs can be made
+    /// before [`Node::run`] starts serving incoming ones.
+    ///
     /// # Arguments
     /// * `addr` - The socket address this node will listen on
     /// * `storage_path` - Path to the directory where Sled will store its data
@@ -46,19 +80,53 @@ impl Node {
     /// # Returns
     /// * `Result<Self>` - A new Node instance or an error
     pub async fn new(addr: SocketAddr, storage_path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_storage(addr, Storage::new(storage_path)?).await
+    }
+
+    /// Creates a new Kademlia node on top of an already opened storage.
+    ///
+    /// Use this to run a node with a non-default [`crate::StorageConfig`], for
+    /// example an in-memory backend on devices without persistent storage.
+    ///
+    /// # Arguments
+    /// * `addr` - The socket address this node will listen on
+    /// * `storage` - The storage holding this node's records
+    pub async fn with_storage(addr: SocketAddr, storage: Storage) -> Result<Self> {
+        Self::with_network(addr, storage, NetworkId::default()).await
+    }
+
+    /// Creates a new Kademlia node taking part in the given overlay network.
+    ///
+    /// The node refuses RPCs from other networks and only sees the records
+    /// the storage holds for its own network.
+    ///
+    /// # Arguments
+    /// * `addr` - The socket address this node will listen on
+    /// * `storage` - The storage holding this node's records
+    /// * `network` - The overlay network to join
+    pub async fn with_network(
+        addr: SocketAddr,
+        storage: Storage,
+        network: NetworkId,
+    ) -> Result<Self> {
         let id = NodeId::random();
-        let routing_table = RoutingTable::new(id);
-        let storage = Storage::new(storage_path)?;
-        let rpc_server = RpcServer::new().await?;
-        let rpc_client = RpcClient::new().await?;
+        let routing_table = Arc::new(Mutex::new(RoutingTable::new(id)));
+        let rpc_server = RpcServer::bind_with_network(addr, network.clone()).await?;
+        let storage = storage.for_network(&network);
+        let rpc_client = rpc_server.client();
+        let addr = rpc_server.local_addr()?;
 
         Ok(Node {
             id,
             addr,
+            network,
             routing_table,
             storage,
             rpc_server,
             rpc_client,
+            started: Instant::now(),
+            pubsub: PubSub::default(),
+            app_handlers: Arc::default(),
         })
     }
 
@@ -75,100 +143,1082 @@ impl Node {
     /// 1. Looks up the k closest nodes to the key
     /// 2. Sends STORE RPCs to each of these nodes
     pub async fn store(&mut self, key: Key, value: Vec<u8>) -> Result<()> {
-        // First store locally with a TTL of 24 hours
-        self.storage.store(
-            key,
-            value.clone(),
-            std::time::Duration::from_secs(24 * 60 * 60),
-        )?;
+        self.store_with_mode(key, value, ValueMode::Plain).await
+    }
+
+    /// Stores an immutable value under the hash of its content.
+    ///
+    /// # Returns
+    /// * `Result<Key>` - The content-addressed key of the value
+    pub async fn store_content(&self, value: Vec<u8>) -> Result<Key> {
+        let key = integrity::content_key(&value);
+        self.store_with_mode(key, value, ValueMode::ContentAddressed)
+            .await?;
+        Ok(key)
+    }
+
+    /// Publishes a signed record under the key derived from its publisher.
+    ///
+    /// Nodes holding an older version of the record replace it; nodes holding
+    /// the same or a newer version keep theirs.
+    ///
+    /// # Returns
+    /// * `Result<Key>` - The key of the record
+    pub async fn store_signed(&self, record: &SignedRecord) -> Result<Key> {
+        let key = record.key();
+        self.store_with_mode(key, record.encode()?, ValueMode::Signed)
+            .await?;
+        Ok(key)
+    }
+
+    /// Stores a value that has to verify against its key in the given mode.
+    ///
+    /// The value is checked before it is stored locally, and every receiving
+    /// node checks it again.
+    pub async fn store_with_mode(&self, key: Key, value: Vec<u8>, mode: ValueMode) -> Result<()> {
+        if !integrity::verify(&key, mode, &value) {
+            bail!("Value does not match key {} in mode {:?}", key, mode);
+        }
+
+        // First store locally with the default TTL, unless that would replace
+        // a value we must keep, such as a newer version of a signed record
+        let replaceable = match self.storage.get(&key)? {
+            Some(existing) => integrity::may_replace(&key, &existing, mode, &value),
+            None => true,
+        };
+        if replaceable {
+            self.storage
+                .store(key, value.clone(), self.storage.default_ttl()?)?;
+        }
 
         // Then replicate to k closest nodes
         let nodes = self.lookup_nodes(key).await?;
-        for (node_id, addr) in nodes {
+        for (_, addr) in nodes {
             self.rpc_client
-                .store(self.id, node_id, addr, key, value.clone())
+                .store(self.id, addr, key, value.clone(), mode)
                 .await?;
         }
         Ok(())
     }
 
+    /// Retrieves a value from the DHT using the Kademlia FIND_VALUE lookup.
+    ///
+    /// Every value is verified against the key in the given mode before it is
+    /// accepted. Peers returning values that fail verification are banned from
+    /// the routing table and the lookup continues with the remaining peers.
+    ///
+    /// # Arguments
+    /// * `key` - The key to look up
+    /// * `mode` - How the value is expected to verify; for [`ValueMode::Signed`]
+    ///   the returned bytes are the encoded [`SignedRecord`] with the highest
+    ///   sequence number among the peers that answered
+    ///
+    /// # Returns
+    /// * `Result<Option<Vec<u8>>>` - The value if a peer returned a valid one
+    pub async fn find_value(&self, key: Key, mode: ValueMode) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .find_value_with_holder(key, mode)
+            .await?
+            .map(|(value, _)| value))
+    }
+
+    /// Retrieves a value like [`Node::find_value`], together with the ID of the
+    /// node that held it, which is our own ID for values stored locally.
+    async fn find_value_with_holder(
+        &self,
+        key: Key,
+        mode: ValueMode,
+    ) -> Result<Option<(Vec<u8>, NodeId)>> {
+        if let Some(value) = self.storage.get(&key)? {
+            if integrity::verify(&key, mode, &value) {
+                return Ok(Some((value, self.id)));
+            }
+        }
+
+        match self
+            .lookup_context()
+            .run(key, LookupQuery::Value(mode))
+            .await?
+        {
+            LookupResult::Value { value, holder } => Ok(Some((value, holder))),
+            LookupResult::Nodes(_) => Ok(None),
+        }
+    }
+
+    /// Stores a blob of any size as a replicated chunked object.
+    ///
+    /// See [`Node::store_object_with`].
+    ///
+    /// # Returns
+    /// * `Result<Key>` - The key of the manifest, which identifies the object
+    pub async fn store_object(&self, data: &[u8]) -> Result<Key> {
+        self.store_object_with(data, Redundancy::Replicated).await
+    }
+
+    /// Stores a blob of any size as a chunked object.
+    ///
+    /// Replicated objects are split into chunks of [`CHUNK_SIZE`] bytes that are
+    /// stored as content-addressed values, [`PARALLEL_CHUNKS`] at a time.
+    /// Erasure-coded objects are split into stripes whose shards are each stored
+    /// once, on distinct nodes close to the shard keys. The manifest listing the
+    /// pieces is stored last, replicated like any content-addressed value.
+    ///
+    /// # Returns
+    /// * `Result<Key>` - The key of the manifest, which identifies the object
+    pub async fn store_object_with(&self, data: &[u8], redundancy: Redundancy) -> Result<Key> {
+        let manifest = match redundancy {
+            Redundancy::Replicated => {
+                let (manifest, chunks) = ObjectManifest::split(data, CHUNK_SIZE)?;
+                stream::iter(chunks)
+                    .map(|chunk| self.store_content(chunk))
+                    .buffer_unordered(PARALLEL_CHUNKS)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                manifest
+            }
+            Redundancy::ErasureCoded(config) => {
+                let (manifest, mut shards) = ObjectManifest::split_erasure(data, config)?;
+                for stripe in manifest.stripes() {
+                    let stripe_shards: Vec<(Key, Vec<u8>)> = stripe
+                        .shards
+                        .iter()
+                        .map(|shard| shard.key)
+                        .zip(shards.drain(..stripe.shards.len()))
+                        .collect();
+                    self.place_shards(stripe_shards, HashSet::new()).await?;
+                }
+                manifest
+            }
+        };
+
+        // The manifest goes last, so it never points to missing chunks
+        self.store_content(manifest.encode()?).await
+    }
+
+    /// Stores each shard of a stripe once, on a node close to the shard's key
+    /// that holds no other shard of the stripe.
+    ///
+    /// Candidates for a shard are the k closest nodes to its key, including
+    /// ourselves, so lookups for the shard find it. If the network has fewer
+    /// nodes than the stripe has shards, nodes are reused.
+    ///
+    /// # Arguments
+    /// * `shards` - Keys and contents of the shards to place
+    /// * `holders` - Nodes already holding shards of the stripe
+    async fn place_shards(
+        &self,
+        shards: Vec<(Key, Vec<u8>)>,
+        mut holders: HashSet<NodeId>,
+    ) -> Result<()> {
+        let lookups = shards.iter().map(|(key, _)| self.lookup_nodes(*key));
+        let closest = join_all(lookups).await;
+
+        for ((key, shard), nodes) in shards.into_iter().zip(closest) {
+            let mut candidates: Vec<(NodeId, Option<SocketAddr>)> = nodes?
+                .into_iter()
+                .map(|(id, addr)| (id, Some(addr)))
+                .collect();
+            candidates.push((self.id, None));
+            candidates.sort_by_key(|(id, _)| Distance::between(id, &key));
+            // Unused nodes first, keeping the distance order within both groups
+            candidates.sort_by_key(|(id, _)| holders.contains(id));
+
+            let mut placed = None;
+            for (id, addr) in candidates {
+                let stored = match addr {
+                    Some(addr) => self
+                        .rpc_client
+                        .store_shard(self.id, addr, key, shard.clone())
+                        .await
+                        .unwrap_or_else(|e| {
+                            log::debug!("Failed to place shard {} on {}: {}", key, id, e);
+                            false
+                        }),
+                    None => {
+                        self.storage.store_shard(
+                            key,
+                            shard.clone(),
+                            self.storage.default_ttl()?,
+                        )?;
+                        true
+                    }
+                };
+                if stored {
+                    placed = Some(id);
+                    break;
+                }
+            }
+
+            match placed {
+                Some(id) => {
+                    holders.insert(id);
+                }
+                None => bail!("No node accepted shard {}", key),
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieves the manifest of a chunked object.
+    ///
+    /// # Returns
+    /// * `Result<Option<ObjectManifest>>` - The manifest, if the key holds one
+    pub async fn get_manifest(&self, key: Key) -> Result<Option<ObjectManifest>> {
+        match self.find_value(key, ValueMode::ContentAddressed).await? {
+            Some(bytes) => Ok(Some(ObjectManifest::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Retrieves a chunked object as a stream of its pieces.
+    ///
+    /// Chunks of replicated objects are fetched [`PARALLEL_CHUNKS`] at a time,
+    /// each through its own lookup, so they come from the different peers
+    /// holding them. Stripes of erasure-coded objects are rebuilt from the first
+    /// shards that arrive. Every chunk and shard is checked against the manifest
+    /// before it is used, and pieces are yielded in object order.
+    ///
+    /// # Returns
+    /// * `Result<Option<(ObjectManifest, ChunkStream)>>` - The manifest and the
+    ///   stream of its pieces, if the key holds a manifest
+    pub async fn get_object(&self, key: Key) -> Result<Option<(ObjectManifest, ChunkStream<'_>)>> {
+        let Some(manifest) = self.get_manifest(key).await? else {
+            return Ok(None);
+        };
+
+        let shared = Arc::new(manifest.clone());
+        let stream = match manifest.erasure {
+            None => stream::iter(0..manifest.chunks.len())
+                .map(move |index| {
+                    let manifest = Arc::clone(&shared);
+                    async move {
+                        let chunk = manifest.chunks[index];
+                        let bytes = self
+                            .find_value(chunk.key, ValueMode::ContentAddressed)
+                            .await?
+                            .ok_or_else(|| anyhow!("Chunk {} ({}) not found", index, chunk.key))?;
+                        manifest.verify_chunk(index, &bytes)?;
+                        Ok(bytes)
+                    }
+                })
+                .buffered(PARALLEL_CHUNKS)
+                .boxed(),
+            Some(layout) => {
+                let parallel_stripes = (PARALLEL_CHUNKS / layout.config.total_shards()).max(1);
+                stream::iter(0..manifest.stripes().len())
+                    .map(move |index| {
+                        let manifest = Arc::clone(&shared);
+                        async move {
+                            let stripe = manifest.stripes()[index];
+                            let needed = layout.config.data_shards as usize;
+                            let shards = self.fetch_shards(&manifest, &stripe, needed).await;
+                            let shards = shards
+                                .into_iter()
+                                .map(|shard| shard.map(|(bytes, _)| bytes))
+                                .collect();
+                            layout.config.decode(shards, stripe.data_len)
+                        }
+                    })
+                    .buffered(parallel_stripes)
+                    .boxed()
+            }
+        };
+        Ok(Some((manifest, stream)))
+    }
+
+    /// Retrieves a whole chunked object, see [`Node::get_object`].
+    ///
+    /// # Returns
+    /// * `Result<Option<Vec<u8>>>` - The object, if the key holds a manifest
+    pub async fn read_object(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        let Some((manifest, pieces)) = self.get_object(key).await? else {
+            return Ok(None);
+        };
+        // The manifest came from another node, so don't trust its length further
+        // than the largest object a manifest can describe
+        let capacity = manifest.total_len.min((MAX_CHUNKS * CHUNK_SIZE) as u64);
+        let mut data = Vec::with_capacity(capacity as usize);
+        pieces
+            .try_for_each(|piece| {
+                data.extend_from_slice(&piece);
+                futures::future::ready(Ok(()))
+            })
+            .await?;
+        Ok(Some(data))
+    }
+
+    /// Fetches the shards of a stripe concurrently until `needed` of them
+    /// arrived and verified.
+    ///
+    /// # Returns
+    /// The shards of the stripe in order, with the nodes holding them; `None`
+    /// for shards that weren't found or weren't needed
+    async fn fetch_shards(
+        &self,
+        manifest: &ObjectManifest,
+        stripe: &Stripe<'_>,
+        needed: usize,
+    ) -> Vec<Option<(Vec<u8>, NodeId)>> {
+        let mut fetches: FuturesUnordered<_> = stripe
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(offset, shard)| async move {
+                let found = self
+                    .find_value_with_holder(shard.key, ValueMode::ContentAddressed)
+                    .await;
+                (offset, found)
+            })
+            .collect();
+
+        let mut shards = vec![None; stripe.shards.len()];
+        let mut found = 0;
+        while let Some((offset, result)) = fetches.next().await {
+            match result {
+                Ok(Some((bytes, holder))) => {
+                    match manifest.verify_chunk(stripe.first_shard + offset, &bytes) {
+                        Ok(()) => {
+                            shards[offset] = Some((bytes, holder));
+                            found += 1;
+                        }
+                        Err(e) => log::debug!("{}", e),
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("Failed to fetch shard {}: {}", stripe.shards[offset].key, e),
+            }
+            if found >= needed {
+                break;
+            }
+        }
+        shards
+    }
+
+    /// Recreates the lost shards of an erasure-coded object.
+    ///
+    /// Every stripe missing shards is rebuilt from the remaining ones, and the
+    /// recreated shards are placed on nodes holding no other shard of the stripe.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of shards recreated
+    pub async fn repair_object(&self, key: Key) -> Result<usize> {
+        let Some(manifest) = self.get_manifest(key).await? else {
+            bail!("Object {} not found", key);
+        };
+        let Some(layout) = manifest.erasure else {
+            return Ok(0);
+        };
+
+        let mut repaired = 0;
+        for stripe in manifest.stripes() {
+            let fetched = self
+                .fetch_shards(&manifest, &stripe, stripe.shards.len())
+                .await;
+            if fetched.iter().all(Option::is_some) {
+                continue;
+            }
+
+            let holders: HashSet<NodeId> = fetched.iter().flatten().map(|(_, id)| *id).collect();
+            let mut shards: Vec<Option<Vec<u8>>> = fetched
+                .into_iter()
+                .map(|shard| shard.map(|(bytes, _)| bytes))
+                .collect();
+            let missing: Vec<usize> = (0..shards.len()).filter(|i| shards[*i].is_none()).collect();
+            if let Err(e) = layout.config.reconstruct(&mut shards) {
+                log::warn!("Stripe of object {} is lost: {}", key, e);
+                continue;
+            }
+
+            let recreated: Vec<(Key, Vec<u8>)> = missing
+                .iter()
+                .filter_map(|&i| Some((stripe.shards[i].key, shards[i].take()?)))
+                .collect();
+            repaired += recreated.len();
+            self.place_shards(recreated, holders).await?;
+        }
+
+        if repaired > 0 {
+            log::info!("Recreated {} shards of object {}", repaired, key);
+        }
+        Ok(repaired)
+    }
+
+    /// Periodically repairs the erasure-coded objects this node is responsible for.
+    ///
+    /// Every node holding a manifest could repair its object; only the one
+    /// closest to the manifest key among those it knows does, so the work isn't
+    /// repeated by all k replicas.
+    async fn repair_loop(&self) -> Result<()> {
+        let start = tokio::time::Instant::now() + REPAIR_INTERVAL;
+        let mut interval = tokio::time::interval_at(start, REPAIR_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let keys = match self.storage.manifest_keys() {
+                Ok(keys) => keys,
+                Err(e) => {
+                    log::warn!("Failed to list stored object manifests: {}", e);
+                    continue;
+                }
+            };
+            for key in keys {
+                match self.storage.get(&key) {
+                    Ok(Some(value)) if ObjectManifest::is_erasure_coded(&value) => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        log::warn!("Failed to read object manifest {}: {}", key, e);
+                        continue;
+                    }
+                }
+                if !self.is_closest_to(&key).await {
+                    continue;
+                }
+                if let Err(e) = self.repair_object(key).await {
+                    log::debug!("Failed to repair object {}: {}", key, e);
+                }
+            }
+        }
+    }
+
+    /// Returns whether we are closer to a key than every node we know.
+    async fn is_closest_to(&self, key: &Key) -> bool {
+        let own_distance = Distance::between(&self.id, key);
+        self.routing_table
+            .lock()
+            .await
+            .closest_nodes(key, K)
+            .iter()
+            .all(|(id, _)| Distance::between(id, key) > own_distance)
+    }
+
     /// Looks up the k closest nodes to a given key using the Kademlia node lookup algorithm.
     ///
     /// This is a core operation in Kademlia that implements the iterative node lookup process:
-    /// 1. Starts with the α closest nodes from the local routing table
-    /// 2. Sends parallel FIND_NODE RPCs to improve knowledge of the target ID's neighborhood
-    /// 3. Iteratively queries nodes that are closer to the target until no closer nodes are found
+    /// 1. Starts with the k closest nodes from the local routing table
+    /// 2. Keeps α FIND_NODE RPCs in flight, sending the next one as soon as any answers
+    /// 3. Iteratively queries nodes that are closer to the target until the k closest
+    ///    nodes have all answered
     ///
     /// # Arguments
     /// * `key` - The target key to find nodes close to
     ///
     /// # Returns
-    /// * `Result<Vec<(NodeId, SocketAddr)>>` - Vector of the k closest nodes and their addresses
+    /// * `Result<Vec<(NodeId, SocketAddr)>>` - Vector of the k closest nodes that
+    ///   answered and their addresses
     ///
     /// # Implementation Details
-    /// * Uses α (ALPHA) parallel lookups for better performance
-    /// * Maintains sets of contacted and pending nodes to avoid duplicate queries
+    /// * Peers slower than their usual latency are marked as stalled and no longer
+    ///   count against α, so the lookup widens instead of waiting for them
+    /// * Peers that don't answer are dropped from the result
     /// * Sorts results by XOR distance to the target key
-    /// * Terminates when no closer nodes are found
     pub async fn lookup_nodes(&self, key: Key) -> Result<Vec<(NodeId, SocketAddr)>> {
-        let mut closest = self.routing_table.closest_nodes(&key, K);
-        let mut contacted = HashSet::new();
-        let mut pending = HashSet::new();
-        let mut closest_with_addr = Vec::new();
-
-        while !closest.is_empty() {
-            let mut concurrent_lookups = Vec::new();
-
-            // Query up to ALPHA nodes in parallel for better network utilization
-            for node_id in closest.iter().take(ALPHA) {
-                if !contacted.contains(node_id) && !pending.contains(node_id) {
-                    // TODO: Get actual SocketAddr from routing table
-                    let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
-                    pending.insert(*node_id);
-                    let lookup = self.rpc_client.find_node(self.id, key, addr);
-                    concurrent_lookups.push(lookup);
+        match self.lookup_context().run(key, LookupQuery::Nodes).await? {
+            LookupResult::Nodes(nodes) => Ok(nodes),
+            LookupResult::Value { .. } => unreachable!("node lookups don't return values"),
+        }
+    }
+
+    /// Returns what lookups started by this node work with.
+    fn lookup_context(&self) -> LookupContext<'_> {
+        LookupContext {
+            client: &self.rpc_client,
+            routing_table: &self.routing_table,
+            own_id: self.id,
+        }
+    }
+
+    /// Subscribes to a topic.
+    ///
+    /// Messages published to the topic by any node of the network are
+    /// delivered to the returned receiver. A receiver that falls behind misses
+    /// the oldest messages instead of holding up the node.
+    pub fn subscribe(&self, topic: Key) -> broadcast::Receiver<TopicMessage> {
+        self.pubsub.subscribe(topic)
+    }
+
+    /// Drops all subscriptions to a topic and its validator.
+    ///
+    /// The node keeps relaying the topic's messages for others.
+    pub fn unsubscribe(&self, topic: &Key) {
+        self.pubsub.unsubscribe(topic)
+    }
+
+    /// Installs a validator for the messages of a topic.
+    ///
+    /// Messages the validator rejects are neither delivered to subscribers nor
+    /// forwarded to other nodes.
+    pub fn set_topic_validator(
+        &self,
+        topic: Key,
+        validator: impl Fn(&TopicMessage) -> bool + Send + Sync + 'static,
+    ) {
+        let validator: Validator = Arc::new(validator);
+        self.pubsub.set_validator(topic, validator)
+    }
+
+    /// Publishes a message to a topic.
+    ///
+    /// The message is delivered to local subscribers and gossiped to
+    /// [`GOSSIP_FANOUT`] random peers, which pass it on.
+    ///
+    /// # Returns
+    /// * `Result<Key>` - The ID of the message
+    pub async fn publish(&self, topic: Key, payload: Vec<u8>) -> Result<Key> {
+        let envelope = self.pubsub.create(self.id, topic, payload);
+        let id = envelope.message.id();
+        match self.pubsub.accept(envelope) {
+            Disposition::Forward(envelope) => self.forward_gossip(envelope, None).await,
+            Disposition::Invalid => bail!("Message rejected by the validator of topic {}", topic),
+            _ => {}
+        }
+        Ok(id)
+    }
+
+    /// Handles gossip received by the RPC server.
+    async fn gossip_loop(&self) -> Result<()> {
+        while let Some((sender, envelope)) = self.rpc_server.next_gossip().await {
+            match self.pubsub.accept(envelope) {
+                Disposition::Forward(envelope) => self.forward_gossip(envelope, Some(sender)).await,
+                Disposition::Invalid => log::debug!("Dropping invalid gossip from {}", sender),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a message on to random peers, other than the one it came from.
+    async fn forward_gossip(&self, envelope: GossipEnvelope, from: Option<NodeId>) {
+        let mut exclude = vec![envelope.message.origin];
+        exclude.extend(from);
+        let peers = self
+            .routing_table
+            .lock()
+            .await
+            .random_nodes(GOSSIP_FANOUT, &exclude);
+
+        let sends = peers.iter().map(|(id, addr)| {
+            let envelope = envelope.clone();
+            async move {
+                if let Err(e) = self.rpc_client.gossip(self.id, *addr, envelope).await {
+                    log::debug!("Failed to gossip to {}: {}", id, e);
                 }
             }
+        });
+        join_all(sends).await;
+    }
+
+    /// Registers the handler of an application protocol.
+    ///
+    /// Requests and one-way messages other nodes send for the protocol are
+    /// passed to the handler, each in its own task. The handler's result
+    /// answers requests; an error is sent back to the requester as a failure.
+    /// Registering a protocol again replaces its handler.
+    ///
+    /// # Arguments
+    /// * `protocol` - Name of the protocol, see [`crate::app::MAX_PROTOCOL_NAME_LEN`]
+    /// * `handler` - Called with every message received for the protocol
+    pub fn register_handler<F, Fut>(&self, protocol: &str, handler: F) -> Result<()>
+    where
+        F: Fn(AppRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>>> + Send + 'static,
+    {
+        self.app_handlers.register(protocol, handler)
+    }
+
+    /// Removes the handler of an application protocol.
+    ///
+    /// Further requests for the protocol fail on the requesting node.
+    pub fn unregister_handler(&self, protocol: &str) -> bool {
+        self.app_handlers.unregister(protocol)
+    }
+
+    /// Sends an application request to a node and waits for its answer.
+    ///
+    /// The node's address is taken from the routing table or found with a
+    /// node lookup.
+    ///
+    /// # Arguments
+    /// * `target` - ID of the node to send to
+    /// * `protocol` - Application protocol of the request
+    /// * `payload` - The request
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>>` - The answer of the target's handler
+    pub async fn send_request(
+        &self,
+        target: NodeId,
+        protocol: &str,
+        payload: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        crate::app::check_protocol_name(protocol)?;
+        let addr = self.resolve_node(target).await?;
+        self.rpc_client
+            .app_request(self.id, addr, protocol, payload)
+            .await
+    }
+
+    /// Sends a one-way application message to a node.
+    ///
+    /// Delivery isn't confirmed; use [`Node::send_request`] when the sender
+    /// needs to know the message arrived.
+    pub async fn send_message(
+        &self,
+        target: NodeId,
+        protocol: &str,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        crate::app::check_protocol_name(protocol)?;
+        let addr = self.resolve_node(target).await?;
+        self.rpc_client
+            .app_message(self.id, addr, protocol, payload)
+            .await
+    }
+
+    /// Finds the address of a node, looking it up if it isn't in the routing table.
+    async fn resolve_node(&self, target: NodeId) -> Result<SocketAddr> {
+        if target == self.id {
+            bail!("Can't send an application message to this node itself");
+        }
+        if let Some(addr) = self.routing_table.lock().await.address_of(&target) {
+            return Ok(addr);
+        }
+        self.lookup_nodes(target)
+            .await?
+            .into_iter()
+            .find(|(id, _)| *id == target)
+            .map(|(_, addr)| addr)
+            .ok_or_else(|| anyhow!("Node {} not found in the network", target))
+    }
 
-            // Process responses and update closest nodes
-            let responses = futures::future::join_all(concurrent_lookups).await;
-            for response in responses {
-                if let Ok(new_nodes) = response {
-                    for (node_id, addr) in new_nodes {
-                        closest.push(node_id);
-                        closest_with_addr.push((node_id, addr));
+    /// Passes application messages received by the RPC server to their handlers,
+    /// running at most [`MAX_APP_HANDLERS`] of them at once.
+    async fn app_loop(&self) -> Result<()> {
+        let running = Arc::new(Semaphore::new(MAX_APP_HANDLERS));
+        while let Some(incoming) = self.rpc_server.next_app_message().await {
+            // Wait for a running handler to finish; meanwhile new messages queue up
+            let permit = Arc::clone(&running).acquire_owned().await?;
+            let handler = self.app_handlers.get(&incoming.request.protocol);
+            let protocol = incoming.request.protocol.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                let result = match handler {
+                    Some(handler) => handler(incoming.request).await,
+                    None => Err(anyhow!("No handler for protocol {}", protocol)),
+                };
+                match incoming.reply {
+                    Some(reply) => reply.send(result).await,
+                    None => {
+                        if let Err(e) = result {
+                            log::debug!("Failed to handle message for {}: {}", protocol, e);
+                        }
                     }
                 }
-            }
+            });
+        }
+        Ok(())
+    }
+
+    /// Joins the network through the given seed nodes.
+    ///
+    /// Each seed is pinged to learn its ID and added to the routing table. A
+    /// lookup of our own ID then fills the routing table with the nodes closest
+    /// to us, which also announces us to them.
+    ///
+    /// # Arguments
+    /// * `seeds` - Addresses of nodes already in the network
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of known nodes after joining
+    pub async fn bootstrap(&self, seeds: &[SocketAddr]) -> Result<usize> {
+        self.lookup_context().contact_seeds(seeds).await?;
+        Ok(self.routing_table.lock().await.len())
+    }
+
+    /// Adds a peer learned outside of the DHT, for example on the local network.
+    ///
+    /// The peer is pinged first, so unreachable or spoofed announcements don't
+    /// enter the routing table.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether the peer answered with the announced ID
+    pub async fn add_peer(&self, id: NodeId, addr: SocketAddr) -> Result<bool> {
+        let started = Instant::now();
+        if self.rpc_client.identify(self.id, addr).await? != id {
+            return Ok(false);
+        }
+        self.routing_table
+            .lock()
+            .await
+            .record_success(id, addr, started.elapsed());
+        Ok(true)
+    }
+
+    /// Returns whether a node is in the routing table.
+    pub async fn knows_peer(&self, id: &NodeId) -> bool {
+        self.routing_table.lock().await.stats(id).is_some()
+    }
+
+    /// Determines whether this node is reachable from the outside.
+    ///
+    /// Each peer is pinged and reports the address the ping arrived from. If the
+    /// peers observe a translated endpoint, the node is behind a NAT and switches
+    /// to client-only mode, so that peers don't add it to their k-buckets.
+    ///
+    /// # Arguments
+    /// * `peers` - Addresses of the peers to ask, ideally on different networks
+    ///
+    /// # Returns
+    /// * `Result<Reachability>` - The detected reachability and public endpoint
+    pub async fn detect_reachability(&self, peers: &[SocketAddr]) -> Result<Reachability> {
+        let probes = peers
+            .iter()
+            .map(|peer| self.rpc_client.observed_addr(self.id, *peer));
+        let observed: Vec<SocketAddr> = futures::future::join_all(probes)
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
 
-            // Maintain k-closest nodes invariant
-            closest.sort_by_key(|n| Distance::between(n, &key));
-            closest.truncate(K);
+        let reachability = Reachability::classify(self.addr, &observed);
+        if reachability != Reachability::Unknown {
+            self.rpc_client
+                .set_client_only(reachability.is_client_only());
+        }
+        Ok(reachability)
+    }
 
-            closest_with_addr.sort_by_key(|(n, _)| Distance::between(n, &key));
-            closest_with_addr.truncate(K);
+    /// Returns whether this node participates as a client-only node.
+    pub fn is_client_only(&self) -> bool {
+        self.rpc_client.is_client_only()
+    }
 
-            // Track queried nodes
-            for node_id in closest.iter().take(ALPHA) {
-                pending.remove(node_id);
-                contacted.insert(*node_id);
+    /// Sets whether the routing table also splits buckets not covering our
+    /// own ID while they may hold one of our k closest nodes.
+    ///
+    /// Relaxed splitting keeps close contacts in highly unbalanced trees, at
+    /// the cost of a larger routing table.
+    pub async fn set_relaxed_split(&self, relaxed: bool) {
+        self.routing_table.lock().await.set_relaxed_split(relaxed);
+    }
+
+    /// Opens a direct path to a client-only node through a relay.
+    ///
+    /// The relay tells the target to send a packet towards us while we ping the
+    /// target's address, which opens the NAT mappings on both sides.
+    ///
+    /// # Arguments
+    /// * `relay` - Address of a node the target keeps in contact with
+    /// * `target` - ID of the client-only node to reach
+    ///
+    /// # Returns
+    /// * `Result<Option<SocketAddr>>` - The target's address if it answered
+    pub async fn connect_via_relay(
+        &self,
+        relay: SocketAddr,
+        target: NodeId,
+    ) -> Result<Option<SocketAddr>> {
+        let Some(target_addr) = self
+            .rpc_client
+            .relay_connect(self.id, relay, target)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        // The first pings may be dropped until the target's punch has gone out
+        for _ in 0..PUNCH_ATTEMPTS {
+            if let Ok(true) = self.rpc_client.ping(self.id, target_addr).await {
+                return Ok(Some(target_addr));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Republishes all locally stored values to the k closest nodes of their keys.
+    ///
+    /// Values are republished periodically (see [`crate::REPUBLISH_INTERVAL`]) to keep
+    /// them available as nodes join and leave the network. Shards of
+    /// erasure-coded objects are stored on one node each and are left to the
+    /// repair loop instead.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of values republished
+    pub async fn republish(&self) -> Result<usize> {
+        let entries = self.storage.entries()?;
+
+        for (key, value) in &entries {
+            let mode = ValueMode::detect(key, value);
+            let nodes = self.lookup_nodes(*key).await?;
+            for (_, addr) in nodes {
+                // Unreachable nodes only cost this replica, not the whole run
+                if let Err(e) = self
+                    .rpc_client
+                    .store(self.id, addr, *key, value.clone(), mode)
+                    .await
+                {
+                    log::debug!("Failed to republish {} to {}: {}", key, addr, e);
+                }
             }
         }
 
-        Ok(closest_with_addr)
+        Ok(entries.len())
+    }
+
+    /// Refreshes every non-empty k-bucket by looking up a random ID in its range.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of buckets refreshed
+    pub async fn refresh_buckets(&self) -> Result<usize> {
+        let targets = self.routing_table.lock().await.refresh_targets();
+        for target in &targets {
+            self.lookup_nodes(*target).await?;
+        }
+        Ok(targets.len())
+    }
+
+    /// Returns the ID of this node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the address this node's RPC socket is bound to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the overlay network this node belongs to.
+    pub fn network(&self) -> &NetworkId {
+        &self.network
+    }
+
+    /// Returns the time elapsed since this node was created.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns the local storage of this node.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Returns a summary of this node's state.
+    pub async fn status(&self) -> Result<NodeStatus> {
+        Ok(NodeStatus {
+            id: self.id,
+            addr: self.addr,
+            network: self.network.clone(),
+            uptime_secs: self.uptime().as_secs(),
+            client_only: self.is_client_only(),
+            known_nodes: self.routing_table.lock().await.len(),
+        })
+    }
+
+    /// Returns the contents of all non-empty k-buckets.
+    pub async fn routing_table_dump(&self) -> Vec<BucketDump> {
+        BucketDump::of(&*self.routing_table.lock().await)
     }
 
     /// Starts the node's RPC server to handle incoming requests.
     ///
     /// This method runs indefinitely, processing incoming RPCs according to the
-    /// Kademlia protocol specification.
+    /// Kademlia protocol specification, hands stored keys over to closer nodes
+    /// as they join, repairs erasure-coded objects, relays gossip and passes
+    /// application messages to their handlers.
     pub async fn run(&self) -> Result<()> {
-        // Wrap routing table in Arc<Mutex> for shared access
-        let routing_table = Arc::new(Mutex::new(self.routing_table.clone()));
-
         // Start the RPC server with all required components
-        self.rpc_server
-            .start(self.id, self.storage.clone(), routing_table)
+        let server = self.rpc_server.start(
+            self.id,
+            self.storage.clone(),
+            Arc::clone(&self.routing_table),
+        );
+        tokio::try_join!(
+            server,
+            self.handoff_loop(),
+            self.repair_loop(),
+            self.gossip_loop(),
+            self.app_loop()
+        )?;
+        Ok(())
+    }
+
+    /// Sends stored keys to newly seen contacts that should now hold them.
+    ///
+    /// At most [`HANDOFF_CHECKS_PER_INTERVAL`] keys are checked and
+    /// [`HANDOFF_STORES_PER_INTERVAL`] keys are sent per [`HANDOFF_INTERVAL`];
+    /// the rest wait in a queue. Failures are logged and retried by the next
+    /// republish, so they never stop the node.
+    async fn handoff_loop(&self) -> Result<()> {
+        let mut queue = HandoffQueue::default();
+        // Keys stored when planning started, kept until all contacts are planned
+        let mut keys = Vec::new();
+        let mut interval = tokio::time::interval(HANDOFF_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            {
+                let mut routing_table = self.routing_table.lock().await;
+                let contacts = routing_table.take_new_contacts();
+                queue.add_contacts(&routing_table, contacts);
+            }
+            if !queue.is_planning() {
+                keys.clear();
+            } else if keys.is_empty() {
+                keys = match self.storage.keys() {
+                    Ok(keys) => keys,
+                    Err(e) => {
+                        log::warn!("Failed to list keys to hand over: {}", e);
+                        continue;
+                    }
+                };
+            }
+
+            let batch = {
+                let routing_table = self.routing_table.lock().await;
+                let planned =
+                    queue.plan_contacts(&routing_table, &keys, HANDOFF_CHECKS_PER_INTERVAL);
+                if planned > 0 {
+                    log::debug!("Planned {} handoffs", planned);
+                }
+                let batch = queue.next_batch(HANDOFF_STORES_PER_INTERVAL);
+                if queue.len() > 0 {
+                    log::debug!("{} handoffs waiting", queue.len());
+                }
+                batch
+            };
+
+            let stores = batch.into_iter().map(|handoff| async move {
+                // The value may have expired since the handoff was planned
+                let value = match self.storage.get(&handoff.key) {
+                    Ok(Some(value)) => value,
+                    Ok(None) => return,
+                    Err(e) => {
+                        log::warn!("Failed to read {} for a handoff: {}", handoff.key, e);
+                        return;
+                    }
+                };
+                let mode = ValueMode::detect(&handoff.key, &value);
+                if let Err(e) = self
+                    .rpc_client
+                    .store(self.id, handoff.addr, handoff.key, value, mode)
+                    .await
+                {
+                    log::debug!(
+                        "Failed to hand {} over to {}: {}",
+                        handoff.key,
+                        handoff.node_id,
+                        e
+                    );
+                }
+            });
+            join_all(stores).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::topic_key;
+    use crate::KEY_SIZE;
+
+    #[tokio::test]
+    async fn test_find_value_returns_newest_signed_record() {
+        let mut nodes = Vec::new();
+        for _ in 0..3 {
+            let node = Node::with_storage("127.0.0.1:0".parse().unwrap(), Storage::in_memory())
+                .await
+                .unwrap();
+            nodes.push(Arc::new(node));
+        }
+        let running: Vec<_> = nodes
+            .iter()
+            .map(|node| {
+                let node = Arc::clone(node);
+                tokio::spawn(async move { node.run().await })
+            })
+            .collect();
+
+        // One holder missed the update and still has the first version
+        let keypair = crate::integrity::Keypair::generate();
+        let old = SignedRecord::new(&keypair, b"profile", 1, b"v1".to_vec());
+        let new = SignedRecord::new(&keypair, b"profile", 2, b"v2".to_vec());
+        let key = old.key();
+        for (holder, record) in nodes[1..].iter().zip([&old, &new]) {
+            holder
+                .storage()
+                .store(key, record.encode().unwrap(), Duration::from_secs(60))
+                .unwrap();
+            assert!(nodes[0].add_peer(holder.id(), holder.addr()).await.unwrap());
+        }
+
+        let value = nodes[0].find_value(key, ValueMode::Signed).await.unwrap();
+        assert_eq!(value, Some(new.encode().unwrap()));
+        for task in running {
+            task.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relaxed_split_keeps_close_contacts() {
+        let node = Node::with_storage("127.0.0.1:0".parse().unwrap(), Storage::in_memory())
             .await
+            .unwrap();
+        node.set_relaxed_split(true).await;
+
+        // All contacts fall into the half of the ID space not covering ours
+        let mut far = [0u8; KEY_SIZE / 8];
+        far.copy_from_slice(node.id().as_bytes());
+        far[0] ^= 0x80;
+        let mut table = node.routing_table.lock().await;
+        for i in 0..40u8 {
+            let mut bytes = far;
+            bytes[1] = i;
+            table.update(
+                NodeId::new(bytes),
+                SocketAddr::from(([127, 0, 0, 1], 9000 + i as u16)),
+            );
+        }
+        assert_eq!(table.len(), 40);
+    }
+
+    #[tokio::test]
+    async fn test_publish_reaches_other_node() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let publisher = Arc::new(
+            Node::with_storage(addr, Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        let subscriber = Arc::new(
+            Node::with_storage(addr, Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        let running: Vec<_> = [&publisher, &subscriber]
+            .into_iter()
+            .map(|node| {
+                let node = Arc::clone(node);
+                tokio::spawn(async move { node.run().await })
+            })
+            .collect();
+
+        let topic = topic_key("news");
+        let mut messages = subscriber.subscribe(topic);
+        assert!(publisher
+            .add_peer(subscriber.id(), subscriber.addr())
+            .await
+            .unwrap());
+        let id = publisher.publish(topic, b"hello".to_vec()).await.unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(5), messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id(), id);
+        assert_eq!(message.origin, publisher.id());
+        assert_eq!(message.payload, b"hello");
+        for task in running {
+            task.abort();
+        }
     }
 }
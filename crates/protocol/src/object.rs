@@ -0,0 +1,392 @@
+//! Objects larger than a single value, stored as chunks plus a manifest.
+//!
+//! A value has to fit into one RPC datagram. Larger blobs are split into
+//! fixed-size chunks, each stored as a content-addressed value, and a manifest
+//! listing the chunk hashes, their sizes and the total length is stored the same
+//! way. The key of the manifest identifies the object. Since every piece is
+//! content-addressed, a reader can verify each chunk on its own as it arrives
+//! and fetch chunks from different peers in parallel.
+//!
+//! Objects are either replicated, with every chunk stored on the k closest
+//! nodes to its key, or erasure-coded (see [`crate::erasure`]): the object is
+//! cut into stripes, each stripe is coded into shards, and each shard is stored
+//! once on a distinct node. The manifest then lists the shards stripe by stripe.
+
+use crate::erasure::ErasureConfig;
+use crate::integrity::content_key;
+use crate::Key;
+use anyhow::{anyhow, bail, Result};
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Size of the chunks objects are split into, chosen to fit into one datagram.
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Number of chunks stored or fetched concurrently.
+pub const PARALLEL_CHUNKS: usize = 8;
+
+/// Interval at which nodes check the erasure-coded objects they are
+/// responsible for and recreate lost shards.
+pub const REPAIR_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Maximum number of chunks one manifest may list, so that the manifest
+/// itself still fits into one value.
+pub const MAX_CHUNKS: usize = CHUNK_SIZE / 32;
+
+/// Verified pieces of an object in object order, see [`crate::Node::get_object`].
+///
+/// Replicated objects yield one piece per chunk, erasure-coded objects one per stripe.
+pub type ChunkStream<'a> = BoxStream<'a, Result<Vec<u8>>>;
+
+/// Marks values as object manifests of this format version.
+const MANIFEST_MAGIC: [u8; 4] = *b"COM1";
+
+/// A chunk listed in a manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// Content-addressed key of the chunk
+    pub key: Key,
+    /// Length of the chunk in bytes
+    pub len: u32,
+}
+
+/// How an object is replicated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Redundancy {
+    /// Every chunk is stored on the k closest nodes to its key
+    #[default]
+    Replicated,
+    /// Every stripe is coded into shards stored once each on distinct nodes
+    ErasureCoded(ErasureConfig),
+}
+
+/// Layout of an erasure-coded object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureLayout {
+    /// How each stripe is coded
+    pub config: ErasureConfig,
+    /// Number of object bytes per stripe; the last stripe may be shorter
+    pub stripe_len: u64,
+}
+
+/// One stripe of an erasure-coded object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stripe<'a> {
+    /// Index of the first shard of the stripe in [`ObjectManifest::chunks`]
+    pub first_shard: usize,
+    /// The shards of the stripe, data shards first
+    pub shards: &'a [ChunkRef],
+    /// Number of object bytes the stripe holds
+    pub data_len: usize,
+}
+
+/// Describes how an object is split into chunks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectManifest {
+    /// Always [`MANIFEST_MAGIC`]
+    magic: [u8; 4],
+    /// Total length of the object in bytes
+    pub total_len: u64,
+    /// The chunks in object order, or the shards of every stripe in stripe order
+    pub chunks: Vec<ChunkRef>,
+    /// Layout of the stripes, if the object is erasure-coded
+    pub erasure: Option<ErasureLayout>,
+}
+
+impl ObjectManifest {
+    /// Splits a blob into chunks of at most `chunk_size` bytes.
+    ///
+    /// # Returns
+    /// * `Result<(Self, Vec<Vec<u8>>)>` - The manifest and the chunks in object
+    ///   order, or an error if the blob needs more than [`MAX_CHUNKS`] chunks
+    pub fn split(data: &[u8], chunk_size: usize) -> Result<(Self, Vec<Vec<u8>>)> {
+        if chunk_size == 0 || chunk_size > CHUNK_SIZE {
+            bail!("Chunk size must be between 1 and {} bytes", CHUNK_SIZE);
+        }
+        let chunks: Vec<Vec<u8>> = data.chunks(chunk_size).map(<[u8]>::to_vec).collect();
+        if chunks.len() > MAX_CHUNKS {
+            bail!(
+                "Object of {} bytes needs {} chunks, at most {} are supported",
+                data.len(),
+                chunks.len(),
+                MAX_CHUNKS
+            );
+        }
+
+        let manifest = ObjectManifest {
+            magic: MANIFEST_MAGIC,
+            total_len: data.len() as u64,
+            chunks: chunk_refs(&chunks),
+            erasure: None,
+        };
+        Ok((manifest, chunks))
+    }
+
+    /// Splits a blob into stripes and codes each into shards.
+    ///
+    /// Stripes are sized so that every shard fits into one value.
+    ///
+    /// # Returns
+    /// * `Result<(Self, Vec<Vec<u8>>)>` - The manifest and the shards of all
+    ///   stripes in stripe order
+    pub fn split_erasure(data: &[u8], config: ErasureConfig) -> Result<(Self, Vec<Vec<u8>>)> {
+        let stripe_len = CHUNK_SIZE * config.data_shards as usize;
+        let stripes = data.len().div_ceil(stripe_len);
+        if stripes * config.total_shards() > MAX_CHUNKS {
+            bail!(
+                "Object of {} bytes needs {} shards, at most {} are supported",
+                data.len(),
+                stripes * config.total_shards(),
+                MAX_CHUNKS
+            );
+        }
+
+        let mut shards = Vec::with_capacity(stripes * config.total_shards());
+        for stripe in data.chunks(stripe_len) {
+            shards.extend(config.encode(stripe)?);
+        }
+        let manifest = ObjectManifest {
+            magic: MANIFEST_MAGIC,
+            total_len: data.len() as u64,
+            chunks: chunk_refs(&shards),
+            erasure: Some(ErasureLayout {
+                config,
+                stripe_len: stripe_len as u64,
+            }),
+        };
+        Ok((manifest, shards))
+    }
+
+    /// Returns the stripes of an erasure-coded object, or none for a
+    /// replicated one.
+    pub fn stripes(&self) -> Vec<Stripe<'_>> {
+        let Some(layout) = self.erasure else {
+            return Vec::new();
+        };
+        let shard_count = layout.config.total_shards();
+        self.chunks
+            .chunks(shard_count)
+            .enumerate()
+            .map(|(index, shards)| {
+                let start = index as u64 * layout.stripe_len;
+                Stripe {
+                    first_shard: index * shard_count,
+                    shards,
+                    data_len: layout.stripe_len.min(self.total_len - start) as usize,
+                }
+            })
+            .collect()
+    }
+
+    /// Encodes the manifest as stored in the DHT.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Decodes a manifest, checking that its chunk sizes add up and stay within
+    /// the limits [`ObjectManifest::split`] enforces.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let manifest: ObjectManifest =
+            bincode::deserialize(bytes).map_err(|e| anyhow!("Malformed object manifest: {}", e))?;
+        if manifest.magic != MANIFEST_MAGIC {
+            bail!("Value is not an object manifest");
+        }
+        if manifest.chunks.len() > MAX_CHUNKS {
+            bail!(
+                "Manifest lists {} chunks, more than the limit of {}",
+                manifest.chunks.len(),
+                MAX_CHUNKS
+            );
+        }
+        if manifest
+            .chunks
+            .iter()
+            .any(|chunk| chunk.len as usize > CHUNK_SIZE)
+        {
+            bail!("Manifest lists chunks larger than {} bytes", CHUNK_SIZE);
+        }
+        match manifest.erasure {
+            None => {
+                let chunk_total: u64 = manifest.chunks.iter().map(|chunk| chunk.len as u64).sum();
+                if chunk_total != manifest.total_len {
+                    bail!(
+                        "Manifest chunks add up to {} bytes instead of {}",
+                        chunk_total,
+                        manifest.total_len
+                    );
+                }
+            }
+            Some(layout) => manifest.check_stripes(layout)?,
+        }
+        Ok(manifest)
+    }
+
+    /// Returns whether a value is a valid manifest of an erasure-coded object.
+    pub fn is_erasure_coded(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MANIFEST_MAGIC)
+            && Self::decode(bytes).is_ok_and(|manifest| manifest.erasure.is_some())
+    }
+
+    /// Checks that the shards of an erasure-coded manifest match its layout.
+    fn check_stripes(&self, layout: ErasureLayout) -> Result<()> {
+        let config = ErasureConfig::new(layout.config.data_shards, layout.config.parity_shards)?;
+        if layout.stripe_len == 0 {
+            bail!("Manifest has empty stripes");
+        }
+        let shards = self
+            .total_len
+            .div_ceil(layout.stripe_len)
+            .saturating_mul(config.total_shards() as u64);
+        if self.chunks.len() as u64 != shards {
+            bail!(
+                "Manifest lists {} shards instead of {}",
+                self.chunks.len(),
+                shards
+            );
+        }
+        for stripe in self.stripes() {
+            let shard_len = config.shard_len(stripe.data_len);
+            if stripe
+                .shards
+                .iter()
+                .any(|shard| shard.len as usize != shard_len)
+            {
+                bail!("Manifest shards don't match their stripe length");
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the key the manifest is stored under.
+    pub fn key(&self) -> Result<Key> {
+        Ok(content_key(&self.encode()?))
+    }
+
+    /// Checks that fetched bytes are the chunk at `index`.
+    pub fn verify_chunk(&self, index: usize, chunk: &[u8]) -> Result<()> {
+        let expected = self
+            .chunks
+            .get(index)
+            .ok_or_else(|| anyhow!("Object has no chunk {}", index))?;
+        if chunk.len() != expected.len as usize || content_key(chunk) != expected.key {
+            bail!("Chunk {} does not match the manifest", index);
+        }
+        Ok(())
+    }
+}
+
+/// Lists the keys and lengths of chunks.
+fn chunk_refs(chunks: &[Vec<u8>]) -> Vec<ChunkRef> {
+    chunks
+        .iter()
+        .map(|chunk| ChunkRef {
+            key: content_key(chunk),
+            len: chunk.len() as u32,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_verify() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let (manifest, chunks) = ObjectManifest::split(&data, 4000).unwrap();
+
+        assert_eq!(manifest.total_len, 10_000);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(manifest.chunks[2].len, 10_000 - 2 * 4000);
+        assert_eq!(chunks.concat(), data);
+        for (index, chunk) in chunks.iter().enumerate() {
+            manifest.verify_chunk(index, chunk).unwrap();
+        }
+        assert!(manifest.verify_chunk(0, &chunks[1]).is_err());
+        assert!(manifest.verify_chunk(3, &chunks[0]).is_err());
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let (manifest, _) = ObjectManifest::split(b"small object", CHUNK_SIZE).unwrap();
+        let bytes = manifest.encode().unwrap();
+        assert_eq!(ObjectManifest::decode(&bytes).unwrap(), manifest);
+        assert_eq!(manifest.key().unwrap(), content_key(&bytes));
+
+        let mut inconsistent = manifest.clone();
+        inconsistent.total_len += 1;
+        assert!(ObjectManifest::decode(&inconsistent.encode().unwrap()).is_err());
+        assert!(ObjectManifest::decode(b"not a manifest").is_err());
+    }
+
+    #[test]
+    fn test_decode_enforces_limits() {
+        let oversized = ObjectManifest {
+            magic: MANIFEST_MAGIC,
+            total_len: 2 * u32::MAX as u64,
+            chunks: vec![
+                ChunkRef {
+                    key: Key::random(),
+                    len: u32::MAX,
+                };
+                2
+            ],
+            erasure: None,
+        };
+        assert!(ObjectManifest::decode(&oversized.encode().unwrap()).is_err());
+
+        let (mut manifest, _) = ObjectManifest::split(b"x", CHUNK_SIZE).unwrap();
+        manifest.chunks = vec![manifest.chunks[0]; MAX_CHUNKS + 1];
+        manifest.total_len = MAX_CHUNKS as u64 + 1;
+        assert!(ObjectManifest::decode(&manifest.encode().unwrap()).is_err());
+
+        // A layout claiming more stripes than fit into memory is refused
+        let (mut erasure, _) =
+            ObjectManifest::split_erasure(b"x", ErasureConfig::new(2, 1).unwrap()).unwrap();
+        erasure.total_len = u64::MAX;
+        erasure.erasure.as_mut().unwrap().stripe_len = 1;
+        assert!(ObjectManifest::decode(&erasure.encode().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_erasure_coded_manifest() {
+        let config = ErasureConfig::new(2, 1).unwrap();
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 3) as u32)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let (manifest, shards) = ObjectManifest::split_erasure(&data, config).unwrap();
+
+        // Two stripes: a full one and one holding the remaining chunk
+        let stripes = manifest.stripes();
+        assert_eq!(stripes.len(), 2);
+        assert_eq!(shards.len(), 6);
+        assert_eq!(stripes[0].data_len, CHUNK_SIZE * 2);
+        assert_eq!(stripes[1].data_len, CHUNK_SIZE);
+        assert_eq!(stripes[1].first_shard, 3);
+        assert_eq!(stripes[1].shards[0].len as usize, CHUNK_SIZE / 2);
+
+        let decoded = ObjectManifest::decode(&manifest.encode().unwrap()).unwrap();
+        assert_eq!(decoded, manifest);
+
+        let mut rebuilt = Vec::new();
+        for stripe in &stripes {
+            let mut partial: Vec<Option<Vec<u8>>> = shards
+                [stripe.first_shard..stripe.first_shard + 3]
+                .iter()
+                .cloned()
+                .map(Some)
+                .collect();
+            partial[0] = None;
+            rebuilt.extend(config.decode(partial, stripe.data_len).unwrap());
+        }
+        assert_eq!(rebuilt, data);
+    }
+
+    #[test]
+    fn test_rejects_oversized_objects() {
+        let data = vec![0u8; (MAX_CHUNKS + 1) * 16];
+        assert!(ObjectManifest::split(&data, 16).is_err());
+        assert!(ObjectManifest::split(&data, CHUNK_SIZE + 1).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Topic-based publish/subscribe over the overlay.
+//!
+//! Topics are identified by keys. A published message spreads epidemically:
+//! every node that receives it for the first time forwards it to
+//! [`GOSSIP_FANOUT`] random peers from its routing table, until its hop limit is
+//! used up. Nodes remember the IDs of recent messages to drop duplicates, and
+//! drop messages older than [`MESSAGE_TTL`], so old messages can't be replayed
+//! once they have left the duplicate cache.
+//!
+//! Nodes subscribed to a topic deliver its messages to their subscribers and
+//! can install a validator per topic. Messages failing validation are neither
+//! delivered nor forwarded. Nodes not subscribed to a topic can't judge its
+//! messages and relay them unchecked.
+
+use crate::{Key, NodeId};
+use parking_lot::Mutex;
+use replicrypt::sha1_digest;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Number of peers each node forwards a new message to.
+pub const GOSSIP_FANOUT: usize = 6;
+
+/// Number of times a message may be forwarded after it was published.
+pub const HOP_LIMIT: u8 = 8;
+
+/// Age after which messages are dropped instead of delivered or forwarded.
+pub const MESSAGE_TTL: Duration = Duration::from_secs(300);
+
+/// Number of message IDs remembered to detect duplicates.
+const SEEN_CACHE_SIZE: usize = 8192;
+
+/// Number of messages buffered per topic for subscribers that fall behind.
+const SUBSCRIPTION_BUFFER: usize = 256;
+
+/// Returns the key of a topic with a human-readable name.
+pub fn topic_key(name: &str) -> Key {
+    let mut bytes = b"coreoverlay/topic/".to_vec();
+    bytes.extend_from_slice(name.as_bytes());
+    Key::new(sha1_digest(bytes))
+}
+
+/// A message published to a topic.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicMessage {
+    /// Topic the message was published to
+    pub topic: Key,
+    /// Node that published the message
+    pub origin: NodeId,
+    /// Counter of the publishing node, starting at a random value so that IDs
+    /// aren't reused after a restart
+    pub sequence: u64,
+    /// Time of publication in seconds since the Unix epoch
+    pub published_at: u64,
+    /// The application payload
+    pub payload: Vec<u8>,
+}
+
+impl TopicMessage {
+    /// Returns the ID used to detect duplicates of this message.
+    pub fn id(&self) -> Key {
+        let mut bytes = Vec::with_capacity(56);
+        bytes.extend_from_slice(self.topic.as_bytes());
+        bytes.extend_from_slice(self.origin.as_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.published_at.to_be_bytes());
+        Key::new(sha1_digest(bytes))
+    }
+
+    /// Returns whether the message is older than [`MESSAGE_TTL`].
+    ///
+    /// Messages claiming to come from the future are treated the same way, so
+    /// a wrong clock can't extend their lifetime.
+    pub fn is_expired(&self) -> bool {
+        let now = unix_secs();
+        let ttl = MESSAGE_TTL.as_secs();
+        self.published_at.saturating_add(ttl) < now || self.published_at > now.saturating_add(ttl)
+    }
+}
+
+/// A message on its way through the overlay.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct GossipEnvelope {
+    /// The message being spread
+    pub(crate) message: TopicMessage,
+    /// Number of further times the message may be forwarded
+    pub(crate) hops_left: u8,
+}
+
+/// Decides whether a message of a topic is accepted.
+pub type Validator = Arc<dyn Fn(&TopicMessage) -> bool + Send + Sync>;
+
+/// What happened to a received message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Disposition {
+    /// Accepted and to be forwarded in this envelope
+    Forward(GossipEnvelope),
+    /// Accepted, but the hop limit is used up
+    Final,
+    /// Seen before
+    Duplicate,
+    /// Older than [`MESSAGE_TTL`]
+    Expired,
+    /// Rejected by the topic's validator
+    Invalid,
+}
+
+/// Local state of a topic.
+struct TopicState {
+    /// Delivers messages to the subscribers
+    sender: broadcast::Sender<TopicMessage>,
+    /// Checks messages before they are delivered or forwarded
+    validator: Option<Validator>,
+}
+
+/// IDs of recently seen messages, oldest first.
+#[derive(Default)]
+struct SeenCache {
+    ids: HashSet<Key>,
+    order: VecDeque<Key>,
+}
+
+impl SeenCache {
+    /// Records an ID, returning whether it is new.
+    fn insert(&mut self, id: Key) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > SEEN_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Subscriptions and duplicate detection of a node.
+pub(crate) struct PubSub {
+    topics: Mutex<HashMap<Key, TopicState>>,
+    seen: Mutex<SeenCache>,
+    next_sequence: AtomicU64,
+}
+
+impl Default for PubSub {
+    /// Starts the sequence numbers at a random value, so messages published
+    /// after a restart don't collide with IDs peers still remember.
+    fn default() -> Self {
+        PubSub {
+            topics: Mutex::default(),
+            seen: Mutex::default(),
+            next_sequence: AtomicU64::new(rand::random()),
+        }
+    }
+}
+
+impl PubSub {
+    /// Subscribes to a topic, returning a receiver for its messages.
+    pub(crate) fn subscribe(&self, topic: Key) -> broadcast::Receiver<TopicMessage> {
+        self.topics
+            .lock()
+            .entry(topic)
+            .or_insert_with(|| TopicState {
+                sender: broadcast::channel(SUBSCRIPTION_BUFFER).0,
+                validator: None,
+            })
+            .sender
+            .subscribe()
+    }
+
+    /// Drops all subscriptions to a topic and its validator.
+    pub(crate) fn unsubscribe(&self, topic: &Key) {
+        self.topics.lock().remove(topic);
+    }
+
+    /// Installs the validator of a topic, subscribing to it if necessary.
+    pub(crate) fn set_validator(&self, topic: Key, validator: Validator) {
+        self.topics
+            .lock()
+            .entry(topic)
+            .or_insert_with(|| TopicState {
+                sender: broadcast::channel(SUBSCRIPTION_BUFFER).0,
+                validator: None,
+            })
+            .validator = Some(validator);
+    }
+
+    /// Creates a new message of this node.
+    pub(crate) fn create(&self, origin: NodeId, topic: Key, payload: Vec<u8>) -> GossipEnvelope {
+        GossipEnvelope {
+            message: TopicMessage {
+                topic,
+                origin,
+                sequence: self.next_sequence.fetch_add(1, Ordering::Relaxed),
+                published_at: unix_secs(),
+                payload,
+            },
+            hops_left: HOP_LIMIT,
+        }
+    }
+
+    /// Handles a published or received message.
+    ///
+    /// New, valid messages are delivered to the topic's subscribers, if any.
+    pub(crate) fn accept(&self, envelope: GossipEnvelope) -> Disposition {
+        let message = &envelope.message;
+        if message.is_expired() {
+            return Disposition::Expired;
+        }
+        if !self.seen.lock().insert(message.id()) {
+            return Disposition::Duplicate;
+        }
+
+        if let Some(topic) = self.topics.lock().get(&message.topic) {
+            if let Some(validator) = &topic.validator {
+                if !validator(message) {
+                    return Disposition::Invalid;
+                }
+            }
+            // Nobody listening is fine; the node still relays the message
+            let _ = topic.sender.send(message.clone());
+        }
+
+        match envelope.hops_left.checked_sub(1) {
+            Some(hops_left) => Disposition::Forward(GossipEnvelope {
+                hops_left,
+                ..envelope
+            }),
+            None => Disposition::Final,
+        }
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivers_once_and_forwards() {
+        let pubsub = PubSub::default();
+        let topic = topic_key("events");
+        let mut subscriber = pubsub.subscribe(topic);
+
+        let envelope = pubsub.create(NodeId::random(), topic, b"hello".to_vec());
+        let Disposition::Forward(forwarded) = pubsub.accept(envelope.clone()) else {
+            panic!("new message not forwarded");
+        };
+        assert_eq!(forwarded.hops_left, HOP_LIMIT - 1);
+        assert_eq!(subscriber.try_recv().unwrap().payload, b"hello");
+
+        assert_eq!(pubsub.accept(envelope), Disposition::Duplicate);
+        assert!(subscriber.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_message_ids_differ_across_restarts() {
+        let origin = NodeId::random();
+        let topic = topic_key("t");
+        let before = PubSub::default().create(origin, topic, Vec::new());
+        let after = PubSub::default().create(origin, topic, Vec::new());
+        assert_ne!(before.message.id(), after.message.id());
+    }
+
+    #[test]
+    fn test_hop_limit_and_expiry() {
+        let pubsub = PubSub::default();
+        let mut envelope = pubsub.create(NodeId::random(), topic_key("t"), Vec::new());
+        envelope.hops_left = 0;
+        assert_eq!(pubsub.accept(envelope), Disposition::Final);
+
+        let mut old = pubsub.create(NodeId::random(), topic_key("t"), Vec::new());
+        old.message.published_at -= MESSAGE_TTL.as_secs() + 1;
+        assert_eq!(pubsub.accept(old), Disposition::Expired);
+
+        // Timestamps far in the future don't overflow
+        let mut future = pubsub.create(NodeId::random(), topic_key("t"), Vec::new());
+        future.message.published_at = u64::MAX;
+        assert!(future.message.is_expired());
+        assert_eq!(pubsub.accept(future), Disposition::Expired);
+    }
+
+    #[test]
+    fn test_validator_blocks_delivery() {
+        let pubsub = PubSub::default();
+        let topic = topic_key("validated");
+        let mut subscriber = pubsub.subscribe(topic);
+        pubsub.set_validator(topic, Arc::new(|message| !message.payload.is_empty()));
+
+        let empty = pubsub.create(NodeId::random(), topic, Vec::new());
+        assert_eq!(pubsub.accept(empty), Disposition::Invalid);
+        assert!(subscriber.try_recv().is_err());
+
+        // Topics without subscribers are relayed unchecked
+        let other = pubsub.create(NodeId::random(), topic_key("other"), Vec::new());
+        assert!(matches!(pubsub.accept(other), Disposition::Forward(_)));
+    }
+
+    #[test]
+    fn test_seen_cache_is_bounded() {
+        let mut seen = SeenCache::default();
+        let first = Key::random();
+        assert!(seen.insert(first));
+        for _ in 0..SEEN_CACHE_SIZE {
+            seen.insert(Key::random());
+        }
+        assert_eq!(seen.order.len(), SEEN_CACHE_SIZE);
+        assert!(seen.insert(first));
+    }
+}
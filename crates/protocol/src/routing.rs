@@ -1,15 +1,95 @@
 //! Implementation of Kademlia's routing table using k-buckets.
 //!
 //! The routing table is a key component of the Kademlia DHT, organizing known nodes
-//! based on their XOR distance from the local node. It uses a binary tree-like structure
-//! where each k-bucket stores up to k nodes with specific distance properties.
+//! based on their XOR distance from the local node. It uses a binary tree of k-buckets
+//! that split as the table fills, where each k-bucket stores up to k nodes whose IDs
+//! share a prefix.
+//!
+//! The table also remembers how every contact behaved: how many RPCs it answered,
+//! how fast, how often it timed out and whether it returned invalid data. Responsive
+//! contacts are preferred in lookups, contacts that keep failing are evicted, and
+//! contacts caught returning bad data are banned for a while.
 
 use crate::KEY_SIZE;
 use crate::{Distance, NodeId, K};
-use std::collections::VecDeque;
+use rand::seq::IndexedRandom;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::time::Instant;
 
+/// Number of consecutive failed RPCs after which a contact is evicted.
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+/// How long a peer that returned invalid data is ignored.
+pub const BAN_DURATION: Duration = Duration::from_secs(3600);
+
+/// Average latency above which a contact is considered slow.
+pub const SLOW_PEER_LATENCY: Duration = Duration::from_secs(1);
+
+/// Maximum number of newly added contacts remembered until they are taken.
+const MAX_NEW_CONTACTS: usize = 256;
+
+/// Weight of a new latency sample in the moving average, in percent.
+const LATENCY_SAMPLE_WEIGHT: u32 = 20;
+
+/// Observed behavior of a contact.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// Number of RPCs the peer answered
+    pub successes: u32,
+    /// Number of RPCs the peer didn't answer in time
+    pub timeouts: u32,
+    /// Number of failed RPCs since the last successful one
+    pub consecutive_failures: u32,
+    /// Number of responses that failed verification
+    pub bad_data: u32,
+    /// Moving average of the response latency, once a response was timed
+    pub latency: Option<Duration>,
+}
+
+impl PeerStats {
+    /// Returns the share of RPCs the peer answered, if any were sent.
+    pub fn success_rate(&self) -> Option<f64> {
+        let total = self.successes + self.timeouts;
+        (total > 0).then(|| self.successes as f64 / total as f64)
+    }
+
+    /// Ranks the peer for lookups; lower tiers are preferred.
+    ///
+    /// Tier 0 holds peers without known problems, tier 1 slow or unreliable
+    /// peers, and tier 2 peers whose last RPC failed.
+    pub fn tier(&self) -> u8 {
+        if self.consecutive_failures > 0 {
+            2
+        } else if self
+            .latency
+            .is_some_and(|latency| latency > SLOW_PEER_LATENCY)
+            || self.success_rate().is_some_and(|rate| rate < 0.5)
+        {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn record_success(&mut self, latency: Duration) {
+        self.successes = self.successes.saturating_add(1);
+        self.consecutive_failures = 0;
+        self.latency = Some(match self.latency {
+            Some(average) => {
+                (average * (100 - LATENCY_SAMPLE_WEIGHT) + latency * LATENCY_SAMPLE_WEIGHT) / 100
+            }
+            None => latency,
+        });
+    }
+
+    fn record_timeout(&mut self) {
+        self.timeouts = self.timeouts.saturating_add(1);
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    }
+}
+
 /// Information about a node in the Kademlia network.
 ///
 /// This struct contains all necessary information to identify and communicate with
@@ -17,11 +97,13 @@ use tokio::time::Instant;
 /// - A unique node identifier (NodeId)
 /// - Network address information (SocketAddr)
 /// - Timestamp of last successful contact
+/// - Statistics about past RPCs
 ///
 /// # Fields
 /// * `node_id` - The unique 160-bit identifier of the node
 /// * `sock_addr` - The network address (IP and port) used for communication
 /// * `last_seen` - Timestamp of the last successful contact with this node
+/// * `stats` - How the node behaved so far
 ///
 #[derive(Clone)]
 pub struct NodeInfo {
@@ -31,6 +113,8 @@ pub struct NodeInfo {
     pub sock_addr: SocketAddr,
     /// Timestamp of the last successful contact with this node
     pub last_seen: Instant,
+    /// How the node behaved so far
+    pub stats: PeerStats,
 }
 
 /// A k-bucket in the Kademlia routing table that stores up to k nodes.
@@ -40,93 +124,418 @@ pub struct NodeInfo {
 /// long-lived nodes over newer nodes when the bucket is full.
 ///
 /// # Properties
+/// - Covers all IDs starting with a given prefix of `depth` bits
 /// - Stores up to k nodes (where k is a system-wide parameter)
 /// - Implements a least-recently seen eviction policy
 /// - Orders nodes based on last contact time
 #[derive(Clone)]
 pub struct KBucket {
+    /// Prefix shared by all IDs in the bucket's range; bits past `depth` are zero
+    prefix: NodeId,
+    /// Number of leading bits of `prefix` that define the range
+    depth: usize,
     /// Queue of nodes in this bucket, ordered by time last seen
-    nodes: VecDeque<NodeId>,
+    nodes: VecDeque<NodeInfo>,
 }
 
 impl KBucket {
-    /// Creates a new, empty k-bucket with capacity K.
+    /// Creates a new, empty k-bucket with capacity K covering the whole ID space.
     ///
     /// # Returns
     /// A new KBucket instance that can store up to K nodes.
     pub fn new() -> Self {
+        Self::with_range(NodeId::new([0u8; KEY_SIZE / 8]), 0)
+    }
+
+    /// Creates an empty k-bucket covering the IDs starting with the first `depth`
+    /// bits of `prefix`.
+    fn with_range(prefix: NodeId, depth: usize) -> Self {
         KBucket {
+            prefix: mask(&prefix, depth),
+            depth,
             nodes: VecDeque::with_capacity(K),
         }
     }
 
+    /// Returns the number of prefix bits defining the range of this bucket.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns whether an ID falls into the range of this bucket.
+    pub fn covers(&self, id: &NodeId) -> bool {
+        mask(id, self.depth) == self.prefix
+    }
+
+    /// Returns the smallest XOR distance between `target` and any ID in the range.
+    fn min_distance(&self, target: &NodeId) -> Distance {
+        Distance::between(&self.prefix, &mask(target, self.depth))
+    }
+
+    /// Generates a random ID in the range of this bucket.
+    fn random_id(&self) -> NodeId {
+        let mut bytes = [0u8; KEY_SIZE / 8];
+        bytes.copy_from_slice(NodeId::random().as_bytes());
+        let prefix = self.prefix.as_bytes();
+
+        for bit in 0..self.depth {
+            let (byte, mask) = (bit / 8, 0x80u8 >> (bit % 8));
+            bytes[byte] = (bytes[byte] & !mask) | (prefix[byte] & mask);
+        }
+
+        NodeId::new(bytes)
+    }
+
+    /// Splits the bucket into its two halves, farther half from `own_id` first.
+    ///
+    /// Nodes keep their relative order, so the halves stay least-recently seen first.
+    fn split(self, own_id: &NodeId) -> (KBucket, KBucket) {
+        let bit = self.depth;
+        let (byte, bit_mask) = (bit / 8, 0x80u8 >> (bit % 8));
+        let mut upper = [0u8; KEY_SIZE / 8];
+        upper.copy_from_slice(self.prefix.as_bytes());
+        upper[byte] |= bit_mask;
+
+        let mut lower = KBucket::with_range(self.prefix, bit + 1);
+        let mut upper = KBucket::with_range(NodeId::new(upper), bit + 1);
+        for info in self.nodes {
+            if info.node_id.as_bytes()[byte] & bit_mask == 0 {
+                lower.nodes.push_back(info);
+            } else {
+                upper.nodes.push_back(info);
+            }
+        }
+
+        if own_id.as_bytes()[byte] & bit_mask == 0 {
+            (upper, lower)
+        } else {
+            (lower, upper)
+        }
+    }
+
     /// Updates the k-bucket with information about a node.
     ///
     /// This implementation follows the Kademlia paper's k-bucket update algorithm:
     /// 1. If the node already exists, move it to the tail (most-recently seen)
     /// 2. If the bucket isn't full, add the node to the tail
-    /// 3. If the bucket is full, drop the new node (favoring existing nodes)
+    /// 3. If the bucket is full, replace the least-recently seen node whose last
+    ///    RPC failed, or drop the new node (favoring existing nodes)
+    ///
+    /// Full buckets that may split are split by the [`RoutingTable`] before
+    /// this is called.
     ///
     /// # Arguments
     /// * `node` - The NodeId to update or insert
+    /// * `addr` - The address the node was last seen from
+    ///
+    /// # Returns
+    /// Whether the node was not in the bucket before and has been added
     ///
     /// # Note
     /// This implementation uses a simplified eviction policy. The original Kademlia
     /// paper suggests pinging the least-recently seen node and only evicting it if
-    /// it fails to respond.
-    pub fn update(&mut self, node: NodeId) {
-        if let Some(pos) = self.nodes.iter().position(|n| *n == node) {
-            self.nodes.remove(pos);
+    /// it fails to respond; here the failure has to be observed by a previous RPC.
+    pub fn update(&mut self, node: NodeId, addr: SocketAddr) -> bool {
+        let stats = match self.nodes.iter().position(|n| n.node_id == node) {
+            Some(pos) => self.nodes.remove(pos).map(|info| info.stats),
+            None => None,
+        };
+
+        if self.nodes.len() >= K {
+            match self
+                .nodes
+                .iter()
+                .position(|n| n.stats.consecutive_failures > 0)
+            {
+                Some(pos) => {
+                    self.nodes.remove(pos);
+                }
+                None => return false,
+            }
         }
 
-        if self.nodes.len() < K {
-            self.nodes.push_back(node);
+        let added = stats.is_none();
+        self.nodes.push_back(NodeInfo {
+            node_id: node,
+            sock_addr: addr,
+            last_seen: Instant::now(),
+            stats: stats.unwrap_or_default(),
+        });
+        added
+    }
+
+    /// Returns whether a node is in the bucket.
+    fn contains(&self, node: &NodeId) -> bool {
+        self.nodes.iter().any(|n| n.node_id == *node)
+    }
+
+    /// Returns the entry of a node, if it is in the bucket.
+    fn get_mut(&mut self, node: &NodeId) -> Option<&mut NodeInfo> {
+        self.nodes.iter_mut().find(|n| n.node_id == *node)
+    }
+
+    /// Removes a node from the bucket, returning whether it was present.
+    pub fn remove(&mut self, node: &NodeId) -> bool {
+        match self.nodes.iter().position(|n| n.node_id == *node) {
+            Some(pos) => {
+                self.nodes.remove(pos);
+                true
+            }
+            None => false,
         }
     }
+
+    /// Returns the nodes in this bucket, least-recently seen first.
+    pub fn nodes(&self) -> impl Iterator<Item = &NodeInfo> {
+        self.nodes.iter()
+    }
+
+    /// Returns the number of nodes in this bucket.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns whether this bucket contains no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl Default for KBucket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clears all bits of an ID after the first `depth` bits.
+fn mask(id: &NodeId, depth: usize) -> NodeId {
+    let mut bytes = [0u8; KEY_SIZE / 8];
+    for (i, (out, byte)) in bytes.iter_mut().zip(id.as_bytes()).enumerate() {
+        let kept = depth.saturating_sub(i * 8).min(8);
+        *out = if kept == 0 {
+            0
+        } else {
+            byte & (0xFFu8 << (8 - kept))
+        };
+    }
+    NodeId::new(bytes)
 }
 
 /// The Kademlia routing table, consisting of k-buckets organized by XOR distance.
 ///
-/// The routing table maintains k-buckets based on the XOR distance between the local
-/// node's ID and other nodes' IDs. This structure allows efficient lookups with
-/// logarithmic complexity.
+/// The routing table is the binary tree of section 2.4 of the Kademlia paper. It
+/// starts with a single bucket covering the whole ID space. When a bucket is full
+/// and its range includes our own ID, it is split in two, so the table keeps
+/// detailed knowledge of the nodes close to us and coarser knowledge of the rest.
 ///
 /// # Properties
-/// - Contains KEY_SIZE k-buckets (one for each bit of the node ID)
-/// - Each bucket stores nodes with specific XOR distance properties
+/// - Buckets partition the ID space; each covers the IDs with a given prefix
+/// - Buckets are kept ordered from farthest to closest to the local node
+/// - With relaxed splitting, buckets not covering our own ID also split while
+///   they may hold one of our k closest nodes, which keeps highly unbalanced
+///   trees from dropping close contacts
 /// - Provides O(log N) lookup complexity for network size N
 #[derive(Clone)]
 pub struct RoutingTable {
     /// ID of the local node
     node_id: NodeId,
-    /// Vector of k-buckets, indexed by the distance prefix length
+    /// k-buckets partitioning the ID space, farthest from the local node first
     buckets: Vec<KBucket>,
+    /// Whether buckets not covering our own ID may split
+    relaxed_split: bool,
+    /// Contacts added since [`RoutingTable::take_new_contacts`] was last called
+    new_contacts: Vec<(NodeId, SocketAddr)>,
+    /// Peers that misbehaved, with the time their ban ends
+    banned: HashMap<NodeId, Instant>,
 }
 
 impl RoutingTable {
     /// Creates a new routing table for the given local node ID.
     ///
-    /// Initializes KEY_SIZE empty k-buckets, one for each bit position
-    /// in the node ID space.
+    /// Starts with a single k-bucket covering the whole ID space.
     ///
     /// # Arguments
     /// * `node_id` - The ID of the local node
     pub fn new(node_id: NodeId) -> Self {
-        let buckets = (0..KEY_SIZE).map(|_| KBucket::new()).collect();
-        RoutingTable { node_id, buckets }
+        RoutingTable {
+            node_id,
+            buckets: vec![KBucket::new()],
+            relaxed_split: false,
+            new_contacts: Vec::new(),
+            banned: HashMap::new(),
+        }
+    }
+
+    /// Creates a new routing table that also splits buckets not covering our own ID
+    /// while they may hold one of our k closest nodes.
+    ///
+    /// # Arguments
+    /// * `node_id` - The ID of the local node
+    pub fn with_relaxed_split(node_id: NodeId) -> Self {
+        RoutingTable {
+            relaxed_split: true,
+            ..Self::new(node_id)
+        }
+    }
+
+    /// Sets whether buckets not covering our own ID may split.
+    ///
+    /// Only later splits are affected; buckets are never merged back.
+    pub fn set_relaxed_split(&mut self, relaxed: bool) {
+        self.relaxed_split = relaxed;
     }
 
     /// Updates the routing table with information about a node.
     ///
     /// # Arguments
     /// * `node` - The NodeId to update or insert
+    /// * `addr` - The address the node was last seen from
     ///
     /// # Implementation Details
-    /// 1. Calculates the appropriate bucket index based on XOR distance
-    /// 2. Updates the corresponding k-bucket
-    pub fn update(&mut self, node: NodeId) {
-        let bucket_index = self.bucket_index(&node);
-        self.buckets[bucket_index].update(node);
+    /// 1. Ignores banned nodes
+    /// 2. Finds the k-bucket whose range covers the node
+    /// 3. Splits that bucket while it is full and allowed to split
+    /// 4. Updates the k-bucket covering the node, remembering it if it is new
+    pub fn update(&mut self, node: NodeId, addr: SocketAddr) {
+        // Our own ID is never a contact
+        if node == self.node_id || self.is_banned(&node) {
+            return;
+        }
+
+        loop {
+            let index = self.bucket_index(&node);
+            let bucket = &self.buckets[index];
+            if bucket.contains(&node) || bucket.len() < K || !self.can_split(index) {
+                let added = self.buckets[index].update(node, addr);
+                if added && self.new_contacts.len() < MAX_NEW_CONTACTS {
+                    self.new_contacts.push((node, addr));
+                }
+                return;
+            }
+            self.split(index);
+        }
+    }
+
+    /// Returns whether the bucket at `index` may be split.
+    ///
+    /// The bucket covering our own ID always may. With relaxed splitting, other
+    /// buckets may as long as fewer than k known nodes are closer to us than
+    /// the bucket's range.
+    fn can_split(&self, index: usize) -> bool {
+        let bucket = &self.buckets[index];
+        if bucket.depth >= KEY_SIZE {
+            return false;
+        }
+        if bucket.covers(&self.node_id) {
+            return true;
+        }
+        self.relaxed_split
+            && self.buckets[index + 1..]
+                .iter()
+                .map(KBucket::len)
+                .sum::<usize>()
+                < K
+    }
+
+    /// Replaces the bucket at `index` with its two halves.
+    fn split(&mut self, index: usize) {
+        let bucket = self.buckets.remove(index);
+        let (farther, closer) = bucket.split(&self.node_id);
+        self.buckets.insert(index, closer);
+        self.buckets.insert(index, farther);
+    }
+
+    /// Removes a node from the routing table, returning whether it was present.
+    pub fn remove(&mut self, node: &NodeId) -> bool {
+        if *node == self.node_id {
+            return false;
+        }
+        let bucket_index = self.bucket_index(node);
+        self.buckets[bucket_index].remove(node)
+    }
+
+    /// Records that a node answered an RPC, adding it to the table if needed.
+    ///
+    /// # Arguments
+    /// * `node` - The node that answered
+    /// * `addr` - The address the answer came from
+    /// * `latency` - Time between sending the request and receiving the answer
+    pub fn record_success(&mut self, node: NodeId, addr: SocketAddr, latency: Duration) {
+        self.update(node, addr);
+        if let Some(info) = self.get_mut(&node) {
+            info.stats.record_success(latency);
+        }
+    }
+
+    /// Records that a node didn't answer an RPC in time.
+    ///
+    /// Nodes failing [`MAX_CONSECUTIVE_FAILURES`] RPCs in a row are evicted.
+    pub fn record_timeout(&mut self, node: &NodeId) {
+        let Some(info) = self.get_mut(node) else {
+            return;
+        };
+        info.stats.record_timeout();
+        if info.stats.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+            log::debug!("Evicting unresponsive node {}", node);
+            self.remove(node);
+        }
+    }
+
+    /// Records that a node returned data that failed verification.
+    ///
+    /// Honest nodes never do that, so the node is evicted and banned for
+    /// [`BAN_DURATION`].
+    pub fn record_bad_data(&mut self, node: &NodeId) {
+        if let Some(info) = self.get_mut(node) {
+            info.stats.bad_data = info.stats.bad_data.saturating_add(1);
+        }
+        self.ban(node, BAN_DURATION);
+    }
+
+    /// Removes a node and ignores it for the given duration.
+    pub fn ban(&mut self, node: &NodeId, duration: Duration) {
+        self.remove(node);
+        let now = Instant::now();
+        self.banned.retain(|_, until| *until > now);
+        self.banned.insert(*node, now + duration);
+    }
+
+    /// Returns whether a node is currently banned.
+    pub fn is_banned(&self, node: &NodeId) -> bool {
+        self.banned
+            .get(node)
+            .is_some_and(|until| *until > Instant::now())
+    }
+
+    /// Returns the statistics of a node, if it is in the table.
+    pub fn stats(&self, node: &NodeId) -> Option<PeerStats> {
+        if *node == self.node_id {
+            return None;
+        }
+        self.buckets[self.bucket_index(node)]
+            .nodes()
+            .find(|n| n.node_id == *node)
+            .map(|n| n.stats)
+    }
+
+    /// Returns the address of a node, if it is in the table.
+    pub fn address_of(&self, node: &NodeId) -> Option<SocketAddr> {
+        if *node == self.node_id {
+            return None;
+        }
+        self.buckets[self.bucket_index(node)]
+            .nodes()
+            .find(|n| n.node_id == *node)
+            .map(|n| n.sock_addr)
+    }
+
+    /// Returns the entry of a node, if it is in the table.
+    fn get_mut(&mut self, node: &NodeId) -> Option<&mut NodeInfo> {
+        if *node == self.node_id {
+            return None;
+        }
+        let bucket_index = self.bucket_index(node);
+        self.buckets[bucket_index].get_mut(node)
     }
 
     /// Finds the closest nodes to a target ID in the routing table.
@@ -136,36 +545,281 @@ impl RoutingTable {
     /// * `count` - Maximum number of nodes to return
     ///
     /// # Returns
-    /// A vector of the closest NodeIds, sorted by XOR distance from the target
+    /// A vector of the closest nodes and their addresses, preferring responsive
+    /// nodes and sorted by XOR distance from the target within each tier
     ///
     /// # Implementation Details
-    /// 1. Collects all nodes from all buckets
-    /// 2. Sorts them by XOR distance to the target
-    /// 3. Returns the closest `count` nodes
-    pub fn closest_nodes(&self, target: &NodeId, count: usize) -> Vec<NodeId> {
-        let mut nodes = Vec::new();
+    /// 1. Orders the buckets by the smallest distance between their range and
+    ///    the target; since ranges are disjoint, so are these distance ranges
+    /// 2. Walks the buckets in that order until `count` nodes without recent
+    ///    failures were collected
+    /// 3. Sorts the collected nodes by [`PeerStats::tier`], then by XOR distance
+    ///    to the target, and returns the first `count`
+    pub fn closest_nodes(&self, target: &NodeId, count: usize) -> Vec<(NodeId, SocketAddr)> {
+        let mut buckets: Vec<&KBucket> = self.buckets.iter().collect();
+        buckets.sort_by_key(|bucket| bucket.min_distance(target));
 
-        for bucket in &self.buckets {
-            nodes.extend(bucket.nodes.iter().cloned());
+        let mut nodes = Vec::new();
+        let mut healthy = 0;
+        for bucket in buckets {
+            if healthy >= count {
+                break;
+            }
+            for info in bucket.nodes() {
+                if info.stats.consecutive_failures == 0 {
+                    healthy += 1;
+                }
+                nodes.push(info);
+            }
         }
 
-        nodes.sort_by_key(|n| Distance::between(n, target));
-        nodes.truncate(count);
+        nodes.sort_by_key(|n| (n.stats.tier(), Distance::between(&n.node_id, target)));
         nodes
+            .into_iter()
+            .take(count)
+            .map(|n| (n.node_id, n.sock_addr))
+            .collect()
     }
 
-    /// Calculates the index of the k-bucket for a given node ID.
+    /// Returns the contacts added since the last call, oldest first.
     ///
-    /// The bucket index is determined by the length of the common prefix
-    /// (in bits) between the local node's ID and the given node ID.
+    /// Used to hand stored keys over to new nodes in our neighborhood. Only a
+    /// bounded number of contacts is remembered between calls.
+    pub fn take_new_contacts(&mut self) -> Vec<(NodeId, SocketAddr)> {
+        std::mem::take(&mut self.new_contacts)
+    }
+
+    /// Returns the ID of the local node.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// Returns the non-empty k-buckets together with their position in the
+    /// table, farthest from the local node first.
+    pub fn buckets(&self) -> impl Iterator<Item = (usize, &KBucket)> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| !bucket.is_empty())
+    }
+
+    /// Picks up to `count` random nodes, skipping the given ones.
+    ///
+    /// Used to spread gossip, where random peers reach the whole network
+    /// faster than close ones.
+    pub fn random_nodes(&self, count: usize, exclude: &[NodeId]) -> Vec<(NodeId, SocketAddr)> {
+        let candidates: Vec<(NodeId, SocketAddr)> = self
+            .buckets
+            .iter()
+            .flat_map(KBucket::nodes)
+            .filter(|info| !exclude.contains(&info.node_id))
+            .map(|info| (info.node_id, info.sock_addr))
+            .collect();
+        candidates
+            .choose_multiple(&mut rand::rng(), count)
+            .copied()
+            .collect()
+    }
+
+    /// Returns the total number of nodes in the routing table.
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(KBucket::len).sum()
+    }
+
+    /// Returns whether the routing table contains no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Generates one random lookup target for every non-empty k-bucket.
+    ///
+    /// Looking up a random ID in a bucket's range refreshes that bucket, as
+    /// described in section 2.3 of the Kademlia paper.
+    pub fn refresh_targets(&self) -> Vec<NodeId> {
+        self.buckets()
+            .map(|(_, bucket)| bucket.random_id())
+            .collect()
+    }
+
+    /// Returns the position of the k-bucket whose range covers a given node ID.
     ///
     /// # Arguments
-    /// * `node` - The NodeId to calculate the bucket index for
+    /// * `node` - The NodeId to find the bucket for
     ///
     /// # Returns
-    /// The index of the appropriate k-bucket
+    /// The index of the appropriate k-bucket in the table
     fn bucket_index(&self, node: &NodeId) -> usize {
-        let distance = Distance::between(&self.node_id, node);
-        distance.leading_zeros() as usize
+        self.buckets
+            .iter()
+            .position(|bucket| bucket.covers(node))
+            .expect("buckets partition the ID space")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_unresponsive_nodes_are_evicted() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let node = NodeId::random();
+        table.update(node, addr(1));
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES - 1 {
+            table.record_timeout(&node);
+        }
+        assert_eq!(table.stats(&node).unwrap().consecutive_failures, 2);
+
+        table.record_timeout(&node);
+        assert!(table.stats(&node).is_none());
+    }
+
+    #[test]
+    fn test_success_resets_failures() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let node = NodeId::random();
+        table.update(node, addr(1));
+        table.record_timeout(&node);
+        table.record_success(node, addr(1), Duration::from_millis(10));
+
+        let stats = table.stats(&node).unwrap();
+        assert_eq!(stats.consecutive_failures, 0);
+        assert_eq!(stats.success_rate(), Some(0.5));
+        assert_eq!(stats.latency, Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_bad_data_bans_node() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let node = NodeId::random();
+        table.update(node, addr(1));
+        table.record_bad_data(&node);
+
+        assert!(table.is_banned(&node));
+        table.update(node, addr(1));
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_buckets_split_around_own_id() {
+        let own_id = NodeId::random();
+        let mut table = RoutingTable::new(own_id);
+        for i in 0..200 {
+            table.update(NodeId::random(), addr(i));
+        }
+
+        assert!(table.buckets.len() > 1);
+        assert!(table.buckets.iter().all(|bucket| bucket.len() <= K));
+        // Only the last, closest bucket covers our own ID
+        let last = table.buckets.len() - 1;
+        assert!(table.buckets[last].covers(&own_id));
+        assert!(table.buckets[..last].iter().all(|b| !b.covers(&own_id)));
+        // Buckets are ordered from farthest to closest
+        let distances: Vec<Distance> = table
+            .buckets
+            .iter()
+            .map(|bucket| bucket.min_distance(&own_id))
+            .collect();
+        assert!(distances.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn test_closest_nodes_matches_full_scan() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let mut all = Vec::new();
+        for i in 0..300 {
+            let node = NodeId::random();
+            table.update(node, addr(i));
+            all.push(node);
+        }
+        let known: Vec<NodeId> = all
+            .into_iter()
+            .filter(|node| table.stats(node).is_some())
+            .collect();
+
+        for _ in 0..20 {
+            let target = NodeId::random();
+            let mut expected = known.clone();
+            expected.sort_by_key(|node| Distance::between(node, &target));
+            expected.truncate(K);
+
+            let found: Vec<NodeId> = table
+                .closest_nodes(&target, K)
+                .into_iter()
+                .map(|(node, _)| node)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_relaxed_split_keeps_close_contacts() {
+        let own_id = NodeId::new([0u8; KEY_SIZE / 8]);
+        // All contacts share the first bit with each other but not with us
+        let far = |i: u8| {
+            let mut bytes = [0u8; KEY_SIZE / 8];
+            bytes[0] = 0x80;
+            bytes[1] = i;
+            NodeId::new(bytes)
+        };
+
+        let mut strict = RoutingTable::new(own_id);
+        let mut relaxed = RoutingTable::with_relaxed_split(own_id);
+        for i in 0..40 {
+            strict.update(far(i), addr(i as u16));
+            relaxed.update(far(i), addr(i as u16));
+        }
+
+        assert_eq!(strict.len(), K);
+        assert_eq!(relaxed.len(), 40);
+    }
+
+    #[test]
+    fn test_refresh_targets_fall_into_their_buckets() {
+        let mut table = RoutingTable::new(NodeId::random());
+        for i in 0..100 {
+            table.update(NodeId::random(), addr(i));
+        }
+        let targets = table.refresh_targets();
+        let buckets: Vec<&KBucket> = table.buckets().map(|(_, bucket)| bucket).collect();
+        assert_eq!(targets.len(), buckets.len());
+        for (target, bucket) in targets.iter().zip(buckets) {
+            assert!(bucket.covers(target));
+        }
+    }
+
+    #[test]
+    fn test_closest_nodes_prefers_responsive_nodes() {
+        let target = NodeId::random();
+        let mut table = RoutingTable::new(NodeId::random());
+        let nodes: Vec<NodeId> = (0..10).map(|_| NodeId::random()).collect();
+        for (i, node) in nodes.iter().enumerate() {
+            table.update(*node, addr(i as u16));
+        }
+
+        let closest = table.closest_nodes(&target, 10)[0].0;
+        table.record_timeout(&closest);
+        let ranked = table.closest_nodes(&target, 10);
+        assert_eq!(ranked.len(), 10);
+        assert_eq!(ranked.last().unwrap().0, closest);
+    }
+
+    #[test]
+    fn test_random_nodes_skip_excluded() {
+        let mut table = RoutingTable::new(NodeId::random());
+        let nodes: Vec<NodeId> = (0..10).map(|_| NodeId::random()).collect();
+        for (port, node) in nodes.iter().enumerate() {
+            table.update(*node, addr(port as u16));
+        }
+
+        let picked = table.random_nodes(4, &nodes[..2]);
+        assert_eq!(picked.len(), 4);
+        assert!(picked.iter().all(|(id, _)| !nodes[..2].contains(id)));
+        assert_eq!(table.random_nodes(20, &[]).len(), table.len());
     }
 }
@@ -5,28 +5,105 @@
 //! - STORE: Instructs a node to store a key-value pair
 //! - FIND_NODE: Finds the k closest nodes to a given ID
 //! - FIND_VALUE: Similar to FIND_NODE but returns a value if found
+//!
+//! In addition, PONG reflects the address a request was observed from, a small
+//! relay exchange lets two peers behind NATs coordinate a hole punch, GOSSIP
+//! carries publish/subscribe messages without expecting a response, and APP
+//! messages carry application protocols between nodes.
+//!
+//! All traffic of a node flows through a single UDP socket. Requests carry an
+//! identifier that is echoed in the matching response, so the same socket can
+//! serve incoming RPCs while any number of outgoing RPCs are in flight.
+//!
+//! Every request names the [`NetworkId`] of its sender. Nodes refuse requests
+//! from other networks, so separate overlays never learn about each other even
+//! when their nodes can reach one another.
 
+use crate::app::AppRequest;
+use crate::integrity::{self, ValueMode};
+use crate::nat::{NatRegistry, PunchLimiter};
+use crate::pubsub::GossipEnvelope;
 use crate::routing::RoutingTable;
 use crate::storage::Storage;
-use crate::{Key, NodeId, K};
-use anyhow::Result;
+use crate::watch::ValueVersion;
+use crate::{Key, NetworkId, NodeId, K, RPC_TIMEOUT};
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+/// Maximum number of received requests buffered before new ones are dropped
+const REQUEST_QUEUE_SIZE: usize = 1024;
+
+/// Maximum number of received gossip messages buffered before new ones are dropped
+const GOSSIP_QUEUE_SIZE: usize = 1024;
+
+/// Maximum number of received application messages buffered before new ones are dropped
+const APP_QUEUE_SIZE: usize = 1024;
 
 /// Server component for handling incoming Kademlia RPC requests
 pub struct RpcServer {
-    /// UDP socket for receiving RPC messages
-    socket: UdpSocket,
+    /// Socket shared with the clients created through [`RpcServer::client`]
+    transport: Arc<Transport>,
+    /// Requests received by the socket reader, waiting to be handled
+    requests: Mutex<mpsc::Receiver<IncomingRequest>>,
+    /// Background task reading datagrams from the socket
+    reader: JoinHandle<()>,
+    /// Client-only peers that can be reached for hole punching
+    nat_clients: parking_lot::Mutex<NatRegistry>,
+    /// Limits the hole punches relays can make this node start
+    punches: parking_lot::Mutex<PunchLimiter>,
+    /// Queue of received gossip messages, see [`RpcServer::next_gossip`]
+    gossip_tx: mpsc::Sender<(NodeId, GossipEnvelope)>,
+    /// Receiving end of the gossip queue
+    gossip_rx: Mutex<mpsc::Receiver<(NodeId, GossipEnvelope)>>,
+    /// Queue of received application messages, see [`RpcServer::next_app_message`]
+    app_tx: mpsc::Sender<IncomingApp>,
+    /// Receiving end of the application message queue
+    app_rx: Mutex<mpsc::Receiver<IncomingApp>>,
+}
+
+/// An application message handed from the server loop to the node.
+pub(crate) struct IncomingApp {
+    /// The message
+    pub(crate) request: AppRequest,
+    /// Where to send the handler's result; `None` for one-way messages
+    pub(crate) reply: Option<AppReply>,
+}
+
+/// Sends the answer to an application request.
+pub(crate) struct AppReply {
+    transport: Arc<Transport>,
+    addr: SocketAddr,
+    id: u64,
+    responder: NodeId,
+}
+
+impl AppReply {
+    /// Sends the handler's result, or the error it failed with.
+    pub(crate) async fn send(self, result: Result<Vec<u8>>) {
+        let response = RpcResponse::App {
+            responder: self.responder,
+            result: result.map_err(|e| e.to_string()),
+        };
+        if let Err(e) = self.transport.respond(self.addr, self.id, response).await {
+            log::debug!("Failed to respond to {}: {}", self.addr, e);
+        }
+    }
 }
 
 /// Client component for making outgoing Kademlia RPC requests
 pub struct RpcClient {
-    /// UDP socket for sending RPC messages
-    socket: UdpSocket,
+    /// Socket used for sending RPC messages and receiving their responses
+    transport: Arc<Transport>,
+    /// Background task reading responses, if this client owns its socket
+    reader: Option<JoinHandle<()>>,
 }
 
 /// Enumeration of possible RPC message types in the Kademlia protocol.
@@ -48,6 +125,8 @@ enum RpcMessage {
         key: Key,
         /// Value to be stored
         value: Vec<u8>,
+        /// How the value can be verified against the key
+        mode: ValueMode,
     },
     /// Request to find the k closest nodes to a target
     FindNode {
@@ -63,6 +142,97 @@ enum RpcMessage {
         /// Key of the value to find
         key: Key,
     },
+    /// Request asking a relay to coordinate a hole punch towards a NATed peer
+    RelayConnect {
+        /// ID of the sending node
+        sender: NodeId,
+        /// ID of the client-only peer the sender wants to reach
+        target: NodeId,
+    },
+    /// Notification from a relay that a peer is about to punch towards us
+    PunchNotify {
+        /// ID of the relaying node
+        sender: NodeId,
+        /// ID of the peer that asked for the connection
+        peer: NodeId,
+        /// Address of the peer as observed by the relay
+        peer_addr: SocketAddr,
+    },
+    /// Request for an application protocol, answered with its handler's result
+    AppRequest {
+        /// ID of the sending node
+        sender: NodeId,
+        /// Application protocol the request belongs to
+        protocol: String,
+        /// The application payload
+        payload: Vec<u8>,
+    },
+    /// One-way message for an application protocol; not answered
+    AppMessage {
+        /// ID of the sending node
+        sender: NodeId,
+        /// Application protocol the message belongs to
+        protocol: String,
+        /// The application payload
+        payload: Vec<u8>,
+    },
+    /// A publish/subscribe message; not answered
+    Gossip {
+        /// ID of the forwarding node
+        sender: NodeId,
+        /// The message and its remaining hop count
+        envelope: GossipEnvelope,
+    },
+    /// Request to store a key-value pair for a given time
+    StoreWithTtl {
+        /// ID of the sending node
+        sender: NodeId,
+        /// Key under which to store the value
+        key: Key,
+        /// Value to be stored
+        value: Vec<u8>,
+        /// How the value can be verified against the key
+        mode: ValueMode,
+        /// Seconds the value should be kept, capped by the receiver's policy
+        ttl_secs: u64,
+    },
+    /// Request for the version of a stored value, without the value itself
+    GetVersion {
+        /// ID of the sending node
+        sender: NodeId,
+        /// Key of the value
+        key: Key,
+    },
+    /// Request to store a shard of an erasure-coded object, which the receiver
+    /// keeps but doesn't republish
+    StoreShard {
+        /// ID of the sending node
+        sender: NodeId,
+        /// Content-addressed key of the shard
+        key: Key,
+        /// The shard
+        shard: Vec<u8>,
+    },
+}
+
+impl RpcMessage {
+    /// Returns the ID of the node that sent this message.
+    fn sender(&self) -> NodeId {
+        match self {
+            RpcMessage::Ping { sender }
+            | RpcMessage::Store { sender, .. }
+            | RpcMessage::FindNode { sender, .. }
+            | RpcMessage::FindValue { sender, .. }
+            | RpcMessage::RelayConnect { sender, .. }
+            | RpcMessage::PunchNotify { sender, .. }
+            | RpcMessage::AppRequest { sender, .. }
+            | RpcMessage::AppMessage { sender, .. }
+            | RpcMessage::Gossip { sender, .. }
+            | RpcMessage::StoreWithTtl { sender, .. }
+            | RpcMessage::GetVersion { sender, .. }
+            | RpcMessage::StoreShard { sender, .. } => *sender,
+        }
+    }
 }
 
 /// Enumeration of possible RPC response types in the Kademlia protocol.
@@ -72,6 +242,8 @@ enum RpcResponse {
     Pong {
         /// ID of the responding node
         responder: NodeId,
+        /// Source address the ping was received from
+        observed: SocketAddr,
     },
     /// Response containing k closest nodes to a target
     NodesFound {
@@ -94,6 +266,243 @@ enum RpcResponse {
         /// Whether the store operation succeeded
         success: bool,
     },
+    /// Response to a RelayConnect message
+    RelayConnected {
+        /// ID of the responding node
+        responder: NodeId,
+        /// Last known address of the target, if the relay knows it
+        target_addr: Option<SocketAddr>,
+    },
+    /// Response to an AppRequest message
+    App {
+        /// ID of the responding node
+        responder: NodeId,
+        /// The handler's result, or why the request failed
+        result: Result<Vec<u8>, String>,
+    },
+    /// Response to a GetVersion message
+    Version {
+        /// ID of the responding node
+        responder: NodeId,
+        /// Version of the stored value, if the node holds one
+        version: Option<ValueVersion>,
+    },
+    /// Refusal of a request sent from another network
+    WrongNetwork {
+        /// Network the responding node belongs to
+        network: NetworkId,
+    },
+}
+
+/// Envelope for every datagram exchanged between nodes.
+#[derive(Serialize, Deserialize)]
+enum RpcPacket {
+    /// An RPC request expecting a response with the same `id`
+    Request {
+        /// Identifier echoed back in the response
+        id: u64,
+        /// Whether the sender is client-only and must not enter routing tables
+        client_only: bool,
+        /// Network the sender belongs to
+        network: NetworkId,
+        /// The request itself
+        message: RpcMessage,
+    },
+    /// The response to an earlier request
+    Response {
+        /// Identifier of the request being answered
+        id: u64,
+        /// The response itself
+        response: RpcResponse,
+    },
+}
+
+/// A request handed from the socket reader to the server loop.
+struct IncomingRequest {
+    id: u64,
+    client_only: bool,
+    message: RpcMessage,
+    src: SocketAddr,
+}
+
+/// A UDP socket together with the bookkeeping needed to match responses
+/// to the requests that are waiting for them.
+///
+/// Request IDs start at a random value and a response is only accepted from
+/// the address its request was sent to, so other hosts can't answer in place
+/// of the node that was asked.
+struct Transport {
+    socket: UdpSocket,
+    /// Callers waiting for a response, with the address they sent their request to
+    pending: parking_lot::Mutex<HashMap<u64, (SocketAddr, oneshot::Sender<RpcResponse>)>>,
+    next_id: AtomicU64,
+    client_only: AtomicBool,
+    network: NetworkId,
+}
+
+impl Transport {
+    /// Binds a new transport for the given network to the given address.
+    async fn bind(addr: SocketAddr, network: NetworkId) -> Result<Arc<Self>> {
+        let socket = UdpSocket::bind(addr).await?;
+        Ok(Arc::new(Transport {
+            socket,
+            pending: parking_lot::Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(rand::random()),
+            client_only: AtomicBool::new(false),
+            network,
+        }))
+    }
+
+    /// Spawns the task reading datagrams from the socket.
+    ///
+    /// Responses are delivered to their waiting callers. Requests are forwarded
+    /// to `requests` if given, and dropped otherwise. Requests from other
+    /// networks are refused right away.
+    fn spawn_reader(
+        self: &Arc<Self>,
+        requests: Option<mpsc::Sender<IncomingRequest>>,
+    ) -> JoinHandle<()> {
+        let transport = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65536]; // Maximum UDP packet size
+
+            loop {
+                let (size, src) = match transport.socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        log::debug!("Failed to receive datagram: {}", e);
+                        continue;
+                    }
+                };
+
+                match bincode::deserialize(&buf[..size]) {
+                    Ok(RpcPacket::Response { id, response }) => {
+                        let mut pending = transport.pending.lock();
+                        match pending.get(&id) {
+                            Some((addr, _)) if *addr == src => {
+                                if let Some((_, waiter)) = pending.remove(&id) {
+                                    let _ = waiter.send(response);
+                                }
+                            }
+                            Some(_) => log::debug!("Ignoring response from unexpected {}", src),
+                            None => {}
+                        }
+                    }
+                    Ok(RpcPacket::Request {
+                        id,
+                        client_only,
+                        network,
+                        message,
+                    }) => {
+                        if requests.is_some() && network != transport.network {
+                            log::debug!("Refusing request from {} of network {}", src, network);
+                            let refusal = RpcResponse::WrongNetwork {
+                                network: transport.network.clone(),
+                            };
+                            if let Err(e) = transport.respond(src, id, refusal).await {
+                                log::debug!("Failed to respond to {}: {}", src, e);
+                            }
+                        } else if let Some(requests) = &requests {
+                            let request = IncomingRequest {
+                                id,
+                                client_only,
+                                message,
+                                src,
+                            };
+                            if requests.try_send(request).is_err() {
+                                log::debug!("Dropping request from {}: queue full", src);
+                            }
+                        }
+                    }
+                    Err(e) => log::debug!("Ignoring malformed packet from {}: {}", src, e),
+                }
+            }
+        })
+    }
+
+    /// Sends a request and waits for its response.
+    async fn call(&self, addr: SocketAddr, message: RpcMessage) -> Result<RpcResponse> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().insert(id, (addr, tx));
+
+        // Unregisters the waiter even if the caller stops waiting early,
+        // as lookups do with the queries still in flight when they finish
+        let _pending = PendingGuard {
+            transport: self,
+            id,
+        };
+        self.exchange(id, addr, message, rx).await
+    }
+
+    async fn exchange(
+        &self,
+        id: u64,
+        addr: SocketAddr,
+        message: RpcMessage,
+        rx: oneshot::Receiver<RpcResponse>,
+    ) -> Result<RpcResponse> {
+        self.send_request(id, addr, message).await?;
+        match tokio::time::timeout(RPC_TIMEOUT, rx).await {
+            Ok(Ok(RpcResponse::WrongNetwork { network })) => Err(anyhow!(
+                "Node at {} belongs to network {}, not {}",
+                addr,
+                network,
+                self.network
+            )),
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(anyhow!("RPC to {} was cancelled", addr)),
+            Err(_) => Err(anyhow!("RPC to {} timed out", addr)),
+        }
+    }
+
+    /// Sends a request without waiting for a response.
+    async fn notify(&self, addr: SocketAddr, message: RpcMessage) -> Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.send_request(id, addr, message).await
+    }
+
+    async fn send_request(&self, id: u64, addr: SocketAddr, message: RpcMessage) -> Result<()> {
+        let packet = RpcPacket::Request {
+            id,
+            client_only: self.client_only.load(Ordering::Relaxed),
+            network: self.network.clone(),
+            message,
+        };
+        let bytes = bincode::serialize(&packet)?;
+        self.socket.send_to(&bytes, addr).await?;
+        Ok(())
+    }
+
+    /// Sends the response to request `id` back to `addr`.
+    async fn respond(&self, addr: SocketAddr, id: u64, response: RpcResponse) -> Result<()> {
+        let bytes = bincode::serialize(&RpcPacket::Response { id, response })?;
+        self.socket.send_to(&bytes, addr).await?;
+        Ok(())
+    }
+}
+
+/// Answers a STORE or STORE_SHARD request, logging why a value was refused.
+fn stored_response(node_id: NodeId, key: Key, result: Result<()>) -> RpcResponse {
+    if let Err(e) = &result {
+        log::debug!("Refusing to store {}: {}", key, e);
+    }
+    RpcResponse::Stored {
+        responder: node_id,
+        success: result.is_ok(),
+    }
+}
+
+/// Removes the waiter of a request from the pending map when dropped.
+struct PendingGuard<'a> {
+    transport: &'a Transport,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.transport.pending.lock().remove(&self.id);
+    }
 }
 
 impl RpcServer {
@@ -102,34 +511,153 @@ impl RpcServer {
     /// # Returns
     /// * `Result<Self>` - New RpcServer instance or error
     pub async fn new() -> Result<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        Ok(RpcServer { socket })
+        Self::bind(SocketAddr::from(([0, 0, 0, 0], 0))).await
+    }
+
+    /// Creates a new RPC server for the default network bound to the given address.
+    ///
+    /// # Arguments
+    /// * `addr` - The socket address to listen on
+    ///
+    /// # Returns
+    /// * `Result<Self>` - New RpcServer instance or error
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::bind_with_network(addr, NetworkId::default()).await
+    }
+
+    /// Creates a new RPC server bound to the given address, serving only
+    /// requests from the given network.
+    ///
+    /// # Arguments
+    /// * `addr` - The socket address to listen on
+    /// * `network` - The network the server belongs to
+    pub async fn bind_with_network(addr: SocketAddr, network: NetworkId) -> Result<Self> {
+        let transport = Transport::bind(addr, network).await?;
+        let (tx, rx) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let reader = transport.spawn_reader(Some(tx));
+        let (gossip_tx, gossip_rx) = mpsc::channel(GOSSIP_QUEUE_SIZE);
+        let (app_tx, app_rx) = mpsc::channel(APP_QUEUE_SIZE);
+
+        Ok(RpcServer {
+            transport,
+            requests: Mutex::new(rx),
+            reader,
+            nat_clients: parking_lot::Mutex::new(NatRegistry::default()),
+            punches: parking_lot::Mutex::new(PunchLimiter::default()),
+            gossip_tx,
+            gossip_rx: Mutex::new(gossip_rx),
+            app_tx,
+            app_rx: Mutex::new(app_rx),
+        })
+    }
+
+    /// Returns the address the server socket is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.transport.socket.local_addr()?)
+    }
+
+    /// Returns the network the server belongs to.
+    pub fn network(&self) -> &NetworkId {
+        &self.transport.network
+    }
+
+    /// Creates a client that sends its RPCs from the server socket.
+    ///
+    /// Peers see such requests coming from the address the server listens on,
+    /// which keeps reflected addresses and NAT mappings meaningful.
+    pub fn client(&self) -> RpcClient {
+        RpcClient {
+            transport: Arc::clone(&self.transport),
+            reader: None,
+        }
+    }
+
+    /// Waits for the next gossip message received by the server.
+    ///
+    /// Gossip is handed over instead of handled by the server loop, since
+    /// forwarding it needs the node's publish/subscribe state.
+    pub(crate) async fn next_gossip(&self) -> Option<(NodeId, GossipEnvelope)> {
+        self.gossip_rx.lock().await.recv().await
+    }
+
+    /// Waits for the next application message received by the server.
+    ///
+    /// Like gossip, application messages are handed over so that slow
+    /// handlers don't hold up the server loop.
+    pub(crate) async fn next_app_message(&self) -> Option<IncomingApp> {
+        self.app_rx.lock().await.recv().await
+    }
+
+    /// Queues an application message for the node.
+    fn queue_app_message(&self, request: AppRequest, reply: Option<AppReply>, src: SocketAddr) {
+        if self
+            .app_tx
+            .try_send(IncomingApp { request, reply })
+            .is_err()
+        {
+            log::debug!("Dropping application message from {}: queue full", src);
+        }
     }
 
     /// Handles STORE RPC requests
+    ///
+    /// Values that fail verification in their mode are refused, as are values
+    /// that would replace a verifiable value they are not a valid update of.
+    /// Without a TTL, values get the default TTL of the values namespace.
     async fn handle_store(
         &self,
         node_id: NodeId,
-        sender: NodeId,
         key: Key,
         value: Vec<u8>,
+        mode: ValueMode,
+        ttl: Option<Duration>,
         storage: &Storage,
-        routing_table: &mut RoutingTable,
     ) -> RpcResponse {
-        // Update routing table with sender information
-        routing_table.update(sender);
+        let result = self.store_verified(key, value, mode, ttl, false, storage);
+        stored_response(node_id, key, result)
+    }
 
-        // Store with 24 hour TTL
-        let ttl = Duration::from_secs(24 * 60 * 60);
-        match storage.store(key, value, ttl) {
-            Ok(()) => RpcResponse::Stored {
-                responder: node_id,
-                success: true,
-            },
-            Err(_) => RpcResponse::Stored {
-                responder: node_id,
-                success: false,
-            },
+    /// Handles STORE_SHARD RPC requests
+    ///
+    /// Shards are checked like content-addressed values, with the default TTL
+    /// of the values namespace.
+    async fn handle_store_shard(
+        &self,
+        node_id: NodeId,
+        key: Key,
+        shard: Vec<u8>,
+        storage: &Storage,
+    ) -> RpcResponse {
+        let mode = ValueMode::ContentAddressed;
+        let result = self.store_verified(key, shard, mode, None, true, storage);
+        stored_response(node_id, key, result)
+    }
+
+    /// Stores a value or shard after checking it may be stored under `key`.
+    fn store_verified(
+        &self,
+        key: Key,
+        value: Vec<u8>,
+        mode: ValueMode,
+        ttl: Option<Duration>,
+        shard: bool,
+        storage: &Storage,
+    ) -> Result<()> {
+        if !integrity::verify(&key, mode, &value) {
+            bail!("Value does not match key {} in mode {:?}", key, mode);
+        }
+        if let Some(existing) = storage.get(&key)? {
+            if !integrity::may_replace(&key, &existing, mode, &value) {
+                bail!("Value would replace a protected value under {}", key);
+            }
+        }
+        let ttl = match ttl {
+            Some(ttl) => ttl,
+            None => storage.default_ttl()?,
+        };
+        match shard {
+            true => storage.store_shard(key, value, ttl),
+            false => storage.store(key, value, ttl),
         }
     }
 
@@ -137,22 +665,11 @@ impl RpcServer {
     async fn handle_find_node(
         &self,
         node_id: NodeId,
-        sender: NodeId,
         target: Key,
-        routing_table: &mut RoutingTable,
+        routing_table: &RoutingTable,
     ) -> RpcResponse {
-        // Update routing table with sender information
-        routing_table.update(sender);
-
         // Find k closest nodes to target
-        let closest_nodes = routing_table.closest_nodes(&target, K);
-
-        // Convert NodeIds to (NodeId, SocketAddr) pairs
-        // TODO: Get actual addresses from routing table
-        let nodes: Vec<(NodeId, SocketAddr)> = closest_nodes
-            .into_iter()
-            .map(|id| (id, SocketAddr::from(([127, 0, 0, 1], 8000))))
-            .collect();
+        let nodes = routing_table.closest_nodes(&target, K);
 
         RpcResponse::NodesFound {
             responder: node_id,
@@ -164,14 +681,10 @@ impl RpcServer {
     async fn handle_find_value(
         &self,
         node_id: NodeId,
-        sender: NodeId,
         key: Key,
         storage: &Storage,
-        routing_table: &mut RoutingTable,
+        routing_table: &RoutingTable,
     ) -> RpcResponse {
-        // Update routing table with sender information
-        routing_table.update(sender);
-
         // First try to find the value locally
         match storage.get(&key) {
             Ok(Some(value)) => RpcResponse::ValueFound {
@@ -180,11 +693,7 @@ impl RpcServer {
             },
             Ok(None) | Err(_) => {
                 // If value not found, return k closest nodes
-                let closest_nodes = routing_table.closest_nodes(&key, K);
-                let nodes: Vec<(NodeId, SocketAddr)> = closest_nodes
-                    .into_iter()
-                    .map(|id| (id, SocketAddr::from(([127, 0, 0, 1], 8000))))
-                    .collect();
+                let nodes = routing_table.closest_nodes(&key, K);
 
                 RpcResponse::NodesFound {
                     responder: node_id,
@@ -194,6 +703,89 @@ impl RpcServer {
         }
     }
 
+    /// Handles GET_VERSION RPC requests
+    async fn handle_get_version(
+        &self,
+        node_id: NodeId,
+        key: Key,
+        storage: &Storage,
+    ) -> RpcResponse {
+        let version = match storage.get(&key) {
+            Ok(value) => value.map(|value| ValueVersion::of(&key, &value)),
+            Err(e) => {
+                log::debug!("Failed to read {} for a version request: {}", key, e);
+                None
+            }
+        };
+        RpcResponse::Version {
+            responder: node_id,
+            version,
+        }
+    }
+
+    /// Handles RELAY_CONNECT RPC requests
+    ///
+    /// If the target is a client-only peer known to this node, it is told to
+    /// punch towards the requester while the requester learns the target's
+    /// address and punches back.
+    async fn handle_relay_connect(
+        &self,
+        node_id: NodeId,
+        sender: NodeId,
+        target: NodeId,
+        src: SocketAddr,
+    ) -> RpcResponse {
+        let target_addr = self.nat_clients.lock().lookup(&target);
+
+        if let Some(addr) = target_addr {
+            let notify = RpcMessage::PunchNotify {
+                sender: node_id,
+                peer: sender,
+                peer_addr: src,
+            };
+            if let Err(e) = self.transport.notify(addr, notify).await {
+                log::debug!("Failed to notify {} about hole punch: {}", addr, e);
+            }
+        }
+
+        RpcResponse::RelayConnected {
+            responder: node_id,
+            target_addr,
+        }
+    }
+
+    /// Handles PUNCH_NOTIFY RPC requests
+    ///
+    /// Sends a ping to the peer so that our NAT opens a mapping for its replies.
+    /// Only relays from the routing table are followed, and only as often as
+    /// the [`PunchLimiter`] allows, since otherwise anyone could make this node
+    /// send pings to an address of their choice.
+    async fn handle_punch_notify(
+        &self,
+        node_id: NodeId,
+        peer: NodeId,
+        peer_addr: SocketAddr,
+        src: SocketAddr,
+        known_relay: bool,
+    ) -> RpcResponse {
+        if !known_relay {
+            log::debug!("Ignoring hole punch request from unknown relay {}", src);
+        } else if !self.punches.lock().allow() {
+            log::debug!("Ignoring hole punch request from {}: rate limited", src);
+        } else {
+            log::debug!("Punching towards {} ({})", peer, peer_addr);
+            let ping = RpcMessage::Ping { sender: node_id };
+            if let Err(e) = self.transport.notify(peer_addr, ping).await {
+                log::debug!("Failed to punch towards {}: {}", peer_addr, e);
+            }
+        }
+
+        RpcResponse::Pong {
+            responder: node_id,
+            observed: src,
+        }
+    }
+
     /// Starts the RPC server's main loop handling incoming requests.
     pub async fn start(
         &self,
@@ -201,31 +793,122 @@ impl RpcServer {
         storage: Storage,
         routing_table: Arc<Mutex<RoutingTable>>,
     ) -> Result<()> {
-        let mut buf = vec![0u8; 65536]; // Maximum UDP packet size
+        let mut requests = self.requests.lock().await;
 
-        loop {
-            let (size, src) = self.socket.recv_from(&mut buf).await?;
-            let message: RpcMessage = bincode::deserialize(&buf[..size])?;
+        while let Some(request) = requests.recv().await {
+            let IncomingRequest {
+                id,
+                client_only,
+                message,
+                src,
+            } = request;
 
             // Clone Arc and get mutex lock
             let mut routing_table = routing_table.lock().await;
 
+            // Banned peers returned invalid data before and are ignored
+            if routing_table.is_banned(&message.sender()) {
+                log::debug!("Ignoring request from banned node {}", message.sender());
+                continue;
+            }
+
+            // Checked before the sender enters the routing table below
+            let known_relay = matches!(message, RpcMessage::PunchNotify { .. })
+                && routing_table.address_of(&message.sender()) == Some(src);
+
+            // Client-only peers are not reachable by others, so they are
+            // remembered for relaying instead of entering the routing table
+            if client_only {
+                if !self.nat_clients.lock().register(message.sender(), src) {
+                    log::debug!("Not relaying for {} at {}", message.sender(), src);
+                }
+            } else {
+                routing_table.update(message.sender(), src);
+            }
+
             let response = match message {
-                RpcMessage::Ping { sender } => {
-                    // Update routing table and respond with Pong
-                    routing_table.update(sender);
-                    RpcResponse::Pong { responder: node_id }
+                RpcMessage::Gossip { sender, envelope } => {
+                    if self.gossip_tx.try_send((sender, envelope)).is_err() {
+                        log::debug!("Dropping gossip from {}: queue full", src);
+                    }
+                    continue;
+                }
+                RpcMessage::AppRequest {
+                    sender,
+                    protocol,
+                    payload,
+                } => {
+                    let request = AppRequest {
+                        sender,
+                        sender_addr: src,
+                        protocol,
+                        payload,
+                    };
+                    let reply = AppReply {
+                        transport: Arc::clone(&self.transport),
+                        addr: src,
+                        id,
+                        responder: node_id,
+                    };
+                    self.queue_app_message(request, Some(reply), src);
+                    continue;
+                }
+                RpcMessage::AppMessage {
+                    sender,
+                    protocol,
+                    payload,
+                } => {
+                    let request = AppRequest {
+                        sender,
+                        sender_addr: src,
+                        protocol,
+                        payload,
+                    };
+                    self.queue_app_message(request, None, src);
+                    continue;
                 }
-                RpcMessage::Store { sender, key, value } => {
-                    self.handle_store(node_id, sender, key, value, &storage, &mut routing_table)
+                RpcMessage::Ping { .. } => RpcResponse::Pong {
+                    responder: node_id,
+                    observed: src,
+                },
+                RpcMessage::Store {
+                    key, value, mode, ..
+                } => {
+                    self.handle_store(node_id, key, value, mode, None, &storage)
+                        .await
+                }
+                RpcMessage::StoreWithTtl {
+                    key,
+                    value,
+                    mode,
+                    ttl_secs,
+                    ..
+                } => {
+                    let ttl = Some(Duration::from_secs(ttl_secs));
+                    self.handle_store(node_id, key, value, mode, ttl, &storage)
                         .await
                 }
-                RpcMessage::FindNode { sender, target } => {
-                    self.handle_find_node(node_id, sender, target, &mut routing_table)
+                RpcMessage::FindNode { target, .. } => {
+                    self.handle_find_node(node_id, target, &routing_table).await
+                }
+                RpcMessage::FindValue { key, .. } => {
+                    self.handle_find_value(node_id, key, &storage, &routing_table)
                         .await
                 }
-                RpcMessage::FindValue { sender, key } => {
-                    self.handle_find_value(node_id, sender, key, &storage, &mut routing_table)
+                RpcMessage::GetVersion { key, .. } => {
+                    self.handle_get_version(node_id, key, &storage).await
+                }
+                RpcMessage::StoreShard { key, shard, .. } => {
+                    self.handle_store_shard(node_id, key, shard, &storage).await
+                }
+                RpcMessage::RelayConnect { sender, target } => {
+                    self.handle_relay_connect(node_id, sender, target, src)
+                        .await
+                }
+                RpcMessage::PunchNotify {
+                    peer, peer_addr, ..
+                } => {
+                    self.handle_punch_notify(node_id, peer, peer_addr, src, known_relay)
                         .await
                 }
             };
@@ -234,57 +917,164 @@ impl RpcServer {
             drop(routing_table);
 
             // Send response
-            let response_bytes = bincode::serialize(&response)?;
-            self.socket.send_to(&response_bytes, src).await?;
+            if let Err(e) = self.transport.respond(src, id, response).await {
+                log::debug!("Failed to respond to {}: {}", src, e);
+            }
         }
+
+        Ok(())
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.reader.abort();
     }
 }
 
 impl RpcClient {
-    /// Creates a new RPC client bound to an arbitrary port.
+    /// Creates a new RPC client for the default network bound to an arbitrary port.
     pub async fn new() -> Result<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        Ok(RpcClient { socket })
+        Self::with_network(NetworkId::default()).await
+    }
+
+    /// Creates a new RPC client for the given network bound to an arbitrary port.
+    ///
+    /// Nodes of other networks refuse its requests.
+    pub async fn with_network(network: NetworkId) -> Result<Self> {
+        let transport = Transport::bind(SocketAddr::from(([0, 0, 0, 0], 0)), network).await?;
+        let reader = transport.spawn_reader(None);
+        Ok(RpcClient {
+            transport,
+            reader: Some(reader),
+        })
+    }
+
+    /// Returns the network the client's requests are sent for.
+    pub fn network(&self) -> &NetworkId {
+        &self.transport.network
+    }
+
+    /// Marks outgoing requests as coming from a client-only node.
+    ///
+    /// Peers receiving requests from a client-only node will not add it to
+    /// their routing tables, since other nodes could not reach it.
+    pub fn set_client_only(&self, client_only: bool) {
+        self.transport
+            .client_only
+            .store(client_only, Ordering::Relaxed);
+    }
+
+    /// Returns whether outgoing requests are marked as client-only.
+    pub fn is_client_only(&self) -> bool {
+        self.transport.client_only.load(Ordering::Relaxed)
     }
 
     /// Sends a PING RPC to check if a node is alive.
     pub async fn ping(&self, node: NodeId, addr: SocketAddr) -> Result<bool> {
         let message = RpcMessage::Ping { sender: node };
 
-        let message_bytes = bincode::serialize(&message)?;
-        self.socket.send_to(&message_bytes, addr).await?;
-
-        let mut buf = vec![0u8; 65536];
-        let (size, _) = self.socket.recv_from(&mut buf).await?;
-
-        match bincode::deserialize(&buf[..size])? {
+        match self.transport.call(addr, message).await? {
             RpcResponse::Pong { .. } => Ok(true),
             _ => Ok(false),
         }
     }
 
+    /// Sends a PING RPC and returns the ID of the node that answered.
+    ///
+    /// Used to learn the identity of a peer known only by its address, such as
+    /// a bootstrap node.
+    pub async fn identify(&self, node: NodeId, addr: SocketAddr) -> Result<NodeId> {
+        let message = RpcMessage::Ping { sender: node };
+
+        match self.transport.call(addr, message).await? {
+            RpcResponse::Pong { responder, .. } => Ok(responder),
+            _ => Err(anyhow!("Unexpected response to ping from {}", addr)),
+        }
+    }
+
+    /// Sends a PING RPC and returns the address the peer observed it from.
+    ///
+    /// Asking several peers reveals the public endpoint of this node when it
+    /// sits behind a NAT.
+    pub async fn observed_addr(&self, node: NodeId, addr: SocketAddr) -> Result<SocketAddr> {
+        let message = RpcMessage::Ping { sender: node };
+
+        match self.transport.call(addr, message).await? {
+            RpcResponse::Pong { observed, .. } => Ok(observed),
+            _ => Err(anyhow!("Unexpected response to ping from {}", addr)),
+        }
+    }
+
     /// Sends a STORE RPC to store a key-value pair on a node.
+    ///
+    /// The receiving node verifies the value in the given mode and refuses it
+    /// if it doesn't match the key.
     pub async fn store(
         &self,
         node: NodeId,
-        target: NodeId,
         addr: SocketAddr,
         key: Key,
         value: Vec<u8>,
+        mode: ValueMode,
     ) -> Result<bool> {
         let message = RpcMessage::Store {
             sender: node,
             key,
             value,
+            mode,
         };
 
-        let message_bytes = bincode::serialize(&message)?;
-        self.socket.send_to(&message_bytes, addr).await?;
+        match self.transport.call(addr, message).await? {
+            RpcResponse::Stored { success, .. } => Ok(success),
+            _ => Ok(false),
+        }
+    }
 
-        let mut buf = vec![0u8; 65536];
-        let (size, _) = self.socket.recv_from(&mut buf).await?;
+    /// Sends a STORE_SHARD RPC to store a shard of an erasure-coded object.
+    ///
+    /// The receiving node checks the shard against its content-addressed key
+    /// and keeps it without republishing it.
+    pub async fn store_shard(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+        key: Key,
+        shard: Vec<u8>,
+    ) -> Result<bool> {
+        let message = RpcMessage::StoreShard {
+            sender: node,
+            key,
+            shard,
+        };
 
-        match bincode::deserialize(&buf[..size])? {
+        match self.transport.call(addr, message).await? {
+            RpcResponse::Stored { success, .. } => Ok(success),
+            _ => Ok(false),
+        }
+    }
+
+    /// Sends a STORE RPC asking the node to keep the value for `ttl`.
+    ///
+    /// The node shortens TTLs beyond the maximum of its storage policy.
+    pub async fn store_with_ttl(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+        key: Key,
+        value: Vec<u8>,
+        mode: ValueMode,
+        ttl: Duration,
+    ) -> Result<bool> {
+        let message = RpcMessage::StoreWithTtl {
+            sender: node,
+            key,
+            value,
+            mode,
+            ttl_secs: ttl.as_secs(),
+        };
+
+        match self.transport.call(addr, message).await? {
             RpcResponse::Stored { success, .. } => Ok(success),
             _ => Ok(false),
         }
@@ -302,19 +1092,16 @@ impl RpcClient {
             target,
         };
 
-        let message_bytes = bincode::serialize(&message)?;
-        self.socket.send_to(&message_bytes, addr).await?;
-
-        let mut buf = vec![0u8; 65536];
-        let (size, _) = self.socket.recv_from(&mut buf).await?;
-
-        match bincode::deserialize(&buf[..size])? {
+        match self.transport.call(addr, message).await? {
             RpcResponse::NodesFound { nodes, .. } => Ok(nodes),
             _ => Ok(vec![]),
         }
     }
 
     /// Sends a FIND_VALUE RPC to retrieve a stored value.
+    ///
+    /// The returned value comes straight from the peer and has to be verified
+    /// by the caller, see [`crate::integrity::verify`].
     pub async fn find_value(
         &self,
         node: NodeId,
@@ -323,16 +1110,185 @@ impl RpcClient {
     ) -> Result<Result<Vec<u8>, Vec<(NodeId, SocketAddr)>>> {
         let message = RpcMessage::FindValue { sender: node, key };
 
-        let message_bytes = bincode::serialize(&message)?;
-        self.socket.send_to(&message_bytes, addr).await?;
-
-        let mut buf = vec![0u8; 65536];
-        let (size, _) = self.socket.recv_from(&mut buf).await?;
-
-        match bincode::deserialize(&buf[..size])? {
+        match self.transport.call(addr, message).await? {
             RpcResponse::ValueFound { value, .. } => Ok(Ok(value)),
             RpcResponse::NodesFound { nodes, .. } => Ok(Err(nodes)),
             _ => Ok(Err(vec![])),
         }
     }
+
+    /// Sends a GET_VERSION RPC asking which version of a value a node holds.
+    ///
+    /// # Returns
+    /// * `Result<Option<ValueVersion>>` - The version, or `None` if the node holds no value
+    pub async fn get_version(
+        &self,
+        node: NodeId,
+        key: Key,
+        addr: SocketAddr,
+    ) -> Result<Option<ValueVersion>> {
+        let message = RpcMessage::GetVersion { sender: node, key };
+
+        match self.transport.call(addr, message).await? {
+            RpcResponse::Version { version, .. } => Ok(version),
+            _ => Err(anyhow!(
+                "Unexpected response to a version request from {}",
+                addr
+            )),
+        }
+    }
+
+    /// Sends an application request and waits for the handler's answer.
+    ///
+    /// Fails if the node has no handler for the protocol or the handler fails.
+    pub async fn app_request(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+        protocol: &str,
+        payload: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let message = RpcMessage::AppRequest {
+            sender: node,
+            protocol: protocol.to_string(),
+            payload,
+        };
+
+        match self.transport.call(addr, message).await? {
+            RpcResponse::App { result, .. } => {
+                result.map_err(|e| anyhow!("Request to {} failed: {}", addr, e))
+            }
+            _ => Err(anyhow!(
+                "Unexpected response to application request from {}",
+                addr
+            )),
+        }
+    }
+
+    /// Sends a one-way application message without waiting for a response.
+    pub async fn app_message(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+        protocol: &str,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        let message = RpcMessage::AppMessage {
+            sender: node,
+            protocol: protocol.to_string(),
+            payload,
+        };
+        self.transport.notify(addr, message).await
+    }
+
+    /// Sends a GOSSIP message to a node without waiting for a response.
+    pub(crate) async fn gossip(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+        envelope: GossipEnvelope,
+    ) -> Result<()> {
+        let message = RpcMessage::Gossip {
+            sender: node,
+            envelope,
+        };
+        self.transport.notify(addr, message).await
+    }
+
+    /// Sends a RELAY_CONNECT RPC asking `relay` to coordinate a hole punch
+    /// towards the client-only node `target`.
+    ///
+    /// # Returns
+    /// * `Result<Option<SocketAddr>>` - The target's address as seen by the relay,
+    ///   or `None` if the relay does not know the target
+    pub async fn relay_connect(
+        &self,
+        node: NodeId,
+        relay: SocketAddr,
+        target: NodeId,
+    ) -> Result<Option<SocketAddr>> {
+        let message = RpcMessage::RelayConnect {
+            sender: node,
+            target,
+        };
+
+        match self.transport.call(relay, message).await? {
+            RpcResponse::RelayConnected { target_addr, .. } => Ok(target_addr),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for RpcClient {
+    fn drop(&mut self) {
+        if let Some(reader) = &self.reader {
+            reader.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_from_other_networks_are_refused() {
+        let network: NetworkId = "staging".parse().unwrap();
+        let server = Arc::new(
+            RpcServer::bind_with_network("127.0.0.1:0".parse().unwrap(), network.clone())
+                .await
+                .unwrap(),
+        );
+        let addr = server.local_addr().unwrap();
+        let server_id = NodeId::random();
+        let serving = tokio::spawn({
+            let server = Arc::clone(&server);
+            let routing_table = Arc::new(Mutex::new(RoutingTable::new(server_id)));
+            async move {
+                server
+                    .start(server_id, Storage::in_memory(), routing_table)
+                    .await
+            }
+        });
+
+        let outsider = RpcClient::new().await.unwrap();
+        let refused = outsider.ping(NodeId::random(), addr).await.unwrap_err();
+        assert!(refused.to_string().contains("belongs to network staging"));
+
+        let member = RpcClient::with_network(network).await.unwrap();
+        assert!(member.ping(NodeId::random(), addr).await.unwrap());
+        serving.abort();
+    }
+
+    #[tokio::test]
+    async fn test_responses_from_other_addresses_are_ignored() {
+        let node = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = RpcClient::new().await.unwrap();
+        let node_addr = node.local_addr().unwrap();
+        let (node_id, spoofed_id) = (NodeId::random(), NodeId::random());
+
+        let answering = tokio::spawn(async move {
+            let mut buf = vec![0u8; 65536];
+            let (size, src) = node.recv_from(&mut buf).await.unwrap();
+            let Ok(RpcPacket::Request { id, .. }) = bincode::deserialize(&buf[..size]) else {
+                panic!("expected a request");
+            };
+            let pong = |responder| {
+                let response = RpcResponse::Pong {
+                    responder,
+                    observed: src,
+                };
+                bincode::serialize(&RpcPacket::Response { id, response }).unwrap()
+            };
+            // Another host answers first with the right ID
+            spoofer.send_to(&pong(spoofed_id), src).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            node.send_to(&pong(node_id), src).await.unwrap();
+        });
+
+        let responder = client.identify(NodeId::random(), node_addr).await.unwrap();
+        assert_eq!(responder, node_id);
+        answering.await.unwrap();
+    }
 }
@@ -0,0 +1,236 @@
+//! Lightweight participation in the overlay for tools and short-lived clients.
+//!
+//! A [`ClientSession`] runs iterative lookups like a node, with its own routing
+//! table, but doesn't serve requests or store records. It announces itself as
+//! client-only, so nodes don't add it to their k-buckets. Values are read from
+//! and written to the k nodes closest to their keys, not just to the node the
+//! session was bootstrapped from.
+
+use crate::control::BucketDump;
+use crate::integrity::{self, ValueMode};
+use crate::lookup::{LookupContext, LookupHop, LookupQuery, LookupResult};
+use crate::rpc::RpcClient;
+use crate::{Key, NetworkId, NodeId, RoutingTable};
+use anyhow::{bail, Result};
+use futures::future::join_all;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// How a node answered a store request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoreStatus {
+    /// The node stored the value
+    Accepted,
+    /// The node refused the value, for example because it failed verification
+    Refused,
+    /// The request failed
+    Failed(String),
+}
+
+/// The answer of one node to a store request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreOutcome {
+    /// ID of the node
+    pub node: NodeId,
+    /// Address of the node
+    pub addr: SocketAddr,
+    /// What the node answered
+    pub status: StoreStatus,
+}
+
+/// A value found in the overlay.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FoundValue {
+    /// The value, verified against its key
+    pub value: Vec<u8>,
+    /// ID of the node that returned the value
+    pub holder: NodeId,
+    /// Address of the node that returned the value, if it is still known
+    pub holder_addr: Option<SocketAddr>,
+}
+
+/// The queries of a value lookup and what it found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupTrace {
+    /// Every query of the lookup, in the order they finished
+    pub hops: Vec<LookupHop>,
+    /// The value, if a peer returned one that verified
+    pub found: Option<FoundValue>,
+    /// The closest nodes that answered, if no value was found
+    pub closest: Vec<(NodeId, SocketAddr)>,
+}
+
+/// A non-storing participant in the overlay.
+pub struct ClientSession {
+    /// Random ID used for the session's lookups
+    id: NodeId,
+    /// Client for the session's RPCs, marked as client-only
+    client: RpcClient,
+    /// Contacts learned during the session
+    routing_table: Mutex<RoutingTable>,
+}
+
+impl ClientSession {
+    /// Opens a session in the given overlay network.
+    ///
+    /// The session knows no nodes until [`ClientSession::bootstrap`] is called.
+    pub async fn new(network: NetworkId) -> Result<Self> {
+        let id = NodeId::random();
+        let client = RpcClient::with_network(network).await?;
+        client.set_client_only(true);
+        Ok(ClientSession {
+            id,
+            client,
+            routing_table: Mutex::new(RoutingTable::new(id)),
+        })
+    }
+
+    /// Returns the ID the session uses in its requests.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the client sending the session's RPCs.
+    pub fn client(&self) -> &RpcClient {
+        &self.client
+    }
+
+    /// Learns the network through the given seed nodes.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of nodes known afterwards
+    pub async fn bootstrap(&self, seeds: &[SocketAddr]) -> Result<usize> {
+        self.lookup_context().contact_seeds(seeds).await?;
+        Ok(self.routing_table.lock().await.len())
+    }
+
+    /// Returns the contacts the session knows, closest to the given key first.
+    pub async fn known_nodes(&self, key: &Key, count: usize) -> Vec<(NodeId, SocketAddr)> {
+        self.routing_table.lock().await.closest_nodes(key, count)
+    }
+
+    /// Returns the contents of all non-empty k-buckets of the session.
+    pub async fn routing_table_dump(&self) -> Vec<BucketDump> {
+        BucketDump::of(&*self.routing_table.lock().await)
+    }
+
+    /// Looks up the k closest nodes to a key.
+    pub async fn lookup_nodes(&self, key: Key) -> Result<Vec<(NodeId, SocketAddr)>> {
+        match self.lookup_context().run(key, LookupQuery::Nodes).await? {
+            LookupResult::Nodes(nodes) => Ok(nodes),
+            LookupResult::Value { .. } => unreachable!("node lookups don't return values"),
+        }
+    }
+
+    /// Looks up a value verifying against its key in the given mode.
+    pub async fn get(&self, key: Key, mode: ValueMode) -> Result<Option<FoundValue>> {
+        match self
+            .lookup_context()
+            .run(key, LookupQuery::Value(mode))
+            .await?
+        {
+            LookupResult::Value { value, holder } => {
+                let holder_addr = self.routing_table.lock().await.address_of(&holder);
+                Ok(Some(FoundValue {
+                    value,
+                    holder,
+                    holder_addr,
+                }))
+            }
+            LookupResult::Nodes(_) => Ok(None),
+        }
+    }
+
+    /// Looks up a value like [`ClientSession::get`], recording every query.
+    pub async fn trace(&self, key: Key, mode: ValueMode) -> Result<LookupTrace> {
+        let mut hops = Vec::new();
+        let result = self
+            .lookup_context()
+            .run_traced(key, LookupQuery::Value(mode), Some(&mut hops))
+            .await?;
+        let (found, closest) = match result {
+            LookupResult::Value { value, holder } => {
+                let holder_addr = self.routing_table.lock().await.address_of(&holder);
+                let found = FoundValue {
+                    value,
+                    holder,
+                    holder_addr,
+                };
+                (Some(found), Vec::new())
+            }
+            LookupResult::Nodes(nodes) => (None, nodes),
+        };
+        Ok(LookupTrace {
+            hops,
+            found,
+            closest,
+        })
+    }
+
+    /// Stores a value on the k closest nodes to its key.
+    ///
+    /// # Returns
+    /// * `Result<Vec<StoreOutcome>>` - The answer of every node asked, closest first
+    pub async fn put(
+        &self,
+        key: Key,
+        value: Vec<u8>,
+        mode: ValueMode,
+    ) -> Result<Vec<StoreOutcome>> {
+        self.put_with_ttl(key, value, mode, None).await
+    }
+
+    /// Stores a value on the k closest nodes to its key, kept for `ttl` if given.
+    ///
+    /// Without a TTL, the nodes keep the value for their default TTL.
+    pub async fn put_with_ttl(
+        &self,
+        key: Key,
+        value: Vec<u8>,
+        mode: ValueMode,
+        ttl: Option<Duration>,
+    ) -> Result<Vec<StoreOutcome>> {
+        if !integrity::verify(&key, mode, &value) {
+            bail!("Value does not match key {} in mode {:?}", key, mode);
+        }
+
+        let nodes = self.lookup_nodes(key).await?;
+        if nodes.is_empty() {
+            bail!("No nodes found to store {} on", key);
+        }
+
+        let stores = nodes.iter().map(|(node, addr)| {
+            let value = value.clone();
+            async move {
+                let stored = match ttl {
+                    Some(ttl) => {
+                        self.client
+                            .store_with_ttl(self.id, *addr, key, value, mode, ttl)
+                            .await
+                    }
+                    None => self.client.store(self.id, *addr, key, value, mode).await,
+                };
+                let status = match stored {
+                    Ok(true) => StoreStatus::Accepted,
+                    Ok(false) => StoreStatus::Refused,
+                    Err(e) => StoreStatus::Failed(e.to_string()),
+                };
+                StoreOutcome {
+                    node: *node,
+                    addr: *addr,
+                    status,
+                }
+            }
+        });
+        Ok(join_all(stores).await)
+    }
+
+    fn lookup_context(&self) -> LookupContext<'_> {
+        LookupContext {
+            client: &self.client,
+            routing_table: &self.routing_table,
+            own_id: self.id,
+        }
+    }
+}
@@ -1,18 +1,226 @@
-use crate::Key;
-use anyhow::Result;
-use sled::Db;
+//! Persistent storage for the records held by a Kademlia node.
+//!
+//! Records are grouped into namespaces, each kept apart by the storage backend, so
+//! that application values, provider records, routing snapshots and package chunks
+//! can share a key without colliding. Every record carries a [`RecordKind`] tag and an
+//! absolute expiry time, and every namespace has a [`NamespacePolicy`] bounding the
+//! TTL of its records and the space they may use.
+//!
+//! A storage is further partitioned by [`NetworkId`]: the views returned by
+//! [`Storage::for_network`] share one backend but never see each other's records,
+//! so a process taking part in several overlays can keep their data in one place.
+
+use crate::backend::{BackendKind, MemoryBackend, SledBackend, StorageBackend, StorageConfig};
+use crate::log_backend::LogBackend;
+use crate::object::ObjectManifest;
+use crate::{Key, NetworkId};
+use anyhow::{bail, Result};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Bound;
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Well-known namespace names.
+pub mod namespaces {
+    /// Values stored through the STORE RPC
+    pub const VALUES: &str = "values";
+    /// Provider records announcing which nodes hold some content
+    pub const PROVIDERS: &str = "providers";
+    /// Snapshots of the routing table
+    pub const ROUTING: &str = "routing";
+    /// Chunks of distributed packages
+    pub const PACKAGES: &str = "packages";
+    /// Keys of the values that are manifests of erasure-coded objects
+    pub const MANIFESTS: &str = "manifests";
+}
+
+/// Prefix of the backend namespaces belonging to a network other than the default one.
+const NETWORK_PREFIX: &str = "net/";
+
+/// Number of entries fetched from the backend at a time when iterating.
+const ITER_PAGE_SIZE: usize = 256;
+
+/// Size of the header preceding every stored value: expiry (8 bytes) and kind (1 byte).
+const RECORD_HEADER_SIZE: usize = 9;
+
+/// The kind of a stored record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RecordKind {
+    /// A plain value
+    Value,
+    /// A provider record pointing to nodes that hold some content
+    Provider,
+    /// A marker recording that a value was deleted
+    Tombstone,
+    /// One chunk of a package or larger object
+    PackageChunk,
+    /// Metadata describing other records
+    Metadata,
+    /// One shard of an erasure-coded object; shards are stored once each, so
+    /// they are kept up by repairs instead of being republished
+    Shard,
+}
+
+impl RecordKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            RecordKind::Value => 0,
+            RecordKind::Provider => 1,
+            RecordKind::Tombstone => 2,
+            RecordKind::PackageChunk => 3,
+            RecordKind::Metadata => 4,
+            RecordKind::Shard => 5,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(RecordKind::Value),
+            1 => Some(RecordKind::Provider),
+            2 => Some(RecordKind::Tombstone),
+            3 => Some(RecordKind::PackageChunk),
+            4 => Some(RecordKind::Metadata),
+            5 => Some(RecordKind::Shard),
+            _ => None,
+        }
+    }
+}
+
+/// A record stored in a namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The kind of the record
+    pub kind: RecordKind,
+    /// The stored bytes
+    pub value: Vec<u8>,
+    /// Point in time after which the record is dropped
+    pub expires_at: SystemTime,
+}
+
+impl Record {
+    /// Returns whether the record has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+
+    /// Encodes the record as handed to the backend: expiry, kind tag, then the value.
+    fn encode(&self) -> Vec<u8> {
+        let expiry_secs = self
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + self.value.len());
+        bytes.extend_from_slice(&expiry_secs.to_be_bytes());
+        bytes.push(self.kind.to_byte());
+        bytes.extend_from_slice(&self.value);
+        bytes
+    }
+
+    /// Decodes a record, returning `None` for malformed data.
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < RECORD_HEADER_SIZE {
+            return None;
+        }
 
-/// Persistent storage implementation for a Kademlia node using Sled.
+        let mut expiry_bytes = [0u8; 8];
+        expiry_bytes.copy_from_slice(&bytes[..8]);
+        let expiry_secs = u64::from_be_bytes(expiry_bytes);
+
+        Some(Record {
+            kind: RecordKind::from_byte(bytes[8])?,
+            value: bytes[RECORD_HEADER_SIZE..].to_vec(),
+            expires_at: UNIX_EPOCH + Duration::from_secs(expiry_secs),
+        })
+    }
+}
+
+/// Limits applied to the records of a namespace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespacePolicy {
+    /// TTL used when the caller has no specific TTL
+    pub default_ttl: Duration,
+    /// Upper bound for the TTL of any record; longer TTLs are shortened
+    pub max_ttl: Duration,
+    /// Maximum total size of the stored values in bytes, if limited
+    pub quota_bytes: Option<u64>,
+    /// Maximum number of records, if limited
+    pub max_entries: Option<u64>,
+}
+
+impl Default for NamespacePolicy {
+    /// Kademlia's 24 hour expiry, with records allowed to live up to a week.
+    fn default() -> Self {
+        NamespacePolicy {
+            default_ttl: Duration::from_secs(24 * 60 * 60),
+            max_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            quota_bytes: None,
+            max_entries: None,
+        }
+    }
+}
+
+/// One page of keys listed by [`Namespace::list`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyPage {
+    /// Keys of the live records on the page and the sizes of their values
+    pub keys: Vec<(Key, usize)>,
+    /// Last key scanned, to continue the listing after, if more keys may follow
+    pub next: Option<Key>,
+}
+
+/// Summary statistics about the contents of a [`Storage`] or one of its namespaces.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct StorageStats {
+    /// Number of stored entries, including expired ones not yet cleaned up
+    pub entries: u64,
+    /// Total size of the stored values in bytes
+    pub value_bytes: u64,
+    /// Size of the database on disk in bytes
+    pub disk_bytes: u64,
+}
+
+/// Shared state of an open namespace.
+struct NamespaceState {
+    /// Backend holding the records
+    backend: Arc<dyn StorageBackend>,
+    /// Limits applied to the records
+    policy: RwLock<NamespacePolicy>,
+    /// Current number of records and their total size, for quota checks
+    usage: Mutex<StorageStats>,
+}
+
+/// A handle to one namespace of a [`Storage`].
+///
+/// Handles are cheap to clone and all handles to a namespace share its policy
+/// and usage accounting.
+#[derive(Clone)]
+pub struct Namespace {
+    /// Name of the namespace
+    name: String,
+    /// Name of the namespace in the backend, including the network prefix
+    backend_name: String,
+    /// State shared by all handles to the namespace
+    state: Arc<NamespaceState>,
+}
+
+/// Storage for the records of a Kademlia node, on top of a pluggable backend.
 #[derive(Clone)]
 pub struct Storage {
-    /// Sled database instance
-    db: Db,
+    /// Backend holding the encoded records
+    backend: Arc<dyn StorageBackend>,
+    /// Namespaces opened so far by any view of the backend, by backend name
+    namespaces: Arc<RwLock<HashMap<String, Namespace>>>,
+    /// Prefix of this view's namespaces in the backend; empty for the default network
+    prefix: String,
 }
 
 impl Storage {
-    /// Creates a new storage instance with Sled backend.
+    /// Creates a new storage instance with Sled backend and default configuration.
     ///
     /// # Arguments
     /// * `path` - Path where the database files will be stored
@@ -20,99 +228,859 @@ impl Storage {
     /// # Returns
     /// A new Storage instance or an error if database creation fails
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let db = sled::Config::new()
-            .path(path)
-            .mode(sled::Mode::HighThroughput) // Optimize for DHT workload
-            .cache_capacity(1024 * 1024 * 64) // 64MB cache
-            .open()?;
+        Self::with_config(path, &StorageConfig::default())
+    }
+
+    /// Creates a new storage instance with the configured backend.
+    ///
+    /// # Arguments
+    /// * `path` - Path where the backend stores its files, if it has any
+    /// * `config` - Backend, cache size and flush policy to use
+    pub fn with_config<P: AsRef<Path>>(path: P, config: &StorageConfig) -> Result<Self> {
+        let storage = match config.backend {
+            BackendKind::Sled => {
+                let backend = Arc::new(SledBackend::open(path, config)?);
+                let storage = Self::with_backend(Arc::clone(&backend) as Arc<dyn StorageBackend>);
+                storage.migrate_legacy_values(&backend)?;
+                storage
+            }
+            BackendKind::Memory => Self::in_memory(),
+            BackendKind::Log => Self::with_backend(Arc::new(LogBackend::open(path, config)?)),
+        };
+        Ok(storage)
+    }
+
+    /// Moves values stored before namespaces were introduced into [`namespaces::VALUES`].
+    ///
+    /// Those versions kept values in sled's default tree, each prefixed with its
+    /// TTL in seconds as a big-endian `u64`. The TTL is counted again from now.
+    fn migrate_legacy_values(&self, backend: &SledBackend) -> Result<()> {
+        let legacy = backend.legacy_entries()?;
+        if legacy.is_empty() {
+            return Ok(());
+        }
+
+        let values = self.namespace(namespaces::VALUES)?;
+        let mut migrated = 0;
+        for (key, bytes) in &legacy {
+            let (Ok(key), Some((ttl, value))) = (
+                Key::try_from(key.as_slice()),
+                bytes.split_first_chunk::<8>(),
+            ) else {
+                log::warn!(
+                    "Dropping malformed legacy entry with a {} byte key",
+                    key.len()
+                );
+                continue;
+            };
+            let ttl = Duration::from_secs(u64::from_be_bytes(*ttl));
+            values.store(key, RecordKind::Value, value.to_vec(), ttl)?;
+            migrated += 1;
+        }
+
+        // Only cleared once everything was copied, so an interrupted migration is redone
+        backend.clear_legacy_entries()?;
+        log::info!(
+            "Migrated {} values from the legacy storage layout",
+            migrated
+        );
+        Ok(())
+    }
+
+    /// Creates a new storage instance keeping all data in memory.
+    pub fn in_memory() -> Self {
+        Self::with_backend(Arc::new(MemoryBackend::new()))
+    }
+
+    /// Creates a new storage instance on top of the given backend.
+    pub fn with_backend(backend: Arc<dyn StorageBackend>) -> Self {
+        Storage {
+            backend,
+            namespaces: Arc::new(RwLock::new(HashMap::new())),
+            prefix: String::new(),
+        }
+    }
+
+    /// Returns a view of this storage holding the records of one network.
+    ///
+    /// Views share the backend but not their namespaces; views of the same
+    /// network share the state of their namespaces. The default network uses
+    /// the namespaces as they are, so data stored before networks were
+    /// introduced stays visible to it.
+    pub fn for_network(&self, network: &NetworkId) -> Self {
+        let prefix = if network.is_default() {
+            String::new()
+        } else {
+            format!("{}{}/", NETWORK_PREFIX, network)
+        };
+        if prefix == self.prefix {
+            return self.clone();
+        }
+
+        Storage {
+            backend: Arc::clone(&self.backend),
+            namespaces: Arc::clone(&self.namespaces),
+            prefix,
+        }
+    }
+
+    /// Opens a namespace, creating it if it doesn't exist yet.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the namespace, e.g. one of [`namespaces`]
+    ///
+    /// # Returns
+    /// A handle to the namespace, using the default policy unless one was set.
+    /// Names starting with the prefix reserved for other networks are refused,
+    /// so a view can't reach into another network's data.
+    pub fn namespace(&self, name: &str) -> Result<Namespace> {
+        if name.is_empty() || name.starts_with(NETWORK_PREFIX) {
+            bail!("Invalid namespace name '{}'", name);
+        }
+        let backend_name = format!("{}{}", self.prefix, name);
+        if let Some(namespace) = self.namespaces.read().get(&backend_name) {
+            return Ok(namespace.clone());
+        }
+
+        let mut namespaces = self.namespaces.write();
+        if let Some(namespace) = namespaces.get(&backend_name) {
+            return Ok(namespace.clone());
+        }
+
+        let namespace = Namespace {
+            name: name.to_string(),
+            backend_name: backend_name.clone(),
+            state: Arc::new(NamespaceState {
+                backend: Arc::clone(&self.backend),
+                policy: RwLock::new(NamespacePolicy::default()),
+                usage: Mutex::new(StorageStats::default()),
+            }),
+        };
 
-        Ok(Storage { db })
+        let mut usage = StorageStats::default();
+        for item in namespace.raw_entries() {
+            let (_, bytes) = item?;
+            usage.entries += 1;
+            usage.value_bytes += bytes.len().saturating_sub(RECORD_HEADER_SIZE) as u64;
+        }
+        *namespace.state.usage.lock() = usage;
+
+        namespaces.insert(backend_name, namespace.clone());
+        Ok(namespace)
+    }
+
+    /// Sets the policy of a namespace, opening it if necessary.
+    ///
+    /// Policies are not persisted; nodes configure them at startup. Records
+    /// already stored are not affected until they are rewritten.
+    pub fn set_policy(&self, name: &str, policy: NamespacePolicy) -> Result<()> {
+        *self.namespace(name)?.state.policy.write() = policy;
+        Ok(())
+    }
+
+    /// Returns the names of all namespaces of this network that hold data.
+    pub fn namespace_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .backend
+            .namespaces()?
+            .into_iter()
+            .filter_map(|name| {
+                if self.prefix.is_empty() {
+                    (!name.starts_with(NETWORK_PREFIX)).then_some(name)
+                } else {
+                    name.strip_prefix(&self.prefix).map(str::to_string)
+                }
+            })
+            .collect())
     }
 
     /// Stores a value with the specified time-to-live.
     ///
+    /// The value is stored as a [`RecordKind::Value`] in the [`namespaces::VALUES`]
+    /// namespace. Manifests of erasure-coded objects are also recorded in the
+    /// [`namespaces::MANIFESTS`] namespace, so that repairs find them without
+    /// reading every value.
+    ///
     /// # Arguments
     /// * `key` - The key under which to store the value
     /// * `value` - The value to store
     /// * `ttl` - Duration after which the value should expire
-    pub fn store(&self, key: Key, mut value: Vec<u8>, ttl: Duration) -> Result<()> {
-        // Prepend expiry timestamp to value
-        let expiry = Instant::now() + ttl;
-        let expiry_bytes = expiry
-            .duration_since(Instant::now())
-            .as_secs()
-            .to_be_bytes();
-        let mut stored_value = expiry_bytes.to_vec();
-        stored_value.extend(value);
-
-        self.db.insert(key.as_bytes(), stored_value)?;
-        self.db.flush()?;
+    pub fn store(&self, key: Key, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let is_manifest = ObjectManifest::is_erasure_coded(&value);
+        self.namespace(namespaces::VALUES)?
+            .store(key, RecordKind::Value, value, ttl)?;
+        if is_manifest {
+            self.namespace(namespaces::MANIFESTS)?.store(
+                key,
+                RecordKind::Metadata,
+                Vec::new(),
+                ttl,
+            )?;
+        }
         Ok(())
     }
 
+    /// Returns the TTL given to values stored without a specific TTL.
+    ///
+    /// This is the default TTL of the [`namespaces::VALUES`] namespace.
+    pub fn default_ttl(&self) -> Result<Duration> {
+        Ok(self.namespace(namespaces::VALUES)?.policy().default_ttl)
+    }
+
+    /// Stores a shard of an erasure-coded object with the specified time-to-live.
+    ///
+    /// The shard is stored as a [`RecordKind::Shard`] in the [`namespaces::VALUES`]
+    /// namespace, so it is found like a value but left out of
+    /// [`Storage::entries`] and [`Storage::keys`], and thus not copied to
+    /// other nodes by republishing or handoff.
+    pub fn store_shard(&self, key: Key, shard: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.namespace(namespaces::VALUES)?
+            .store(key, RecordKind::Shard, shard, ttl)
+    }
+
     /// Retrieves a value by its key if it exists and hasn't expired.
     ///
+    /// Only [`RecordKind::Value`] and [`RecordKind::Shard`] records of the
+    /// [`namespaces::VALUES`] namespace are returned; a tombstone hides the
+    /// value it replaced.
+    ///
     /// # Arguments
     /// * `key` - The key to look up
     ///
     /// # Returns
     /// * `Result<Option<Vec<u8>>>` - The value if found and not expired
     pub fn get(&self, key: &Key) -> Result<Option<Vec<u8>>> {
-        if let Some(ivec) = self.db.get(key.as_bytes())? {
-            let value = ivec.to_vec();
+        let record = self.namespace(namespaces::VALUES)?.get(key)?;
+        Ok(record
+            .filter(|record| matches!(record.kind, RecordKind::Value | RecordKind::Shard))
+            .map(|record| record.value))
+    }
 
-            // First 8 bytes are expiry timestamp
-            if value.len() < 8 {
-                return Ok(None);
-            }
+    /// Lists keys of the [`namespaces::VALUES`] namespace, one page at a time.
+    ///
+    /// See [`Namespace::list`].
+    pub fn list(&self, prefix: &[u8], start_after: Option<&Key>, limit: usize) -> Result<KeyPage> {
+        self.namespace(namespaces::VALUES)?
+            .list(prefix, start_after, limit)
+    }
 
-            let mut expiry_bytes = [0u8; 8];
-            expiry_bytes.copy_from_slice(&value[..8]);
-            let expiry_secs = u64::from_be_bytes(expiry_bytes);
+    /// Returns all stored values that haven't expired.
+    ///
+    /// Used to republish the stored data to the network.
+    pub fn entries(&self) -> Result<Vec<(Key, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        for item in self.namespace(namespaces::VALUES)?.iter() {
+            let (key, record) = item?;
+            if record.kind == RecordKind::Value {
+                entries.push((key, record.value));
+            }
+        }
+        Ok(entries)
+    }
 
-            if Duration::from_secs(expiry_secs) > Instant::now().duration_since(Instant::now()) {
-                Ok(Some(value[8..].to_vec()))
-            } else {
-                // Value has expired, remove it
-                self.db.remove(key.as_bytes())?;
-                self.db.flush()?;
-                Ok(None)
+    /// Returns the keys of all stored values that haven't expired.
+    pub fn keys(&self) -> Result<Vec<Key>> {
+        let mut keys = Vec::new();
+        for item in self.namespace(namespaces::VALUES)?.iter() {
+            let (key, record) = item?;
+            if record.kind == RecordKind::Value {
+                keys.push(key);
             }
-        } else {
-            Ok(None)
         }
+        Ok(keys)
     }
 
-    /// Performs cleanup of expired entries.
+    /// Returns the keys of stored values that were manifests of erasure-coded
+    /// objects when they were stored.
     ///
-    /// Iterates through all entries and removes expired ones.
+    /// The value under a key may have expired or changed since, so callers
+    /// check it again before using it.
+    pub fn manifest_keys(&self) -> Result<Vec<Key>> {
+        let mut keys = Vec::new();
+        for item in self.namespace(namespaces::MANIFESTS)?.iter() {
+            keys.push(item?.0);
+        }
+        Ok(keys)
+    }
+
+    /// Collects statistics about the entries of all namespaces.
+    pub fn stats(&self) -> Result<StorageStats> {
+        let mut stats = StorageStats {
+            disk_bytes: self.backend.size_on_disk()?,
+            ..Default::default()
+        };
+        for name in self.namespace_names()? {
+            let usage = self.namespace(&name)?.usage();
+            stats.entries += usage.entries;
+            stats.value_bytes += usage.value_bytes;
+        }
+        Ok(stats)
+    }
+
+    /// Performs cleanup of expired entries in all namespaces.
+    ///
+    /// Iterates through all entries and removes expired ones, then lets the
+    /// backend reclaim the space they used.
     /// This operation can be expensive for large datasets.
     pub fn cleanup(&self) -> Result<()> {
-        let mut batch = sled::Batch::default();
-
-        for item in self.db.iter() {
-            let (key, value) = item?;
-            if value.len() >= 8 {
-                let mut expiry_bytes = [0u8; 8];
-                expiry_bytes.copy_from_slice(&value[..8]);
-                let expiry_secs = u64::from_be_bytes(expiry_bytes);
-
-                if Duration::from_secs(expiry_secs) <= Instant::now().duration_since(Instant::now())
-                {
-                    batch.remove(key);
-                }
-            }
+        for name in self.namespace_names()? {
+            self.namespace(&name)?.cleanup()?;
+        }
+        self.backend.cleanup()?;
+        self.backend.flush()
+    }
+
+    /// Makes all previous writes durable, regardless of the flush policy.
+    pub fn flush(&self) -> Result<()> {
+        self.backend.flush()
+    }
+}
+
+impl Namespace {
+    /// Returns the name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the policy of the namespace.
+    pub fn policy(&self) -> NamespacePolicy {
+        *self.state.policy.read()
+    }
+
+    /// Returns the number of records and their total size.
+    pub fn usage(&self) -> StorageStats {
+        *self.state.usage.lock()
+    }
+
+    /// Stores a record, replacing any previous record under the same key.
+    ///
+    /// # Arguments
+    /// * `key` - The key under which to store the record
+    /// * `kind` - The kind of the record
+    /// * `value` - The bytes to store
+    /// * `ttl` - Time-to-live, shortened to the namespace's maximum TTL
+    ///
+    /// # Errors
+    /// Fails if storing the record would exceed the namespace's quota.
+    pub fn store(&self, key: Key, kind: RecordKind, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let policy = self.policy();
+        let record = Record {
+            kind,
+            value,
+            expires_at: SystemTime::now() + ttl.min(policy.max_ttl),
+        };
+
+        // Hold the usage lock across the check and the write so that
+        // concurrent stores can't overshoot the quota together
+        let mut usage = self.state.usage.lock();
+        let old_size = self
+            .state
+            .backend
+            .get(&self.backend_name, key.as_bytes())?
+            .map(|old| old.len().saturating_sub(RECORD_HEADER_SIZE) as u64);
+
+        let entries = usage.entries + u64::from(old_size.is_none());
+        let value_bytes =
+            usage.value_bytes.saturating_sub(old_size.unwrap_or(0)) + record.value.len() as u64;
+        if policy.max_entries.is_some_and(|max| entries > max) {
+            bail!(
+                "Namespace '{}' is full ({} entries)",
+                self.name,
+                usage.entries
+            );
+        }
+        if policy.quota_bytes.is_some_and(|quota| value_bytes > quota) {
+            bail!(
+                "Storing {} would exceed the quota of namespace '{}'",
+                key,
+                self.name
+            );
         }
 
-        self.db.apply_batch(batch)?;
-        self.db.flush()?;
+        self.state
+            .backend
+            .store(&self.backend_name, key.as_bytes(), record.encode())?;
+        usage.entries = entries;
+        usage.value_bytes = value_bytes;
         Ok(())
     }
+
+    /// Stores a tombstone, hiding any value previously stored under the key.
+    pub fn store_tombstone(&self, key: Key, ttl: Duration) -> Result<()> {
+        self.store(key, RecordKind::Tombstone, Vec::new(), ttl)
+    }
+
+    /// Retrieves a record if it exists and hasn't expired.
+    ///
+    /// Expired records are removed when encountered.
+    pub fn get(&self, key: &Key) -> Result<Option<Record>> {
+        let Some(bytes) = self.state.backend.get(&self.backend_name, key.as_bytes())? else {
+            return Ok(None);
+        };
+
+        match Record::decode(&bytes) {
+            Some(record) if !record.is_expired() => Ok(Some(record)),
+            _ => {
+                self.remove_stale(key.as_bytes(), &bytes)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Removes a record.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether a record was removed
+    pub fn remove(&self, key: &Key) -> Result<bool> {
+        let mut usage = self.state.usage.lock();
+        let Some(old) = self
+            .state
+            .backend
+            .remove(&self.backend_name, key.as_bytes())?
+        else {
+            return Ok(false);
+        };
+
+        usage.entries = usage.entries.saturating_sub(1);
+        usage.value_bytes = usage
+            .value_bytes
+            .saturating_sub(old.len().saturating_sub(RECORD_HEADER_SIZE) as u64);
+        Ok(true)
+    }
+
+    /// Removes an expired or undecodable record, unless it was replaced since
+    /// `bytes` were read, so a concurrent store is never deleted.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether the record was removed
+    fn remove_stale(&self, key: &[u8], bytes: &[u8]) -> Result<bool> {
+        let mut usage = self.state.usage.lock();
+        if !self
+            .state
+            .backend
+            .remove_if(&self.backend_name, key, bytes)?
+        {
+            return Ok(false);
+        }
+
+        usage.entries = usage.entries.saturating_sub(1);
+        usage.value_bytes = usage
+            .value_bytes
+            .saturating_sub(bytes.len().saturating_sub(RECORD_HEADER_SIZE) as u64);
+        Ok(true)
+    }
+
+    /// Iterates over all records of the namespace that haven't expired, in key order.
+    ///
+    /// Records are fetched from the backend a page at a time, so records
+    /// stored while iterating may or may not be returned.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(Key, Record)>> + '_ {
+        self.raw_entries().filter_map(|item| {
+            let (key, bytes) = match item {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            let key = Key::try_from(key.as_slice()).ok()?;
+            let record = Record::decode(&bytes).filter(|record| !record.is_expired())?;
+            Some(Ok((key, record)))
+        })
+    }
+
+    /// Iterates over the encoded entries of the namespace, in key order.
+    fn raw_entries(&self) -> RawEntries<'_> {
+        RawEntries {
+            namespace: self,
+            page: VecDeque::new(),
+            start: Bound::Unbounded,
+            done: false,
+        }
+    }
+
+    /// Lists keys in ascending order, one page at a time.
+    ///
+    /// Expired and undecodable records are skipped, so a page may hold fewer
+    /// than `limit` keys even though more follow; continue after
+    /// [`KeyPage::next`] until it is `None`.
+    ///
+    /// # Arguments
+    /// * `prefix` - Only keys starting with these bytes are listed
+    /// * `start_after` - [`KeyPage::next`] of the previous page, if any
+    /// * `limit` - Maximum number of records to scan
+    ///
+    /// # Returns
+    /// * `Result<KeyPage>` - The keys of the page and where to continue
+    pub fn list(&self, prefix: &[u8], start_after: Option<&Key>, limit: usize) -> Result<KeyPage> {
+        let lower = match start_after {
+            Some(key) if key.as_bytes() >= prefix => Bound::Excluded(key.as_bytes().to_vec()),
+            _ => Bound::Included(prefix.to_vec()),
+        };
+
+        let rows = self
+            .state
+            .backend
+            .iterate(&self.backend_name, lower, limit)?;
+        let full = rows.len() == limit;
+        let mut page = KeyPage::default();
+        let mut last = None;
+        for (key, bytes) in rows {
+            if !key.starts_with(prefix) {
+                return Ok(page);
+            }
+            let Ok(key) = Key::try_from(key.as_slice()) else {
+                continue;
+            };
+            last = Some(key);
+            if let Some(record) = Record::decode(&bytes).filter(|r| !r.is_expired()) {
+                page.keys.push((key, record.value.len()));
+            }
+        }
+        page.next = last.filter(|_| full);
+        Ok(page)
+    }
+
+    /// Removes all expired records of the namespace.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - The number of records removed
+    pub fn cleanup(&self) -> Result<usize> {
+        let mut expired = Vec::new();
+        for item in self.raw_entries() {
+            let (key, bytes) = item?;
+            if Record::decode(&bytes).is_none_or(|record| record.is_expired()) {
+                expired.push((key, bytes));
+            }
+        }
+
+        let mut removed = 0;
+        for (key, bytes) in &expired {
+            if self.remove_stale(key, bytes)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Iterator over the encoded entries of a namespace, fetched page by page.
+struct RawEntries<'a> {
+    namespace: &'a Namespace,
+    page: VecDeque<(Vec<u8>, Vec<u8>)>,
+    start: Bound<Vec<u8>>,
+    done: bool,
+}
+
+impl Iterator for RawEntries<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            let state = &self.namespace.state;
+            let start = std::mem::replace(&mut self.start, Bound::Unbounded);
+            match state
+                .backend
+                .iterate(&self.namespace.backend_name, start, ITER_PAGE_SIZE)
+            {
+                Ok(page) => {
+                    self.done = page.len() < ITER_PAGE_SIZE;
+                    if let Some((last, _)) = page.last() {
+                        self.start = Bound::Excluded(last.clone());
+                    }
+                    self.page = page.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
 }
 
 impl Drop for Storage {
     fn drop(&mut self) {
         // Ensure all data is flushed to disk
-        let _ = self.db.flush();
+        let _ = self.backend.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_storage() -> Storage {
+        Storage::with_backend(Arc::new(SledBackend::temporary().unwrap()))
+    }
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_namespaces_do_not_collide() {
+        let storage = temporary_storage();
+        let key = Key::random();
+        let providers = storage.namespace(namespaces::PROVIDERS).unwrap();
+
+        storage.store(key, b"value".to_vec(), HOUR).unwrap();
+        providers
+            .store(key, RecordKind::Provider, b"provider".to_vec(), HOUR)
+            .unwrap();
+
+        assert_eq!(storage.get(&key).unwrap(), Some(b"value".to_vec()));
+        let record = providers.get(&key).unwrap().unwrap();
+        assert_eq!(record.kind, RecordKind::Provider);
+        assert_eq!(record.value, b"provider".to_vec());
+    }
+
+    #[test]
+    fn test_erasure_manifests_are_indexed() {
+        use crate::erasure::ErasureConfig;
+
+        let storage = temporary_storage();
+        let config = ErasureConfig::new(2, 1).unwrap();
+        let (manifest, _) = ObjectManifest::split_erasure(b"object", config).unwrap();
+        let (replicated, _) = ObjectManifest::split(b"object", 1024).unwrap();
+        let key = manifest.key().unwrap();
+
+        storage
+            .store(key, manifest.encode().unwrap(), HOUR)
+            .unwrap();
+        let other = replicated.key().unwrap();
+        storage
+            .store(other, replicated.encode().unwrap(), HOUR)
+            .unwrap();
+        storage
+            .store(Key::random(), b"value".to_vec(), HOUR)
+            .unwrap();
+
+        assert_eq!(storage.manifest_keys().unwrap(), vec![key]);
+    }
+
+    #[test]
+    fn test_shards_are_not_republished() {
+        let storage = temporary_storage();
+        let (value, shard) = (Key::random(), Key::random());
+        storage.store(value, b"value".to_vec(), HOUR).unwrap();
+        storage.store_shard(shard, b"shard".to_vec(), HOUR).unwrap();
+
+        assert_eq!(storage.get(&shard).unwrap(), Some(b"shard".to_vec()));
+        assert_eq!(storage.keys().unwrap(), vec![value]);
+        assert_eq!(storage.entries().unwrap(), vec![(value, b"value".to_vec())]);
+    }
+
+    #[test]
+    fn test_tombstone_hides_value() {
+        let storage = temporary_storage();
+        let key = Key::random();
+
+        storage.store(key, b"value".to_vec(), HOUR).unwrap();
+        storage
+            .namespace(namespaces::VALUES)
+            .unwrap()
+            .store_tombstone(key, HOUR)
+            .unwrap();
+
+        assert_eq!(storage.get(&key).unwrap(), None);
+    }
+
+    #[test]
+    fn test_expired_record_is_dropped() {
+        let storage = temporary_storage();
+        let key = Key::random();
+
+        storage
+            .store(key, b"value".to_vec(), Duration::ZERO)
+            .unwrap();
+
+        assert_eq!(storage.get(&key).unwrap(), None);
+        assert_eq!(storage.stats().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_replaced_expired_record_is_kept() {
+        let storage = temporary_storage();
+        let values = storage.namespace(namespaces::VALUES).unwrap();
+        let key = Key::random();
+
+        values
+            .store(key, RecordKind::Value, b"old".to_vec(), Duration::ZERO)
+            .unwrap();
+        let expired = storage
+            .backend
+            .get(&values.backend_name, key.as_bytes())
+            .unwrap()
+            .unwrap();
+        // A fresh store lands between the expiry check and the removal
+        values
+            .store(key, RecordKind::Value, b"new".to_vec(), HOUR)
+            .unwrap();
+
+        assert!(!values.remove_stale(key.as_bytes(), &expired).unwrap());
+        assert_eq!(values.get(&key).unwrap().unwrap().value, b"new");
+        assert_eq!(values.usage().entries, 1);
+    }
+
+    #[test]
+    fn test_ttl_is_capped_by_policy() {
+        let storage = temporary_storage();
+        let policy = NamespacePolicy {
+            max_ttl: HOUR,
+            ..Default::default()
+        };
+        storage.set_policy(namespaces::VALUES, policy).unwrap();
+        let key = Key::random();
+
+        storage.store(key, b"value".to_vec(), 10 * HOUR).unwrap();
+
+        let record = storage
+            .namespace(namespaces::VALUES)
+            .unwrap()
+            .get(&key)
+            .unwrap()
+            .unwrap();
+        assert!(record.expires_at <= SystemTime::now() + HOUR);
+    }
+
+    #[test]
+    fn test_quota_is_enforced() {
+        let storage = temporary_storage();
+        let policy = NamespacePolicy {
+            quota_bytes: Some(8),
+            ..Default::default()
+        };
+        storage.set_policy(namespaces::PACKAGES, policy).unwrap();
+        let packages = storage.namespace(namespaces::PACKAGES).unwrap();
+        let key = Key::random();
+
+        packages
+            .store(key, RecordKind::PackageChunk, vec![0; 8], HOUR)
+            .unwrap();
+        assert!(packages
+            .store(Key::random(), RecordKind::PackageChunk, vec![0; 1], HOUR)
+            .is_err());
+
+        // Replacing a record only counts the difference in size
+        packages
+            .store(key, RecordKind::PackageChunk, vec![0; 4], HOUR)
+            .unwrap();
+        assert_eq!(packages.usage().value_bytes, 4);
+    }
+
+    #[test]
+    fn test_iterate_namespace() {
+        let storage = temporary_storage();
+        let metadata = storage.namespace("app/metadata").unwrap();
+        for _ in 0..3 {
+            metadata
+                .store(Key::random(), RecordKind::Metadata, b"meta".to_vec(), HOUR)
+                .unwrap();
+        }
+
+        let records: Vec<_> = metadata.iter().collect::<Result<_>>().unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records
+            .windows(2)
+            .all(|w| w[0].0.as_bytes() < w[1].0.as_bytes()));
+        assert!(storage
+            .namespace_names()
+            .unwrap()
+            .contains(&"app/metadata".to_string()));
+    }
+
+    #[test]
+    fn test_listing_continues_past_expired_records() {
+        let storage = temporary_storage();
+        let values = storage.namespace(namespaces::VALUES).unwrap();
+        let mut keys: Vec<Key> = (0..6).map(|_| Key::random()).collect();
+        keys.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        for (i, key) in keys.iter().enumerate() {
+            // Every other record has already expired
+            let ttl = if i % 2 == 0 { Duration::ZERO } else { HOUR };
+            values
+                .store(*key, RecordKind::Value, b"v".to_vec(), ttl)
+                .unwrap();
+        }
+
+        let mut listed = Vec::new();
+        let mut start_after = None;
+        loop {
+            let page = storage.list(&[], start_after.as_ref(), 2).unwrap();
+            listed.extend(page.keys.into_iter().map(|(key, _)| key));
+            match page.next {
+                Some(next) => start_after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(listed, vec![keys[1], keys[3], keys[5]]);
+    }
+
+    #[test]
+    fn test_networks_are_partitioned() {
+        let storage = temporary_storage();
+        let staging = storage.for_network(&"staging".parse().unwrap());
+        let key = Key::random();
+
+        storage.store(key, b"default".to_vec(), HOUR).unwrap();
+        staging.store(key, b"staging".to_vec(), HOUR).unwrap();
+
+        assert_eq!(storage.get(&key).unwrap(), Some(b"default".to_vec()));
+        assert_eq!(staging.get(&key).unwrap(), Some(b"staging".to_vec()));
+        assert_eq!(staging.entries().unwrap().len(), 1);
+        assert_eq!(
+            storage.namespace_names().unwrap(),
+            staging.namespace_names().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_namespaces_of_other_networks_are_unreachable() {
+        let storage = temporary_storage();
+        let staging = storage.for_network(&"staging".parse().unwrap());
+        staging.store(Key::random(), b"v".to_vec(), HOUR).unwrap();
+
+        assert!(storage.namespace("net/staging/values").is_err());
+        assert!(staging.namespace("net/staging/values").is_err());
+        assert!(storage.namespace("").is_err());
+    }
+
+    #[test]
+    fn test_views_of_a_network_share_usage() {
+        let storage = temporary_storage();
+        let network = "staging".parse().unwrap();
+        let (first, second) = (storage.for_network(&network), storage.for_network(&network));
+        let key = Key::random();
+
+        first.store(key, vec![0; 10], HOUR).unwrap();
+        second.store(key, vec![0; 4], HOUR).unwrap();
+        let usage = first.namespace(namespaces::VALUES).unwrap().usage();
+        assert_eq!((usage.entries, usage.value_bytes), (1, 4));
+    }
+
+    #[test]
+    fn test_legacy_values_are_migrated() {
+        let dir = std::env::temp_dir().join(format!(
+            "kademlia-storage-test-legacy-{}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let key = Key::random();
+        {
+            let db = sled::open(&dir).unwrap();
+            let mut legacy = 3600u64.to_be_bytes().to_vec();
+            legacy.extend_from_slice(b"old value");
+            db.insert(key.as_bytes(), legacy).unwrap();
+            db.insert(b"short", b"x".to_vec()).unwrap();
+            db.flush().unwrap();
+        }
+
+        {
+            let storage = Storage::new(&dir).unwrap();
+            assert_eq!(storage.get(&key).unwrap(), Some(b"old value".to_vec()));
+            assert_eq!(storage.keys().unwrap(), vec![key]);
+        }
+        // The default tree is emptied, so opening again doesn't migrate twice
+        let storage = Storage::new(&dir).unwrap();
+        assert_eq!(storage.keys().unwrap(), vec![key]);
+        drop(storage);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -4,16 +4,18 @@
 //! - NodeId: 160-bit identifiers for nodes
 //! - Distance: XOR distance metric between NodeIds
 //! - Key: Type alias for NodeId used in key-value storage
+//! - NetworkId: Name of the overlay network a node belongs to
 //!
 //! The design follows the Kademlia paper's specifications for node identifiers
 //! and the XOR metric space.
 
 use crate::KEY_SIZE;
-use rand::{thread_rng, RngCore};
+use anyhow::{bail, Result};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use sha1::{Digest, Sha1};
 use std::cmp::Ordering;
 use std::fmt;
+use std::str::FromStr;
 
 /// A 160-bit identifier for nodes in the Kademlia network.
 ///
@@ -55,13 +57,40 @@ impl NodeId {
     /// # Returns
     /// A new randomly generated NodeId
     pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
+        let mut rng = rand::rng();
         let mut bytes = [0u8; KEY_SIZE / 8];
         rng.fill_bytes(&mut bytes);
         NodeId(bytes)
     }
 }
 
+/// Converts a 20-byte slice into a NodeId.
+///
+/// Fails if the slice does not contain exactly `KEY_SIZE / 8` bytes.
+impl TryFrom<&[u8]> for NodeId {
+    type Error = std::array::TryFromSliceError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Ok(NodeId(bytes.try_into()?))
+    }
+}
+
+/// Formats the NodeId as a lowercase hex string.
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({})", self)
+    }
+}
+
 /// Represents the XOR distance between two NodeIds in the Kademlia metric space.
 ///
 /// The Distance type implements Kademlia's XOR metric which has these properties:
@@ -85,8 +114,8 @@ impl Distance {
     /// The XOR distance between the two NodeIds
     pub fn between(a: &NodeId, b: &NodeId) -> Self {
         let mut result = [0u8; KEY_SIZE / 8];
-        for i in 0..KEY_SIZE / 8 {
-            result[i] = a.0[i] ^ b.0[i];
+        for (byte, (x, y)) in result.iter_mut().zip(a.0.iter().zip(b.0.iter())) {
+            *byte = x ^ y;
         }
         Distance(result)
     }
@@ -141,3 +170,101 @@ impl PartialOrd for Distance {
 /// In Kademlia, keys are drawn from the same 160-bit space as NodeIds.
 /// This allows the XOR metric to be used for routing to stored values.
 pub type Key = NodeId;
+
+/// Maximum length of a network name in bytes.
+const MAX_NETWORK_NAME_LEN: usize = 64;
+
+/// Identifies an overlay network.
+///
+/// Nodes only talk to nodes of the same network, so staging and production
+/// clusters, or the overlays of several tenants, can share a LAN without their
+/// routing tables or stored values mixing. Network names consist of ASCII
+/// letters, digits, `-`, `_` and `.`.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NetworkId(String);
+
+impl NetworkId {
+    /// Name of the network nodes join unless configured otherwise.
+    pub const DEFAULT: &'static str = "default";
+
+    /// Creates a network ID from its name.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the network, at most 64 bytes long
+    ///
+    /// # Returns
+    /// The network ID, or an error if the name is empty, too long or contains
+    /// characters other than ASCII letters, digits, `-`, `_` and `.`
+    pub fn new(name: &str) -> Result<Self> {
+        if name.is_empty() || name.len() > MAX_NETWORK_NAME_LEN {
+            bail!(
+                "Network name must be 1 to {} bytes long",
+                MAX_NETWORK_NAME_LEN
+            );
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        {
+            bail!("Invalid character {:?} in network name {:?}", c, name);
+        }
+        Ok(NetworkId(name.to_string()))
+    }
+
+    /// Returns the name of the network.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether this is the default network.
+    pub fn is_default(&self) -> bool {
+        self.0 == Self::DEFAULT
+    }
+}
+
+impl Default for NetworkId {
+    fn default() -> Self {
+        NetworkId(Self::DEFAULT.to_string())
+    }
+}
+
+impl FromStr for NetworkId {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::new(name)
+    }
+}
+
+impl fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_names_are_validated() {
+        assert!(NetworkId::new("staging-2.eu_west").is_ok());
+        assert!(NetworkId::new(&"a".repeat(MAX_NETWORK_NAME_LEN)).is_ok());
+
+        assert!(NetworkId::new("").is_err());
+        assert!(NetworkId::new(&"a".repeat(MAX_NETWORK_NAME_LEN + 1)).is_err());
+        assert!(NetworkId::new("tenant/1").is_err());
+        assert!(NetworkId::new("prod net").is_err());
+        assert!(NetworkId::new("réseau").is_err());
+    }
+
+    #[test]
+    fn test_default_network() {
+        assert!(NetworkId::default().is_default());
+        assert_eq!(
+            "default".parse::<NetworkId>().unwrap(),
+            NetworkId::default()
+        );
+        assert!(!"staging".parse::<NetworkId>().unwrap().is_default());
+    }
+}
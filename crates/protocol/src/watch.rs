@@ -0,0 +1,376 @@
+//! Following a key for new versions of its value.
+//!
+//! A [`KeyWatcher`] polls the k nodes closest to a key with GET_VERSION, which
+//! answers with a [`ValueVersion`] describing the stored value instead of the
+//! value itself, and fetches the value only when a new version appears. Polling
+//! keeps watchers stateless on the nodes and works for client-only sessions,
+//! which can't receive pushed notifications.
+//!
+//! Nodes may briefly disagree while a value spreads. The newest version of a
+//! signed record is the one with the highest sequence number, and a newer
+//! sequence number is accepted from a single node since only the publisher can
+//! sign it; if that node can't deliver a value with a valid signature, the
+//! next highest sequence number is tried. Other values carry no order, so the
+//! version most nodes hold wins, with ties going to the closest node.
+
+use crate::integrity::{self, SignedRecord, ValueMode};
+use crate::session::ClientSession;
+use crate::{Key, NodeId};
+use anyhow::{bail, Result};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// Number of polls after which the nodes closest to the key are looked up again.
+const LOOKUP_INTERVAL: u32 = 30;
+
+/// Describes one version of a stored value without carrying the value.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueVersion {
+    /// SHA-1 hash of the stored bytes
+    pub digest: Key,
+    /// Size of the stored value in bytes
+    pub size: usize,
+    /// Sequence number, if the value is a signed record
+    pub sequence: Option<u64>,
+    /// Public key of the publisher, if the value is a signed record
+    pub publisher: Option<Vec<u8>>,
+}
+
+impl ValueVersion {
+    /// Describes the value stored under a key.
+    ///
+    /// Values stored as signed records were verified when they were stored, so
+    /// their signature isn't checked again.
+    pub fn of(key: &Key, value: &[u8]) -> Self {
+        let record = SignedRecord::decode(value)
+            .ok()
+            .filter(|record| record.key() == *key);
+        ValueVersion {
+            digest: integrity::content_key(value),
+            size: value.len(),
+            sequence: record.as_ref().map(|record| record.sequence),
+            publisher: record.map(|record| record.public_key),
+        }
+    }
+
+    /// Returns whether this version supersedes `other`.
+    ///
+    /// A signed record only supersedes an older version of itself; any other
+    /// change of the value is a new version.
+    fn supersedes(&self, other: &ValueVersion) -> bool {
+        match (self.sequence, other.sequence) {
+            (Some(sequence), Some(other_sequence)) => sequence > other_sequence,
+            _ => self.digest != other.digest,
+        }
+    }
+}
+
+/// A new version of a watched value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionUpdate {
+    /// The new version
+    pub version: ValueVersion,
+    /// The value, verified against the version and the watch mode
+    pub value: Vec<u8>,
+    /// ID of the node the value was fetched from
+    pub holder: NodeId,
+    /// Address of the node the value was fetched from
+    pub holder_addr: SocketAddr,
+    /// Number of nodes holding this version when it was seen
+    pub held_by: usize,
+    /// Number of nodes that answered the poll
+    pub answered: usize,
+}
+
+/// A change of a watched value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A new version appeared
+    Changed(VersionUpdate),
+    /// The value was removed or expired on the nodes
+    Removed,
+}
+
+/// Polls the nodes responsible for a key for new versions of its value.
+pub struct KeyWatcher<'a> {
+    session: &'a ClientSession,
+    key: Key,
+    mode: ValueMode,
+    /// Nodes closest to the key, closest first
+    nodes: Vec<(NodeId, SocketAddr)>,
+    polls_since_lookup: u32,
+    current: Option<ValueVersion>,
+}
+
+impl<'a> KeyWatcher<'a> {
+    /// Creates a watcher that hasn't seen any version of the value yet.
+    ///
+    /// # Arguments
+    /// * `session` - Session the nodes are polled through
+    /// * `key` - Key to watch
+    /// * `mode` - Mode new values have to verify in
+    pub fn new(session: &'a ClientSession, key: Key, mode: ValueMode) -> Self {
+        KeyWatcher {
+            session,
+            key,
+            mode,
+            nodes: Vec::new(),
+            polls_since_lookup: 0,
+            current: None,
+        }
+    }
+
+    /// Returns the last version seen, if the value exists.
+    pub fn current(&self) -> Option<&ValueVersion> {
+        self.current.as_ref()
+    }
+
+    /// Asks the nodes responsible for the key which version they hold.
+    ///
+    /// Versions are claimed without proof, so a version whose holders don't
+    /// deliver a value that verifies is passed over for the next best one.
+    ///
+    /// # Returns
+    /// * `Result<Option<WatchEvent>>` - The change since the last poll, if any
+    pub async fn poll(&mut self) -> Result<Option<WatchEvent>> {
+        if self.nodes.is_empty() || self.polls_since_lookup >= LOOKUP_INTERVAL {
+            self.nodes = self.session.lookup_nodes(self.key).await?;
+            self.polls_since_lookup = 0;
+            if self.nodes.is_empty() {
+                bail!("No nodes found responsible for {}", self.key);
+            }
+        }
+        self.polls_since_lookup += 1;
+
+        let client = self.session.client();
+        let own_id = self.session.id();
+        let key = self.key;
+        let queries = self.nodes.iter().map(|(node, addr)| async move {
+            let version = client.get_version(own_id, key, *addr).await;
+            (*node, *addr, version)
+        });
+        let replies: Vec<_> = join_all(queries)
+            .await
+            .into_iter()
+            .filter_map(|(node, addr, version)| version.ok().map(|version| (node, addr, version)))
+            .collect();
+
+        // Look the nodes up again next time if many of them have gone
+        let asked = self.nodes.len();
+        if replies.len() * 2 < asked {
+            self.nodes.clear();
+        }
+        if replies.is_empty() {
+            bail!(
+                "None of the {} nodes responsible for {} answered",
+                asked,
+                self.key
+            );
+        }
+
+        // Versions no holder delivered a verified value for; the claims of their
+        // holders are ignored and the next best version is tried instead
+        let mut failed: Vec<&ValueVersion> = Vec::new();
+        loop {
+            let versions: Vec<Option<&ValueVersion>> = replies
+                .iter()
+                .map(|(_, _, version)| version.as_ref())
+                .filter(|version| !version.is_some_and(|version| failed.contains(&version)))
+                .collect();
+            if versions.is_empty() {
+                return Ok(None);
+            }
+            let Some(version) = newest(&versions) else {
+                return Ok(self.current.take().map(|_| WatchEvent::Removed));
+            };
+            if self
+                .current
+                .as_ref()
+                .is_some_and(|current| !version.supersedes(current))
+            {
+                return Ok(None);
+            }
+
+            let holders: Vec<(NodeId, SocketAddr)> = replies
+                .iter()
+                .filter(|(_, _, held)| held.as_ref() == Some(version))
+                .map(|(node, addr, _)| (*node, *addr))
+                .collect();
+            // A sequence number only orders versions if the publisher signed it,
+            // whatever mode the value is watched in
+            let mode = match version.sequence {
+                Some(_) => ValueMode::Signed,
+                None => self.mode,
+            };
+            for (holder, holder_addr) in &holders {
+                let Ok(Ok(value)) = client.find_value(own_id, key, *holder_addr).await else {
+                    continue;
+                };
+                if integrity::content_key(&value) != version.digest
+                    || !integrity::verify(&key, mode, &value)
+                {
+                    continue;
+                }
+                self.current = Some(version.clone());
+                return Ok(Some(WatchEvent::Changed(VersionUpdate {
+                    version: version.clone(),
+                    value,
+                    holder: *holder,
+                    holder_addr: *holder_addr,
+                    held_by: holders.len(),
+                    answered: replies.len(),
+                })));
+            }
+            failed.push(version);
+        }
+    }
+}
+
+/// Picks the newest version among the answers of the nodes, closest node first.
+///
+/// Returns `None` if the value is gone.
+fn newest<'v>(versions: &[Option<&'v ValueVersion>]) -> Option<&'v ValueVersion> {
+    let signed = versions
+        .iter()
+        .flatten()
+        .filter(|version| version.sequence.is_some())
+        .max_by_key(|version| version.sequence);
+    if signed.is_some() {
+        return signed.copied();
+    }
+
+    // Count how many nodes hold each answer, keeping the order of first appearance
+    let mut counts: Vec<(Option<&ValueVersion>, usize)> = Vec::new();
+    for version in versions {
+        match counts.iter_mut().find(|(counted, _)| counted == version) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((*version, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .and_then(|(version, _)| version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrity::Keypair;
+
+    fn plain(value: &[u8]) -> ValueVersion {
+        ValueVersion::of(&Key::random(), value)
+    }
+
+    #[test]
+    fn test_version_of_signed_record() {
+        let keypair = Keypair::generate();
+        let record = SignedRecord::new(&keypair, b"status", 7, b"online".to_vec());
+        let bytes = record.encode().unwrap();
+
+        let version = ValueVersion::of(&record.key(), &bytes);
+        assert_eq!(version.sequence, Some(7));
+        assert_eq!(version.publisher, Some(record.public_key.clone()));
+        assert_eq!(version.digest, integrity::content_key(&bytes));
+
+        // The same bytes under another key are just a plain value
+        let version = ValueVersion::of(&Key::random(), &bytes);
+        assert_eq!(version.sequence, None);
+    }
+
+    #[test]
+    fn test_newest_plain_version() {
+        let (a, b) = (plain(b"a"), plain(b"b"));
+        assert_eq!(newest(&[Some(&a), Some(&b), Some(&b)]), Some(&b));
+        // Ties go to the answer of the closest node
+        assert_eq!(newest(&[Some(&a), Some(&b)]), Some(&a));
+        assert_eq!(newest(&[None, Some(&a), None]), None);
+        assert_eq!(newest(&[]), None);
+    }
+
+    #[test]
+    fn test_newest_signed_version() {
+        let keypair = Keypair::generate();
+        let versions: Vec<ValueVersion> = (1..=2)
+            .map(|sequence| {
+                let record = SignedRecord::new(&keypair, b"status", sequence, Vec::new());
+                ValueVersion::of(&record.key(), &record.encode().unwrap())
+            })
+            .collect();
+
+        // A single node with a newer sequence number is enough
+        let answers = [Some(&versions[0]), Some(&versions[0]), Some(&versions[1])];
+        assert_eq!(newest(&answers), Some(&versions[1]));
+        assert!(versions[1].supersedes(&versions[0]));
+        assert!(!versions[0].supersedes(&versions[1]));
+    }
+
+    #[tokio::test]
+    async fn test_unverified_claims_fall_back_to_next_version() {
+        check_unverified_claims_fall_back(ValueMode::Signed).await;
+    }
+
+    #[tokio::test]
+    async fn test_unverified_claims_fall_back_in_plain_mode() {
+        check_unverified_claims_fall_back(ValueMode::Plain).await;
+    }
+
+    async fn check_unverified_claims_fall_back(mode: ValueMode) {
+        use crate::node::Node;
+        use crate::storage::Storage;
+        use crate::NetworkId;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let keypair = Keypair::generate();
+        let record = SignedRecord::new(&keypair, b"status", 1, b"online".to_vec());
+        // A node claiming a sequence number the publisher never signed
+        let mut forged = record.clone();
+        forged.sequence = u64::MAX;
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let honest = Arc::new(
+            Node::with_storage(addr, Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        let liar = Arc::new(
+            Node::with_storage(addr, Storage::in_memory())
+                .await
+                .unwrap(),
+        );
+        let ttl = Duration::from_secs(3600);
+        honest
+            .storage()
+            .store(record.key(), record.encode().unwrap(), ttl)
+            .unwrap();
+        liar.storage()
+            .store(record.key(), forged.encode().unwrap(), ttl)
+            .unwrap();
+        let running: Vec<_> = [&honest, &liar]
+            .into_iter()
+            .map(|node| {
+                let node = Arc::clone(node);
+                tokio::spawn(async move { node.run().await })
+            })
+            .collect();
+
+        let session = ClientSession::new(NetworkId::default()).await.unwrap();
+        session
+            .bootstrap(&[honest.addr(), liar.addr()])
+            .await
+            .unwrap();
+        let mut watcher = KeyWatcher::new(&session, record.key(), mode);
+        let Some(WatchEvent::Changed(update)) = watcher.poll().await.unwrap() else {
+            panic!("the signed version wasn't reported");
+        };
+        assert_eq!(update.version.sequence, Some(1));
+        assert_eq!(update.holder, honest.id());
+        assert_eq!(watcher.poll().await.unwrap(), None);
+        for task in running {
+            task.abort();
+        }
+    }
+}
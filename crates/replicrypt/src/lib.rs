@@ -1 +1,5 @@
 pub mod sha1;
+pub mod signing;
+
+pub use sha1::{calculate_sha1, sha1_digest};
+pub use signing::{verify_signature, Keypair};
@@ -41,6 +41,23 @@ pub fn calculate_sha1<T: AsRef<[u8]>>(data: T) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// Calculates the SHA-1 hash of the provided data and returns the raw 20-byte digest.
+///
+/// Use this instead of [`calculate_sha1`] when the hash is used as an identifier,
+/// for example as a content-addressed key.
+///
+/// # Examples
+///
+/// ```
+/// use replicrypt::sha1_digest;
+///
+/// let digest = sha1_digest("Hello, World!");
+/// assert_eq!(digest[0], 0x0a);
+/// ```
+pub fn sha1_digest<T: AsRef<[u8]>>(data: T) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,6 +148,16 @@ mod tests {
         assert_ne!(hash1, hash2);
     }
 
+    /// Test that the raw digest matches the hexadecimal hash
+    #[test]
+    fn test_digest_matches_hex() {
+        let hex: String = sha1_digest("Hello, World!")
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(hex, calculate_sha1("Hello, World!"));
+    }
+
     /// Test that identical inputs produce identical hashes
     #[test]
     fn test_same_inputs_produce_same_hashes() {
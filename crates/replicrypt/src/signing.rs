@@ -0,0 +1,111 @@
+//! A module providing Ed25519 signatures.
+//!
+//! Signatures let a publisher prove that data was produced by the holder of a
+//! key pair. Public keys, signatures and secret seeds are exchanged as plain byte
+//! arrays, so callers don't have to depend on the underlying implementation.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+
+/// Size of a public key in bytes.
+pub const PUBLIC_KEY_SIZE: usize = 32;
+
+/// Size of a signature in bytes.
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// An Ed25519 key pair used to sign data.
+pub struct Keypair {
+    signing_key: SigningKey,
+}
+
+impl Keypair {
+    /// Generates a new random key pair.
+    pub fn generate() -> Self {
+        let mut seed = [0u8; 32];
+        rand::rng().fill_bytes(&mut seed);
+        Self::from_seed(&seed)
+    }
+
+    /// Restores a key pair from the secret seed returned by [`Keypair::seed`].
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Keypair {
+            signing_key: SigningKey::from_bytes(seed),
+        }
+    }
+
+    /// Returns the secret seed of the key pair.
+    ///
+    /// Anyone holding the seed can sign on behalf of the key pair.
+    pub fn seed(&self) -> [u8; 32] {
+        self.signing_key.to_bytes()
+    }
+
+    /// Returns the public key of the key pair.
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_SIZE] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    /// Signs a message.
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+        self.signing_key.sign(message).to_bytes()
+    }
+}
+
+/// Verifies a signature made by [`Keypair::sign`].
+///
+/// Returns `false` for malformed public keys or signatures as well as for
+/// signatures that don't match the message.
+///
+/// # Examples
+///
+/// ```
+/// use replicrypt::{verify_signature, Keypair};
+///
+/// let keypair = Keypair::generate();
+/// let signature = keypair.sign(b"message");
+/// assert!(verify_signature(&keypair.public_key(), b"message", &signature));
+/// assert!(!verify_signature(&keypair.public_key(), b"other", &signature));
+/// ```
+pub fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; PUBLIC_KEY_SIZE]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    verifying_key.verify(message, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that a key pair restored from its seed signs identically
+    #[test]
+    fn test_seed_roundtrip() {
+        let keypair = Keypair::generate();
+        let restored = Keypair::from_seed(&keypair.seed());
+        assert_eq!(keypair.public_key(), restored.public_key());
+        assert_eq!(keypair.sign(b"data"), restored.sign(b"data"));
+    }
+
+    /// Test that signatures of other keys are rejected
+    #[test]
+    fn test_wrong_key_rejected() {
+        let signature = Keypair::generate().sign(b"data");
+        let other = Keypair::generate();
+        assert!(!verify_signature(&other.public_key(), b"data", &signature));
+    }
+
+    /// Test that malformed input is rejected instead of panicking
+    #[test]
+    fn test_malformed_input_rejected() {
+        let keypair = Keypair::generate();
+        let signature = keypair.sign(b"data");
+        assert!(!verify_signature(&[0u8; 3], b"data", &signature));
+        assert!(!verify_signature(&keypair.public_key(), b"data", &signature[..10]));
+    }
+}
@@ -1,3 +1,57 @@
-pub fn kad_messaging() {
-    println!("KAD_FILE_SYSTEM");
+//! Publish/subscribe messaging for distributed apps.
+//!
+//! Apps address topics by name; the names are hashed into overlay keys, and
+//! messages spread through the gossip layer of the protocol node (see
+//! `protocol::pubsub`). Every subscriber on the network receives each message
+//! at most once.
+
+use anyhow::Result;
+use protocol::{topic_key, Key, Node};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub use protocol::TopicMessage;
+
+/// Named topics on top of a protocol node.
+#[derive(Clone)]
+pub struct Messaging {
+    node: Arc<Node>,
+}
+
+impl Messaging {
+    /// Creates the messaging layer of a node.
+    ///
+    /// The node has to be running for messages of other nodes to arrive.
+    pub fn new(node: Arc<Node>) -> Self {
+        Messaging { node }
+    }
+
+    /// Returns the overlay key of a topic name.
+    pub fn topic(name: &str) -> Key {
+        topic_key(name)
+    }
+
+    /// Subscribes to a topic by name.
+    pub fn subscribe(&self, name: &str) -> broadcast::Receiver<TopicMessage> {
+        self.node.subscribe(topic_key(name))
+    }
+
+    /// Stops receiving the messages of a topic.
+    pub fn unsubscribe(&self, name: &str) {
+        self.node.unsubscribe(&topic_key(name))
+    }
+
+    /// Only accepts messages of a topic that pass the given check.
+    pub fn set_validator(
+        &self,
+        name: &str,
+        validator: impl Fn(&TopicMessage) -> bool + Send + Sync + 'static,
+    ) {
+        self.node.set_topic_validator(topic_key(name), validator)
+    }
+
+    /// Publishes a message to a topic by name, returning its ID.
+    pub async fn publish(&self, name: &str, payload: impl Into<Vec<u8>>) -> Result<Key> {
+        self.node.publish(topic_key(name), payload.into()).await
+    }
 }